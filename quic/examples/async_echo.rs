@@ -0,0 +1,157 @@
+//Media Enhanced Swiftlet Quic Async Echo Example
+//MIT License
+//Copyright (c) 2024 Jared Loewenthal
+//
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+//
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+//
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+// A tokio server task echoes main stream messages back to a tokio client task over the loopback interface
+// using the async Endpoint adapter (run with: cargo run --example async_echo --features tokio)
+
+const ALPN_NAME: &[u8] = b"async echo"; // Application-Layer Protocol Negotiation Name used to define the Quic-Application Protocol used in this program
+const SERVER_NAME: &str = "localhost"; // Server "Name" / Domain Name that is put on the generated server certificate
+
+const HEADER_LEN: usize = 2; // Every message starts with its body length as a big-endian u16
+const MESSAGE_COUNT: usize = 10;
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use swiftlet_quic::{
+    async_endpoint::{AsyncEndpoint, MessageFraming},
+    endpoint::{Config, Endpoint, SocketAddr},
+};
+
+fn body_len(header: &[u8]) -> usize {
+    u16::from_be_bytes([header[0], header[1]]) as usize
+}
+
+fn create_message(body: &[u8]) -> Vec<u8> {
+    let mut message = (body.len() as u16).to_be_bytes().to_vec();
+    message.extend_from_slice(body);
+    message
+}
+
+#[tokio::main]
+async fn main() {
+    let server_address = SocketAddr::V6(std::net::SocketAddrV6::new(
+        std::net::Ipv6Addr::LOCALHOST,
+        9021,
+        0,
+        0,
+    ));
+    let framing = MessageFraming {
+        header_len: HEADER_LEN,
+        body_len,
+        max_message_len: HEADER_LEN + u16::MAX as usize,
+    };
+
+    let (server_identity, client_verification) = common::self_signed_pair(SERVER_NAME);
+
+    let mut server = match AsyncEndpoint::spawn(
+        move || {
+            Endpoint::new_server_with_identity(
+                &[server_address],
+                ALPN_NAME,
+                server_identity,
+                create_config(None),
+            )
+        },
+        framing,
+        Duration::from_millis(5),
+    )
+    .await
+    {
+        Ok(server) => server,
+        Err(e) => {
+            println!("Server Endpoint Creation Error: {:?}", e);
+            return;
+        }
+    };
+
+    let server_task = tokio::spawn(async move {
+        if let Some((connection, mut streams)) = server.accept().await {
+            println!("Server Accepted Connection {}", connection.id());
+            while let Some(message) = streams.main.recv().await {
+                if connection.send_main(message).await.is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    let client = match AsyncEndpoint::spawn(
+        move || {
+            Endpoint::new_client_with_verification(
+                true,
+                ALPN_NAME,
+                client_verification,
+                create_config(Some(Duration::from_millis(2000))),
+            )
+        },
+        framing,
+        Duration::from_millis(5),
+    )
+    .await
+    {
+        Ok(client) => client,
+        Err(e) => {
+            println!("Client Endpoint Creation Error: {:?}", e);
+            return;
+        }
+    };
+
+    match client.connect(server_address, SERVER_NAME).await {
+        Ok((connection, mut streams)) => {
+            for message_num in 0..MESSAGE_COUNT {
+                let body = format!("Echo Message {}", message_num);
+                let start_instant = Instant::now();
+                if let Err(e) = connection.send_main(create_message(body.as_bytes())).await {
+                    println!("Client Send Error: {:?}", e);
+                    break;
+                }
+                match streams.main.recv().await {
+                    Some(echo) => println!(
+                        "Received \"{}\" after {:?}",
+                        String::from_utf8_lossy(&echo[HEADER_LEN..]),
+                        start_instant.elapsed()
+                    ),
+                    None => {
+                        println!("Connection Ended Early!");
+                        break;
+                    }
+                }
+            }
+            let _ = connection.close(0).await;
+        }
+        Err(e) => println!("Client Connection Error: {:?}", e),
+    }
+
+    let _ = server_task.await;
+}
+
+fn create_config(keep_alive_timeout: Option<Duration>) -> Config {
+    Config {
+        initial_main_recv_size: 1024,
+        main_recv_first_bytes: HEADER_LEN, // Must match the message framing header length
+        background_recv_first_bytes: HEADER_LEN,
+        send_queue_low_water: 65536,
+        ..common::base_config(keep_alive_timeout)
+    }
+}
@@ -0,0 +1,90 @@
+//Media Enhanced Swiftlet Quic Shared Example Helpers
+//MIT License
+//Copyright (c) 2024 Jared Loewenthal
+//
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+//
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+//
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+// Pieces shared by the examples that run a server and client Endpoint pair within one process
+
+use std::time::Duration;
+
+use swiftlet_quic::endpoint::{
+    tls::{self, Encoding, Identity, ServerVerification},
+    AdmissionLimits, Config, CongestionControl, Pacing, StreamPriority,
+};
+
+// Generates a server identity and the verification a client needs to trust it
+// No certificate files are needed since the client can trust the generated certificate directly
+pub(crate) fn self_signed_pair(server_name: &str) -> (Identity, ServerVerification) {
+    let server_identity = tls::generate_self_signed(server_name).unwrap();
+    let client_verification = match &server_identity {
+        Identity::Memory { cert_data, .. } => ServerVerification::CaMemory {
+            encoding: Encoding::Pem,
+            cert_data: cert_data.clone(),
+        },
+        Identity::PemFiles { cert_path, .. } => ServerVerification::CaFile(cert_path.clone()),
+    };
+    (server_identity, client_verification)
+}
+
+// The Config every example starts from (each one changes the values it depends on)
+pub(crate) fn base_config(keep_alive_timeout: Option<Duration>) -> Config {
+    Config {
+        idle_timeout_in_ms: 5000,
+        reliable_stream_buffer: 65536,
+        unreliable_stream_buffer: 65536,
+        keep_alive_timeout,
+        initial_main_recv_size: 65536,
+        main_recv_first_bytes: 1,
+        initial_rt_recv_size: 65536,
+        rt_recv_first_bytes: 0,
+        initial_background_recv_size: 65536,
+        background_recv_first_bytes: 1,
+        rt_datagram_queue_len: 0,
+        qlog_dir: None,
+        keylog_path: None,
+        client_identity: None,
+        client_verification: None,
+        stateless_retry: false,
+        early_data: false,
+        session_cache_path: None,
+        congestion_control: CongestionControl::Cubic,
+        initial_congestion_window: None,
+        pacing: Pacing::Off,
+        main_stream_priority: StreamPriority {
+            urgency: 1,
+            incremental: false,
+        },
+        rt_stream_priority: StreamPriority {
+            urgency: 0,
+            incremental: false,
+        },
+        background_stream_priority: StreamPriority {
+            urgency: 6,
+            incremental: true,
+        },
+        background_bandwidth_share: None,
+        rt_max_age: None,
+        send_queue_low_water: 0,
+        max_background_streams: 0,
+        udp_batching: false,
+        max_datagram_size: None,
+        admission: AdmissionLimits::default(),
+    }
+}
@@ -0,0 +1,279 @@
+//Media Enhanced Swiftlet Quic Connection Scaling Benchmark Example
+//MIT License
+//Copyright (c) 2024 Jared Loewenthal
+//
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+//
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+//
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+// Runs a server and client Endpoint pair within this process over a perfect virtual link
+// with a growing number of connections that each echo small main stream messages back and forth.
+// Every connection always has one message in flight so the time per round trip (a datagram each way)
+// should stay flat as the connection count grows.
+
+const ALPN_NAME: &[u8] = b"scaling"; // Application-Layer Protocol Negotiation Name used to define the Quic-Application Protocol used in this program
+const SERVER_NAME: &str = "localhost"; // Server "Name" / Domain Name that is put on the generated server certificate
+
+const CONNECTION_COUNTS: [usize; 5] = [100, 250, 500, 1000, 2000];
+const TOTAL_ROUND_TRIPS: u64 = 40_000; // Split evenly between the connections of each run
+const MESSAGE_SIZE: usize = 64;
+
+mod common;
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use swiftlet_quic::{
+    endpoint::{
+        tls::PeerIdentity,
+        transport::{virtual_link_pair, LinkConfig},
+        Config, ConnectionEndReason, ConnectionId, Endpoint, SocketAddr,
+    },
+    EndpointEventCallbacks, EndpointHandler,
+};
+
+fn main() {
+    for (run, num_connections) in CONNECTION_COUNTS.into_iter().enumerate() {
+        match run_echoes(run as u16, num_connections) {
+            Some((handshake_duration, echo_duration)) => {
+                let round_trips =
+                    (TOTAL_ROUND_TRIPS / num_connections as u64) * num_connections as u64;
+                println!(
+                    "{:>5} Connections: Handshakes in {:?}, {} round trips in {:?} ({:.2} us per round trip)",
+                    num_connections,
+                    handshake_duration,
+                    round_trips,
+                    echo_duration,
+                    echo_duration.as_secs_f64() * 1_000_000.0 / round_trips as f64
+                );
+            }
+            None => println!("{:>5} Connections: Run did not complete!", num_connections),
+        }
+    }
+}
+
+fn run_echoes(run: u16, num_connections: usize) -> Option<(Duration, Duration)> {
+    let server_address = SocketAddr::V6(std::net::SocketAddrV6::new(
+        std::net::Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1),
+        9021 + run,
+        0,
+        0,
+    ));
+    let client_address = SocketAddr::V6(std::net::SocketAddrV6::new(
+        std::net::Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 2),
+        50000 + run,
+        0,
+        0,
+    ));
+    let (server_socket, client_socket) = virtual_link_pair(
+        server_address,
+        client_address,
+        LinkConfig::default(),
+        LinkConfig::default(),
+    );
+
+    let (server_identity, client_verification) = common::self_signed_pair(SERVER_NAME);
+
+    let server_thread_handle = std::thread::spawn(move || {
+        let server_endpoint = Endpoint::new_server_with_transport(
+            Box::new(server_socket),
+            ALPN_NAME,
+            server_identity,
+            create_config(None),
+        );
+        match server_endpoint {
+            Ok(mut endpoint) => {
+                let mut server_state = ServerState {};
+                let mut endpoint_handler = EndpointHandler::new(&mut endpoint, &mut server_state);
+                if let Err(e) = endpoint_handler.run_event_loop(Duration::from_millis(5)) {
+                    println!("Server Error: {:?}", e);
+                }
+            }
+            Err(e) => println!("Server Endpoint Creation Error: {:?}", e),
+        }
+    });
+
+    let client_thread_handle = std::thread::spawn(move || {
+        let client_endpoint = Endpoint::new_client_with_transport(
+            Box::new(client_socket),
+            ALPN_NAME,
+            client_verification,
+            create_config(Some(Duration::from_millis(2000))),
+        );
+        match client_endpoint {
+            Ok(mut endpoint) => {
+                let mut client_state = ClientState::new(num_connections);
+                for _ in 0..num_connections {
+                    if let Err(e) = endpoint.add_client_connection(server_address, SERVER_NAME) {
+                        println!("Client Connection Error: {:?}", e);
+                        return None;
+                    }
+                }
+                let mut endpoint_handler = EndpointHandler::new(&mut endpoint, &mut client_state);
+                if let Err(e) = endpoint_handler.run_event_loop(Duration::from_millis(5)) {
+                    println!("Client Error: {:?}", e);
+                }
+                match (client_state.handshake_duration, client_state.echo_duration) {
+                    (Some(handshake_duration), Some(echo_duration)) => {
+                        Some((handshake_duration, echo_duration))
+                    }
+                    _ => None,
+                }
+            }
+            Err(e) => {
+                println!("Client Endpoint Creation Error: {:?}", e);
+                None
+            }
+        }
+    });
+
+    let durations = client_thread_handle.join().unwrap();
+    server_thread_handle.join().unwrap();
+    durations
+}
+
+fn create_config(keep_alive_timeout: Option<Duration>) -> Config {
+    Config {
+        idle_timeout_in_ms: 30000, // The last handshakes of a run can take a while
+        initial_main_recv_size: MESSAGE_SIZE,
+        main_recv_first_bytes: MESSAGE_SIZE,
+        ..common::base_config(keep_alive_timeout)
+    }
+}
+
+struct ServerState {}
+
+impl EndpointEventCallbacks for ServerState {
+    fn connection_started(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        _peer_identity: Option<PeerIdentity>,
+    ) {
+    }
+
+    fn connection_ended(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        _reason: ConnectionEndReason,
+        remaining_connections: usize,
+    ) -> bool {
+        remaining_connections == 0
+    }
+
+    fn tick(&mut self, _endpoint: &mut Endpoint) -> bool {
+        false
+    }
+
+    fn main_stream_recv(
+        &mut self,
+        endpoint: &mut Endpoint,
+        cid: &ConnectionId,
+        read_data: &[u8],
+    ) -> Option<usize> {
+        let _ = endpoint.main_stream_send(cid, read_data.to_vec());
+        Some(MESSAGE_SIZE)
+    }
+}
+
+struct ClientState {
+    num_connections: usize,
+    rounds_per_connection: u64,
+    creation_instant: Instant,
+    echo_start_instant: Option<Instant>,
+    handshake_duration: Option<Duration>,
+    echo_duration: Option<Duration>,
+    round_trips: HashMap<ConnectionId, u64>,
+    finished_connections: usize,
+}
+
+impl ClientState {
+    fn new(num_connections: usize) -> Self {
+        ClientState {
+            num_connections,
+            rounds_per_connection: TOTAL_ROUND_TRIPS / num_connections as u64,
+            creation_instant: Instant::now(),
+            echo_start_instant: None,
+            handshake_duration: None,
+            echo_duration: None,
+            round_trips: HashMap::with_capacity(num_connections),
+            finished_connections: 0,
+        }
+    }
+}
+
+impl EndpointEventCallbacks for ClientState {
+    fn connection_started(
+        &mut self,
+        endpoint: &mut Endpoint,
+        cid: &ConnectionId,
+        _peer_identity: Option<PeerIdentity>,
+    ) {
+        self.round_trips.insert(*cid, 0);
+        if self.round_trips.len() == self.num_connections {
+            // Every connection gets its first message once all of the handshakes are done
+            self.handshake_duration = Some(self.creation_instant.elapsed());
+            self.echo_start_instant = Some(Instant::now());
+            for cid in self.round_trips.keys() {
+                let _ = endpoint.main_stream_send(cid, vec![0; MESSAGE_SIZE]);
+            }
+        }
+    }
+
+    fn connection_ended(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        reason: ConnectionEndReason,
+        remaining_connections: usize,
+    ) -> bool {
+        if self.echo_duration.is_none() {
+            println!("Client Connection Ended Early Reason: {:?}", reason);
+        }
+        remaining_connections == 0
+    }
+
+    fn tick(&mut self, _endpoint: &mut Endpoint) -> bool {
+        false
+    }
+
+    fn main_stream_recv(
+        &mut self,
+        endpoint: &mut Endpoint,
+        cid: &ConnectionId,
+        read_data: &[u8],
+    ) -> Option<usize> {
+        if let Some(round_trips) = self.round_trips.get_mut(cid) {
+            *round_trips += 1;
+            if *round_trips < self.rounds_per_connection {
+                let _ = endpoint.main_stream_send(cid, read_data.to_vec());
+            } else {
+                self.finished_connections += 1;
+                if self.finished_connections == self.num_connections {
+                    if let Some(echo_start_instant) = self.echo_start_instant {
+                        self.echo_duration = Some(echo_start_instant.elapsed());
+                    }
+                    for cid in self.round_trips.keys() {
+                        let _ = endpoint.close_connection(cid, 0);
+                    }
+                }
+            }
+        }
+        Some(MESSAGE_SIZE)
+    }
+}
@@ -29,7 +29,10 @@ use std::collections::BTreeMap;
 use std::time::Duration;
 
 use swiftlet_quic::{
-    endpoint::{Config, ConnectionEndReason, ConnectionId, Endpoint, SocketAddr},
+    endpoint::{
+        tls::PeerIdentity, AdmissionLimits, Config, CongestionControl, ConnectionEndReason,
+        ConnectionId, Endpoint, Pacing, SocketAddr, StreamPriority,
+    },
     EndpointEventCallbacks, EndpointHandler,
 };
 
@@ -78,10 +81,47 @@ fn server_thread(port: u16, server_name: String) {
         rt_recv_first_bytes: 0,
         initial_background_recv_size: BUFFER_SIZE_PER_CONNECTION,
         background_recv_first_bytes: MESSAGE_HEADER_SIZE,
+        rt_datagram_queue_len: 0,
+        qlog_dir: None,
+        keylog_path: None,
+        client_identity: None,
+        client_verification: None,
+        stateless_retry: false,
+        early_data: false,
+        session_cache_path: None,
+        congestion_control: CongestionControl::Cubic,
+        initial_congestion_window: None,
+        pacing: Pacing::On,
+        main_stream_priority: StreamPriority {
+            urgency: 1,
+            incremental: false,
+        },
+        rt_stream_priority: StreamPriority {
+            urgency: 0,
+            incremental: false,
+        },
+        background_stream_priority: StreamPriority {
+            urgency: 6,
+            incremental: true,
+        },
+        background_bandwidth_share: None,
+        rt_max_age: None,
+        send_queue_low_water: 0,
+        max_background_streams: 0,
+        udp_batching: true,
+        max_datagram_size: None,
+        admission: AdmissionLimits::default(),
     };
 
+    // Dual-stack wildcard address that accepts both IPv6 and IPv4 clients
+    let bind_addr = SocketAddr::V6(std::net::SocketAddrV6::new(
+        std::net::Ipv6Addr::UNSPECIFIED,
+        port,
+        0,
+        0,
+    ));
     let mut server_endpoint =
-        match Endpoint::new_server(true, port, ALPN_NAME, CERT_PATH, PKEY_PATH, config) {
+        match Endpoint::new_server(&[bind_addr], ALPN_NAME, CERT_PATH, PKEY_PATH, config) {
             Ok(endpoint) => endpoint,
             Err(_) => {
                 println!("Server Endpoint Creation Error!");
@@ -121,6 +161,36 @@ fn client_thread(server_address: SocketAddr, user_name: String) {
         rt_recv_first_bytes: 0,
         initial_background_recv_size: BUFFER_SIZE_PER_CONNECTION,
         background_recv_first_bytes: MESSAGE_HEADER_SIZE,
+        rt_datagram_queue_len: 0,
+        qlog_dir: None,
+        keylog_path: None,
+        client_identity: None,
+        client_verification: None,
+        stateless_retry: false,
+        early_data: false,
+        session_cache_path: None,
+        congestion_control: CongestionControl::Cubic,
+        initial_congestion_window: None,
+        pacing: Pacing::On,
+        main_stream_priority: StreamPriority {
+            urgency: 1,
+            incremental: false,
+        },
+        rt_stream_priority: StreamPriority {
+            urgency: 0,
+            incremental: false,
+        },
+        background_stream_priority: StreamPriority {
+            urgency: 6,
+            incremental: true,
+        },
+        background_bandwidth_share: None,
+        rt_max_age: None,
+        send_queue_low_water: 0,
+        max_background_streams: 0,
+        udp_batching: true,
+        max_datagram_size: None,
+        admission: AdmissionLimits::default(),
     };
 
     let mut client_endpoint = match Endpoint::new_client_with_first_connection(
@@ -397,7 +467,12 @@ impl ServerState {
 }
 
 impl EndpointEventCallbacks for ServerState {
-    fn connection_started(&mut self, _endpoint: &mut Endpoint, _cid: &ConnectionId) {
+    fn connection_started(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        _peer_identity: Option<PeerIdentity>,
+    ) {
         // Nothing to do until a server gets the first recv data from a potential client
     }
 
@@ -581,7 +656,12 @@ impl ClientHandler {
 }
 
 impl EndpointEventCallbacks for ClientHandler {
-    fn connection_started(&mut self, endpoint: &mut Endpoint, cid: &ConnectionId) {
+    fn connection_started(
+        &mut self,
+        endpoint: &mut Endpoint,
+        cid: &ConnectionId,
+        _peer_identity: Option<PeerIdentity>,
+    ) {
         println!("Announcing Self to Server!");
         let mut send_data = self.create_announce_data();
         set_stream_msg_size(&mut send_data);
@@ -0,0 +1,468 @@
+//Media Enhanced Swiftlet Quic Throughput Benchmark Example
+//MIT License
+//Copyright (c) 2024 Jared Loewenthal
+//
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+//
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+//
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+// Sends a large transfer from a client to a server Endpoint over the loopback interface
+// once without and once with UDP batching (sendmmsg / recvmmsg with GSO / GRO on Linux) and reports the throughput.
+// Afterwards a server fans small real-time "voice" segments out to many client connections (many small datagrams)
+// once without and once with UDP batching and reports how long handing each round to the Endpoint took
+
+const ALPN_NAME: &[u8] = b"throughput"; // Application-Layer Protocol Negotiation Name used to define the Quic-Application Protocol used in this program
+const SERVER_NAME: &str = "localhost"; // Server "Name" / Domain Name that is put on the generated server certificate
+
+const TRANSFER_SIZE: usize = 256 * 1024 * 1024; // 256 MiB
+const CHUNK_SIZE: usize = 1024 * 1024; // Sent and received 1 MiB at a time
+
+const VOICE_LISTENERS: usize = 64; // Client connections that each get every voice segment
+const VOICE_ROUNDS: u64 = 500; // A segment to every listener each server tick
+const VOICE_SEGMENT_SIZE: usize = 160;
+const VOICE_GRACE_TICKS: u64 = 50; // Time for the last segments to arrive before the server closes the connections
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use swiftlet_quic::{
+    endpoint::{
+        tls::PeerIdentity, BackgroundStreamId, Config, ConnectionEndReason, ConnectionId, Endpoint,
+        SocketAddr, StreamClass,
+    },
+    EndpointEventCallbacks, EndpointHandler,
+};
+
+fn main() {
+    for (port, udp_batching) in [(9011, false), (9012, true)] {
+        match run_transfer(port, udp_batching) {
+            Some(duration) => println!(
+                "UDP Batching {}: {} MiB in {:?} ({:.1} Mbps)",
+                if udp_batching { "On" } else { "Off" },
+                TRANSFER_SIZE / (1024 * 1024),
+                duration,
+                (TRANSFER_SIZE * 8) as f64 / duration.as_secs_f64() / 1_000_000.0
+            ),
+            None => println!("Transfer did not complete!"),
+        }
+    }
+    for (port, udp_batching) in [(9013, false), (9014, true)] {
+        match run_voice_fan_out(port, udp_batching) {
+            Some((segments_received, send_duration)) => println!(
+                "Voice Fan-Out UDP Batching {}: {} of {} segments received, {:.1} us per round of {} segments",
+                if udp_batching { "On" } else { "Off" },
+                segments_received,
+                VOICE_ROUNDS * VOICE_LISTENERS as u64,
+                send_duration.as_secs_f64() * 1_000_000.0 / VOICE_ROUNDS as f64,
+                VOICE_LISTENERS
+            ),
+            None => println!("Voice fan-out did not complete!"),
+        }
+    }
+}
+
+fn run_transfer(port: u16, udp_batching: bool) -> Option<Duration> {
+    let server_address = SocketAddr::V6(std::net::SocketAddrV6::new(
+        std::net::Ipv6Addr::LOCALHOST,
+        port,
+        0,
+        0,
+    ));
+
+    let (server_identity, client_verification) = common::self_signed_pair(SERVER_NAME);
+
+    let server_thread_handle = std::thread::spawn(move || {
+        let server_endpoint = Endpoint::new_server_with_identity(
+            &[server_address],
+            ALPN_NAME,
+            server_identity,
+            create_config(None, udp_batching),
+        );
+        match server_endpoint {
+            Ok(mut endpoint) => {
+                let mut server_state = ServerState {
+                    bytes_received: 0,
+                    start_instant: None,
+                    duration_opt: None,
+                };
+                let mut endpoint_handler = EndpointHandler::new(&mut endpoint, &mut server_state);
+                if let Err(e) = endpoint_handler.run_event_loop(Duration::from_millis(5)) {
+                    println!("Server Error: {:?}", e);
+                }
+                server_state.duration_opt
+            }
+            Err(e) => {
+                println!("Server Endpoint Creation Error: {:?}", e);
+                None
+            }
+        }
+    });
+    std::thread::sleep(Duration::from_millis(100));
+
+    let client_thread_handle = std::thread::spawn(move || {
+        let client_endpoint = Endpoint::new_client_with_verification(
+            true,
+            ALPN_NAME,
+            client_verification,
+            create_config(Some(Duration::from_millis(2000)), udp_batching),
+        );
+        match client_endpoint {
+            Ok(mut endpoint) => {
+                if let Err(e) = endpoint.add_client_connection(server_address, SERVER_NAME) {
+                    println!("Client Connection Error: {:?}", e);
+                    return;
+                }
+                let mut client_state = ClientState {
+                    send_data: vec![7; CHUNK_SIZE],
+                    bytes_sent: 0,
+                };
+                let mut endpoint_handler = EndpointHandler::new(&mut endpoint, &mut client_state);
+                if let Err(e) = endpoint_handler.run_event_loop(Duration::from_millis(5)) {
+                    println!("Client Error: {:?}", e);
+                }
+            }
+            Err(e) => println!("Client Endpoint Creation Error: {:?}", e),
+        }
+    });
+
+    client_thread_handle.join().unwrap();
+    server_thread_handle.join().unwrap()
+}
+
+fn run_voice_fan_out(port: u16, udp_batching: bool) -> Option<(u64, Duration)> {
+    let server_address = SocketAddr::V6(std::net::SocketAddrV6::new(
+        std::net::Ipv6Addr::LOCALHOST,
+        port,
+        0,
+        0,
+    ));
+
+    let (server_identity, client_verification) = common::self_signed_pair(SERVER_NAME);
+
+    let server_thread_handle = std::thread::spawn(move || {
+        let server_endpoint = Endpoint::new_server_with_identity(
+            &[server_address],
+            ALPN_NAME,
+            server_identity,
+            create_config(None, udp_batching),
+        );
+        match server_endpoint {
+            Ok(mut endpoint) => {
+                let mut server_state = VoiceServerState {
+                    listeners: Vec::with_capacity(VOICE_LISTENERS),
+                    rounds_sent: 0,
+                    grace_ticks: 0,
+                    send_duration: Duration::ZERO,
+                };
+                let mut endpoint_handler = EndpointHandler::new(&mut endpoint, &mut server_state);
+                if let Err(e) = endpoint_handler.run_event_loop(Duration::from_millis(2)) {
+                    println!("Server Error: {:?}", e);
+                }
+                if server_state.rounds_sent == VOICE_ROUNDS {
+                    Some(server_state.send_duration)
+                } else {
+                    None
+                }
+            }
+            Err(e) => {
+                println!("Server Endpoint Creation Error: {:?}", e);
+                None
+            }
+        }
+    });
+    std::thread::sleep(Duration::from_millis(100));
+
+    let client_thread_handle = std::thread::spawn(move || {
+        let client_endpoint = Endpoint::new_client_with_verification(
+            true,
+            ALPN_NAME,
+            client_verification,
+            create_config(Some(Duration::from_millis(2000)), udp_batching),
+        );
+        match client_endpoint {
+            Ok(mut endpoint) => {
+                for _ in 0..VOICE_LISTENERS {
+                    if let Err(e) = endpoint.add_client_connection(server_address, SERVER_NAME) {
+                        println!("Client Connection Error: {:?}", e);
+                        return 0;
+                    }
+                }
+                let mut client_state = VoiceClientState {
+                    segments_received: 0,
+                };
+                let mut endpoint_handler = EndpointHandler::new(&mut endpoint, &mut client_state);
+                if let Err(e) = endpoint_handler.run_event_loop(Duration::from_millis(5)) {
+                    println!("Client Error: {:?}", e);
+                }
+                client_state.segments_received
+            }
+            Err(e) => {
+                println!("Client Endpoint Creation Error: {:?}", e);
+                0
+            }
+        }
+    });
+
+    let segments_received = client_thread_handle.join().unwrap();
+    server_thread_handle
+        .join()
+        .unwrap()
+        .map(|send_duration| (segments_received, send_duration))
+}
+
+fn create_config(keep_alive_timeout: Option<Duration>, udp_batching: bool) -> Config {
+    Config {
+        reliable_stream_buffer: 16 * 1024 * 1024,
+        initial_background_recv_size: CHUNK_SIZE,
+        background_recv_first_bytes: CHUNK_SIZE,
+        send_queue_low_water: CHUNK_SIZE * 4,
+        udp_batching,
+        ..common::base_config(keep_alive_timeout)
+    }
+}
+
+struct ServerState {
+    bytes_received: usize,
+    start_instant: Option<Instant>,
+    duration_opt: Option<Duration>,
+}
+
+impl EndpointEventCallbacks for ServerState {
+    fn connection_started(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        _peer_identity: Option<PeerIdentity>,
+    ) {
+        self.start_instant = Some(Instant::now());
+    }
+
+    fn connection_ended(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        _reason: ConnectionEndReason,
+        _remaining_connections: usize,
+    ) -> bool {
+        true // Only a single client is expected so the server can stop
+    }
+
+    fn tick(&mut self, _endpoint: &mut Endpoint) -> bool {
+        false
+    }
+
+    fn main_stream_recv(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        _read_data: &[u8],
+    ) -> Option<usize> {
+        None // Nothing is expected on the main stream
+    }
+
+    fn background_stream_recv(
+        &mut self,
+        endpoint: &mut Endpoint,
+        cid: &ConnectionId,
+        read_data: &[u8],
+        _bkgd_id: BackgroundStreamId,
+    ) -> Option<usize> {
+        self.bytes_received += read_data.len();
+        if self.bytes_received < TRANSFER_SIZE {
+            Some(CHUNK_SIZE.min(TRANSFER_SIZE - self.bytes_received))
+        } else {
+            if let Some(start_instant) = self.start_instant {
+                self.duration_opt = Some(start_instant.elapsed());
+            }
+            let _ = endpoint.close_connection(cid, 0);
+            Some(CHUNK_SIZE) // The connection is closing anyways
+        }
+    }
+}
+
+struct ClientState {
+    send_data: Vec<u8>,
+    bytes_sent: usize,
+}
+
+impl ClientState {
+    // Queues chunks until the background send queue reaches the low-water mark
+    fn send_chunks(&mut self, endpoint: &mut Endpoint, cid: &ConnectionId) {
+        while self.bytes_sent < TRANSFER_SIZE {
+            match endpoint.queued_bytes(cid, StreamClass::Background) {
+                Ok(queued_bytes) if queued_bytes < CHUNK_SIZE * 4 => {}
+                _ => return,
+            }
+            if endpoint
+                .background_stream_send(cid, self.send_data.clone())
+                .is_err()
+            {
+                return;
+            }
+            self.bytes_sent += CHUNK_SIZE;
+        }
+    }
+}
+
+impl EndpointEventCallbacks for ClientState {
+    fn connection_started(
+        &mut self,
+        endpoint: &mut Endpoint,
+        cid: &ConnectionId,
+        _peer_identity: Option<PeerIdentity>,
+    ) {
+        self.send_chunks(endpoint, cid);
+    }
+
+    fn connection_ended(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        _reason: ConnectionEndReason,
+        remaining_connections: usize,
+    ) -> bool {
+        remaining_connections == 0
+    }
+
+    fn stream_writable(&mut self, endpoint: &mut Endpoint, cid: &ConnectionId, class: StreamClass) {
+        if class == StreamClass::Background {
+            self.send_chunks(endpoint, cid);
+        }
+    }
+
+    fn tick(&mut self, _endpoint: &mut Endpoint) -> bool {
+        false
+    }
+
+    fn main_stream_recv(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        _read_data: &[u8],
+    ) -> Option<usize> {
+        None // Nothing is expected on the main stream
+    }
+}
+
+struct VoiceServerState {
+    listeners: Vec<ConnectionId>,
+    rounds_sent: u64,
+    grace_ticks: u64,
+    send_duration: Duration,
+}
+
+impl EndpointEventCallbacks for VoiceServerState {
+    fn connection_started(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        cid: &ConnectionId,
+        _peer_identity: Option<PeerIdentity>,
+    ) {
+        self.listeners.push(*cid);
+    }
+
+    fn connection_ended(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        _reason: ConnectionEndReason,
+        remaining_connections: usize,
+    ) -> bool {
+        remaining_connections == 0
+    }
+
+    fn tick(&mut self, endpoint: &mut Endpoint) -> bool {
+        if self.listeners.len() < VOICE_LISTENERS {
+            return false; // Every listener gets every round
+        }
+        if self.rounds_sent < VOICE_ROUNDS {
+            let segment = vec![self.rounds_sent as u8; VOICE_SEGMENT_SIZE];
+            let start_instant = Instant::now();
+            for cid in self.listeners.iter() {
+                let _ = endpoint.rt_stream_send(cid, Some(segment.clone()), true);
+            }
+            self.send_duration += start_instant.elapsed();
+            self.rounds_sent += 1;
+        } else {
+            self.grace_ticks += 1;
+            if self.grace_ticks == VOICE_GRACE_TICKS {
+                for cid in self.listeners.iter() {
+                    let _ = endpoint.close_connection(cid, 0);
+                }
+            }
+        }
+        false
+    }
+
+    fn main_stream_recv(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        _read_data: &[u8],
+    ) -> Option<usize> {
+        None // Nothing is expected on the main stream
+    }
+}
+
+struct VoiceClientState {
+    segments_received: u64,
+}
+
+impl EndpointEventCallbacks for VoiceClientState {
+    fn connection_started(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        _peer_identity: Option<PeerIdentity>,
+    ) {
+    }
+
+    fn connection_ended(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        _reason: ConnectionEndReason,
+        remaining_connections: usize,
+    ) -> bool {
+        remaining_connections == 0
+    }
+
+    fn tick(&mut self, _endpoint: &mut Endpoint) -> bool {
+        false
+    }
+
+    fn main_stream_recv(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        _read_data: &[u8],
+    ) -> Option<usize> {
+        None // Nothing is expected on the main stream
+    }
+
+    fn rt_stream_recv(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        _read_data: &[u8],
+        _rt_id: u64,
+    ) -> usize {
+        self.segments_received += 1;
+        0 // Wait for the next finished real-time segment
+    }
+}
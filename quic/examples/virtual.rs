@@ -0,0 +1,268 @@
+//Media Enhanced Swiftlet Quic Virtual Link Example
+//MIT License
+//Copyright (c) 2024 Jared Loewenthal
+//
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+//
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+//
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+// Runs a server and client Endpoint pair within this process over a lossy virtual link
+// and reports how many 10ms real-time "voice" segments made it across
+
+const ALPN_NAME: &[u8] = b"virtual"; // Application-Layer Protocol Negotiation Name used to define the Quic-Application Protocol used in this program
+const SERVER_NAME: &str = "localhost"; // Server "Name" / Domain Name that is put on the generated server certificate
+
+const VOICE_SEGMENTS: u64 = 500; // 5 seconds of 10ms real-time segments
+const VOICE_SEGMENT_SIZE: usize = 160;
+
+mod common;
+
+use std::time::Duration;
+
+use swiftlet_quic::{
+    endpoint::{
+        tls::PeerIdentity,
+        transport::{virtual_link_pair, LinkConfig},
+        Config, CongestionControl, ConnectionEndReason, ConnectionId, Endpoint, Pacing, SocketAddr,
+    },
+    EndpointEventCallbacks, EndpointHandler,
+};
+
+fn main() {
+    let server_address: SocketAddr = "[fd00::1]:9001".parse().unwrap();
+    let client_address: SocketAddr = "[fd00::2]:50000".parse().unwrap();
+
+    let lossy_link = LinkConfig {
+        latency: Duration::from_millis(40),
+        jitter: Duration::from_millis(10),
+        loss_probability: 0.05,
+        reorder_probability: 0.01,
+        reorder_delay: Duration::from_millis(15),
+        bandwidth_bps: Some(10_000_000),
+        seed: 42,
+    };
+
+    let (server_socket, client_socket) = virtual_link_pair(
+        server_address,
+        client_address,
+        lossy_link.clone(),
+        lossy_link,
+    );
+    let server_monitor = server_socket.get_monitor();
+    let client_monitor = client_socket.get_monitor();
+
+    let (server_identity, client_verification) = common::self_signed_pair(SERVER_NAME);
+
+    let server_thread_handle = std::thread::spawn(move || {
+        let server_endpoint = Endpoint::new_server_with_transport(
+            Box::new(server_socket),
+            ALPN_NAME,
+            server_identity,
+            create_config(None),
+        );
+        match server_endpoint {
+            Ok(mut endpoint) => {
+                let mut server_state = ServerState::new();
+                let mut endpoint_handler = EndpointHandler::new(&mut endpoint, &mut server_state);
+                if let Err(e) = endpoint_handler.run_event_loop(Duration::from_millis(5)) {
+                    println!("Server Error: {:?}", e);
+                }
+                server_state.print_results();
+            }
+            Err(e) => println!("Server Endpoint Creation Error: {:?}", e),
+        }
+    });
+
+    let client_thread_handle = std::thread::spawn(move || {
+        let client_endpoint = Endpoint::new_client_with_transport(
+            Box::new(client_socket),
+            ALPN_NAME,
+            client_verification,
+            create_config(Some(Duration::from_millis(2000))),
+        );
+        match client_endpoint {
+            Ok(mut endpoint) => {
+                if let Err(e) = endpoint.add_client_connection(server_address, SERVER_NAME) {
+                    println!("Client Connection Error: {:?}", e);
+                    return;
+                }
+                let mut client_state = ClientState::new();
+                let mut endpoint_handler = EndpointHandler::new(&mut endpoint, &mut client_state);
+                if let Err(e) = endpoint_handler.run_event_loop(Duration::from_millis(5)) {
+                    println!("Client Error: {:?}", e);
+                }
+            }
+            Err(e) => println!("Client Endpoint Creation Error: {:?}", e),
+        }
+    });
+
+    client_thread_handle.join().unwrap();
+    server_thread_handle.join().unwrap();
+
+    println!("Server Bound Link Stats: {:?}", server_monitor.get_stats());
+    println!("Client Bound Link Stats: {:?}", client_monitor.get_stats());
+}
+
+fn create_config(keep_alive_timeout: Option<Duration>) -> Config {
+    Config {
+        congestion_control: CongestionControl::Bbr2,
+        pacing: Pacing::On,
+        background_bandwidth_share: Some(50),
+        rt_max_age: Some(Duration::from_millis(100)),
+        ..common::base_config(keep_alive_timeout)
+    }
+}
+
+struct ServerState {
+    segments_received: u64,
+    segments_skipped: u64,
+    expected_rt_id: u64,
+}
+
+impl ServerState {
+    fn new() -> Self {
+        ServerState {
+            segments_received: 0,
+            segments_skipped: 0,
+            expected_rt_id: 0,
+        }
+    }
+
+    fn print_results(&self) {
+        println!(
+            "Server received {} of {} real-time segments ({} skipped)",
+            self.segments_received, VOICE_SEGMENTS, self.segments_skipped
+        );
+    }
+}
+
+impl EndpointEventCallbacks for ServerState {
+    fn connection_started(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        cid: &ConnectionId,
+        _peer_identity: Option<PeerIdentity>,
+    ) {
+        println!("Server Connection Started: {}", cid);
+    }
+
+    fn connection_ended(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        reason: ConnectionEndReason,
+        _remaining_connections: usize,
+    ) -> bool {
+        println!("Server Connection Ended Reason: {:?}", reason);
+        // Only a single client is expected so the server can stop
+        true
+    }
+
+    fn tick(&mut self, _endpoint: &mut Endpoint) -> bool {
+        false
+    }
+
+    fn main_stream_recv(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        _read_data: &[u8],
+    ) -> Option<usize> {
+        Some(0)
+    }
+
+    fn rt_stream_recv(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        _read_data: &[u8],
+        rt_id: u64,
+    ) -> usize {
+        if rt_id > self.expected_rt_id {
+            self.segments_skipped += rt_id - self.expected_rt_id;
+        }
+        self.expected_rt_id = rt_id + 1;
+        self.segments_received += 1;
+        0 // Wait for the next finished real-time segment
+    }
+}
+
+struct ClientState {
+    cid_option: Option<ConnectionId>,
+    segments_sent: u64,
+    tick_count: u64,
+}
+
+impl ClientState {
+    fn new() -> Self {
+        ClientState {
+            cid_option: None,
+            segments_sent: 0,
+            tick_count: 0,
+        }
+    }
+}
+
+impl EndpointEventCallbacks for ClientState {
+    fn connection_started(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        cid: &ConnectionId,
+        _peer_identity: Option<PeerIdentity>,
+    ) {
+        println!("Client Connection Started: {}", cid);
+        self.cid_option = Some(*cid);
+    }
+
+    fn connection_ended(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        reason: ConnectionEndReason,
+        remaining_connections: usize,
+    ) -> bool {
+        println!("Client Connection Ended Reason: {:?}", reason);
+        self.cid_option = None;
+        remaining_connections == 0
+    }
+
+    fn tick(&mut self, endpoint: &mut Endpoint) -> bool {
+        self.tick_count += 1;
+        if (self.tick_count % 2) == 0 {
+            if let Some(cid) = &self.cid_option {
+                if self.segments_sent < VOICE_SEGMENTS {
+                    let send_data = vec![self.segments_sent as u8; VOICE_SEGMENT_SIZE];
+                    let _ = endpoint.rt_stream_send(cid, Some(send_data), true);
+                    self.segments_sent += 1;
+                } else {
+                    let _ = endpoint.close_connection(cid, 0);
+                    self.cid_option = None;
+                }
+            }
+        }
+        false
+    }
+
+    fn main_stream_recv(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        _read_data: &[u8],
+    ) -> Option<usize> {
+        Some(0)
+    }
+}
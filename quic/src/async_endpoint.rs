@@ -0,0 +1,787 @@
+//Media Enhanced Swiftlet Quic Rust Library for Real-time Internet Communications
+//MIT License
+//Copyright (c) 2024 Jared Loewenthal
+//
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+//
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+//
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+// Async (tokio) Adapter
+// The single-threaded EndpointHandler event loop keeps running on its own thread and talks to the
+// async side over tokio channels. Commands from the async side wake up the event loop with a Waker.
+// The receive channels are bounded and once one of them is full the connection stops reading its
+// main and background streams, so QUIC flow control makes the peer wait for the application.
+
+use crate::endpoint::tls::PeerIdentity;
+use crate::endpoint::{
+    BackgroundStreamId, ConnectionEndReason, ConnectionId, Endpoint, Error, SocketAddr, Waker,
+    DEFAULT_BACKGROUND_STREAM,
+};
+use crate::{EndpointEventCallbacks, EndpointHandler};
+
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_core::Stream;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot};
+
+/// Number of messages (or time segments) that each receive stream of a connection can buffer up
+///
+/// A full main or background stream pauses reading from the connection until the application catches up.
+/// Time segments that don't fit are dropped since a newer one is on its way.
+pub const RECV_CHANNEL_CAPACITY: usize = 64;
+
+/// Application error code that a connection is closed with when a message on an additional background stream
+/// is longer than the max_message_len (the main and default background streams close it like any other stream failure)
+pub const MESSAGE_TOO_LONG_ERROR_CODE: u64 = 0x4D4C;
+
+/// How received main and background stream data is split up into messages
+///
+/// Every message starts with a fixed length header that tells how many body bytes follow it.
+/// The Endpoint Config main_recv_first_bytes and background_recv_first_bytes must be set to the header_len.
+#[derive(Debug, Clone, Copy)]
+pub struct MessageFraming {
+    /// Number of header bytes at the start of every message (must be greater than 0)
+    pub header_len: usize,
+    /// Returns the number of body bytes that follow the given header
+    pub body_len: fn(&[u8]) -> usize,
+    /// Maximum length of a message (header included)
+    ///
+    /// A header that announces a longer message closes the connection before any of its body is buffered up.
+    pub max_message_len: usize,
+}
+
+/// Errors that the async Endpoint adapter can return
+#[derive(Debug)]
+pub enum AsyncError {
+    /// Error from the Endpoint
+    Endpoint(Error),
+    /// The Endpoint event loop thread has stopped
+    Stopped,
+    /// The connection ended before it was established
+    ConnectionEnded(ConnectionEndReason),
+}
+
+// Commands from the async side that get handled by the event loop thread
+enum Command {
+    Connect(SocketAddr, String, oneshot::Sender<ConnectResult>),
+    MainSend(ConnectionId, Vec<u8>, oneshot::Sender<Result<(), Error>>),
+    RtSend(
+        ConnectionId,
+        Vec<u8>,
+        bool,
+        oneshot::Sender<Result<(), Error>>,
+    ),
+    BackgroundSend(ConnectionId, Vec<u8>, oneshot::Sender<Result<(), Error>>),
+    Close(ConnectionId, u64, oneshot::Sender<Result<bool, Error>>),
+    Shutdown,
+}
+
+type ConnectResult = Result<(Connection, ConnectionStreams), AsyncError>;
+
+/// Async QUIC Endpoint (server or client)
+///
+/// The Endpoint runs on its own thread with the regular EndpointHandler event loop,
+/// so this only needs a tokio runtime for awaiting the results. Dropping it closes all of its connections
+/// and the event loop thread stops once they have ended.
+pub struct AsyncEndpoint {
+    command_send: mpsc::UnboundedSender<Command>,
+    waker: Waker,
+    incoming_recv: mpsc::UnboundedReceiver<(Connection, ConnectionStreams)>,
+}
+
+impl AsyncEndpoint {
+    /// Start an async Endpoint
+    ///
+    /// The create_endpoint function is called on the new event loop thread since an Endpoint stays on the thread it was created on.
+    /// The tick duration is passed on to the EndpointHandler run_event_loop call.
+    pub async fn spawn<F>(
+        create_endpoint: F,
+        framing: MessageFraming,
+        tick_duration: Duration,
+    ) -> Result<Self, AsyncError>
+    where
+        F: FnOnce() -> Result<Endpoint, Error> + Send + 'static,
+    {
+        let (command_send, command_recv) = mpsc::unbounded_channel();
+        let (incoming_send, incoming_recv) = mpsc::unbounded_channel();
+        let (ready_send, ready_recv) = oneshot::channel();
+
+        let engine_command_send = command_send.clone();
+        std::thread::spawn(move || {
+            let mut endpoint = match create_endpoint() {
+                Ok(endpoint) => endpoint,
+                Err(e) => {
+                    let _ = ready_send.send(Err(e));
+                    return;
+                }
+            };
+            let waker = endpoint.get_waker();
+            if ready_send.send(Ok(waker.clone())).is_err() {
+                return;
+            }
+
+            let mut engine = Engine {
+                command_recv,
+                command_send: engine_command_send,
+                waker,
+                incoming_send,
+                pending_connects: HashMap::new(),
+                connections: HashMap::new(),
+                framing,
+                shutting_down: false,
+            };
+            let mut endpoint_handler = EndpointHandler::new(&mut endpoint, &mut engine);
+            // Only a finished shutdown (once every connection has ended) or an error returns
+            let _ = endpoint_handler.run_event_loop(tick_duration);
+            // Dropping the engine ends all of the receive streams and pending connects
+        });
+
+        match ready_recv.await {
+            Ok(Ok(waker)) => Ok(AsyncEndpoint {
+                command_send,
+                waker,
+                incoming_recv,
+            }),
+            Ok(Err(e)) => Err(AsyncError::Endpoint(e)),
+            Err(_) => Err(AsyncError::Stopped),
+        }
+    }
+
+    /// Wait for the next connection started by a peer (only on a server)
+    ///
+    /// Returns None once the Endpoint event loop thread has stopped.
+    pub async fn accept(&mut self) -> Option<(Connection, ConnectionStreams)> {
+        self.incoming_recv.recv().await
+    }
+
+    /// Connect to a server (only on a client)
+    ///
+    /// Returns once the connection is established and ready for the application.
+    pub async fn connect(
+        &self,
+        peer_addr: SocketAddr,
+        server_name: &str,
+    ) -> Result<(Connection, ConnectionStreams), AsyncError> {
+        let (reply_send, reply_recv) = oneshot::channel();
+        send_command(
+            &self.command_send,
+            &self.waker,
+            Command::Connect(peer_addr, server_name.to_string(), reply_send),
+        )?;
+        match reply_recv.await {
+            Ok(result) => result,
+            Err(_) => Err(AsyncError::Stopped),
+        }
+    }
+}
+
+impl Drop for AsyncEndpoint {
+    fn drop(&mut self) {
+        let _ = send_command(&self.command_send, &self.waker, Command::Shutdown);
+    }
+}
+
+fn send_command(
+    command_send: &mpsc::UnboundedSender<Command>,
+    waker: &Waker,
+    command: Command,
+) -> Result<(), AsyncError> {
+    match command_send.send(command) {
+        Ok(_) => {
+            waker.wake();
+            Ok(())
+        }
+        Err(_) => Err(AsyncError::Stopped),
+    }
+}
+
+/// Handle for sending on a connection of an AsyncEndpoint
+///
+/// It can be cloned to send from multiple tasks.
+#[derive(Clone)]
+pub struct Connection {
+    cid: ConnectionId,
+    peer_identity_opt: Option<PeerIdentity>,
+    command_send: mpsc::UnboundedSender<Command>,
+    waker: Waker,
+}
+
+impl Connection {
+    /// Get the Endpoint connection id
+    pub fn id(&self) -> ConnectionId {
+        self.cid
+    }
+
+    /// Get the verified certificate identity of the peer (see EndpointEventCallbacks connection_started)
+    pub fn peer_identity(&self) -> Option<&PeerIdentity> {
+        self.peer_identity_opt.as_ref()
+    }
+
+    /// Send a message over the main stream (see Endpoint main_stream_send)
+    pub async fn send_main(&self, send_data: Vec<u8>) -> Result<(), AsyncError> {
+        let (reply_send, reply_recv) = oneshot::channel();
+        self.send_command(Command::MainSend(self.cid, send_data, reply_send))?;
+        flatten_reply(reply_recv.await)
+    }
+
+    /// Send data over the real-time stream (see Endpoint rt_stream_send)
+    ///
+    /// Set last_send_of_time_segment to true with the final data of each time segment.
+    pub async fn send_rt(
+        &self,
+        send_data: Vec<u8>,
+        last_send_of_time_segment: bool,
+    ) -> Result<(), AsyncError> {
+        let (reply_send, reply_recv) = oneshot::channel();
+        self.send_command(Command::RtSend(
+            self.cid,
+            send_data,
+            last_send_of_time_segment,
+            reply_send,
+        ))?;
+        flatten_reply(reply_recv.await)
+    }
+
+    /// Send a message over the default background stream (see Endpoint background_stream_send)
+    pub async fn send_background(&self, send_data: Vec<u8>) -> Result<(), AsyncError> {
+        let (reply_send, reply_recv) = oneshot::channel();
+        self.send_command(Command::BackgroundSend(self.cid, send_data, reply_send))?;
+        flatten_reply(reply_recv.await)
+    }
+
+    /// Close the connection with a given error code value (see Endpoint close_connection)
+    pub async fn close(&self, error_code: u64) -> Result<bool, AsyncError> {
+        let (reply_send, reply_recv) = oneshot::channel();
+        self.send_command(Command::Close(self.cid, error_code, reply_send))?;
+        flatten_reply(reply_recv.await)
+    }
+
+    #[inline]
+    fn send_command(&self, command: Command) -> Result<(), AsyncError> {
+        send_command(&self.command_send, &self.waker, command)
+    }
+}
+
+fn flatten_reply<T>(
+    reply: Result<Result<T, Error>, oneshot::error::RecvError>,
+) -> Result<T, AsyncError> {
+    match reply {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(e)) => Err(AsyncError::Endpoint(e)),
+        Err(_) => Err(AsyncError::Stopped),
+    }
+}
+
+/// The receive streams of a connection
+///
+/// Each stream ends once the connection has ended.
+pub struct ConnectionStreams {
+    /// Messages received on the main stream
+    pub main: MainStream,
+    /// Time segments received on the real-time stream
+    pub rt: RtStream,
+    /// Messages received on the background streams
+    pub background: BackgroundStream,
+}
+
+/// Stream of messages (header included) received on the main stream of a connection
+pub struct MainStream {
+    recv: mpsc::Receiver<Vec<u8>>,
+    resume: RecvResume,
+}
+
+impl MainStream {
+    /// Wait for the next message
+    pub async fn recv(&mut self) -> Option<Vec<u8>> {
+        let message_opt = self.recv.recv().await;
+        self.resume.notify();
+        message_opt
+    }
+}
+
+impl Stream for MainStream {
+    type Item = Vec<u8>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.recv.poll_recv(cx);
+        if let Poll::Ready(Some(_)) = poll {
+            self.resume.notify();
+        }
+        poll
+    }
+}
+
+/// Stream of complete time segments received on the real-time stream of a connection
+///
+/// The Endpoint Config rt_recv_first_bytes should be set to 0 so each time segment is read as a whole.
+pub struct RtStream {
+    recv: mpsc::Receiver<Vec<u8>>,
+}
+
+impl RtStream {
+    /// Wait for the next time segment
+    pub async fn recv(&mut self) -> Option<Vec<u8>> {
+        self.recv.recv().await
+    }
+}
+
+impl Stream for RtStream {
+    type Item = Vec<u8>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.recv.poll_recv(cx)
+    }
+}
+
+/// Stream of messages (header included) received on the background streams of a connection
+pub struct BackgroundStream {
+    recv: mpsc::Receiver<(BackgroundStreamId, Vec<u8>)>,
+    resume: RecvResume,
+}
+
+impl BackgroundStream {
+    /// Wait for the next message and the background stream it arrived on
+    pub async fn recv(&mut self) -> Option<(BackgroundStreamId, Vec<u8>)> {
+        let message_opt = self.recv.recv().await;
+        self.resume.notify();
+        message_opt
+    }
+}
+
+impl Stream for BackgroundStream {
+    type Item = (BackgroundStreamId, Vec<u8>);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.recv.poll_recv(cx);
+        if let Poll::Ready(Some(_)) = poll {
+            self.resume.notify();
+        }
+        poll
+    }
+}
+
+// Wakes up the event loop once the application took a message from a paused connection
+#[derive(Clone)]
+struct RecvResume {
+    paused: Arc<AtomicBool>,
+    waker: Waker,
+}
+
+impl RecvResume {
+    #[inline]
+    fn notify(&self) {
+        if self.paused.load(Ordering::Acquire) {
+            self.waker.wake();
+        }
+    }
+}
+
+// Puts the message header and body reads back together
+#[derive(Default)]
+struct MessageAssembler {
+    header_opt: Option<(Vec<u8>, usize)>, // Header and the body length it announced
+}
+
+impl MessageAssembler {
+    // Returns the completed message (if any) and the number of bytes to read next
+    // or None when the header announced a message longer than the max_message_len
+    // A stream finishing in the middle of a message hands over less data which is dropped
+    fn next(
+        &mut self,
+        framing: &MessageFraming,
+        read_data: &[u8],
+    ) -> Option<(Option<Vec<u8>>, usize)> {
+        match self.header_opt.take() {
+            Some((_, body_len)) if read_data.len() < body_len => Some((None, framing.header_len)),
+            Some((mut message, _)) => {
+                message.extend_from_slice(read_data);
+                Some((Some(message), framing.header_len))
+            }
+            None if read_data.len() < framing.header_len => Some((None, framing.header_len)),
+            None => {
+                let body_len = (framing.body_len)(read_data);
+                let message_len = framing.header_len.saturating_add(body_len);
+                if message_len > framing.max_message_len {
+                    None
+                } else if body_len == 0 {
+                    Some((Some(read_data.to_vec()), framing.header_len))
+                } else {
+                    self.header_opt = Some((read_data.to_vec(), body_len));
+                    Some((None, body_len))
+                }
+            }
+        }
+    }
+}
+
+// A message waiting for room in its receive channel
+enum HeldMessage {
+    Main(Vec<u8>),
+    Background(BackgroundStreamId, Vec<u8>),
+}
+
+// The event loop side of a connection
+struct ConnectionChannels {
+    main_send: mpsc::Sender<Vec<u8>>,
+    main_assembler: MessageAssembler,
+    rt_send: mpsc::Sender<Vec<u8>>,
+    background_send: mpsc::Sender<(BackgroundStreamId, Vec<u8>)>,
+    background_assemblers: HashMap<BackgroundStreamId, MessageAssembler>,
+    held_messages: VecDeque<HeldMessage>, // Kept in order while the connection is paused
+    resume: RecvResume,
+}
+
+impl ConnectionChannels {
+    // Hands the message over and pauses reading from the connection when its channel is full
+    fn forward(&mut self, endpoint: &mut Endpoint, cid: &ConnectionId, message: HeldMessage) {
+        self.held_messages.push_back(message);
+        if !self.send_held() && !self.resume.paused.swap(true, Ordering::AcqRel) {
+            let _ = endpoint.pause_stream_recv(cid);
+        }
+    }
+
+    // Returns true once every held message has been handed over
+    // (A closed channel just means the application stopped listening so the message is dropped)
+    fn send_held(&mut self) -> bool {
+        while let Some(message) = self.held_messages.pop_front() {
+            let full_message_opt = match message {
+                HeldMessage::Main(data) => match self.main_send.try_send(data) {
+                    Err(TrySendError::Full(data)) => Some(HeldMessage::Main(data)),
+                    _ => None,
+                },
+                HeldMessage::Background(bkgd_id, data) => {
+                    match self.background_send.try_send((bkgd_id, data)) {
+                        Err(TrySendError::Full((bkgd_id, data))) => {
+                            Some(HeldMessage::Background(bkgd_id, data))
+                        }
+                        _ => None,
+                    }
+                }
+            };
+            if let Some(full_message) = full_message_opt {
+                self.held_messages.push_front(full_message);
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// Runs on the event loop thread and forwards the Endpoint events to the async side
+struct Engine {
+    command_recv: mpsc::UnboundedReceiver<Command>,
+    command_send: mpsc::UnboundedSender<Command>, // Cloned into new connection handles
+    waker: Waker,
+    incoming_send: mpsc::UnboundedSender<(Connection, ConnectionStreams)>,
+    pending_connects: HashMap<ConnectionId, oneshot::Sender<ConnectResult>>,
+    connections: HashMap<ConnectionId, ConnectionChannels>,
+    framing: MessageFraming,
+    shutting_down: bool, // The event loop keeps running until the closing connections have ended
+}
+
+impl Engine {
+    // Connections continue reading once the application has made room for all of their held messages
+    fn release_held(&mut self, endpoint: &mut Endpoint) {
+        for (cid, channels) in self.connections.iter_mut() {
+            if channels.resume.paused.load(Ordering::Acquire) && channels.send_held() {
+                channels.resume.paused.store(false, Ordering::Release);
+                let _ = endpoint.resume_stream_recv(cid);
+            }
+        }
+    }
+
+    // Returns true if the event loop should exit
+    fn handle_command(&mut self, endpoint: &mut Endpoint, command: Command) -> bool {
+        match command {
+            Command::Connect(peer_addr, server_name, reply_send) => {
+                match endpoint.add_client_connection(peer_addr, &server_name) {
+                    Ok(cid) => {
+                        self.pending_connects.insert(cid, reply_send);
+                    }
+                    Err(e) => {
+                        let _ = reply_send.send(Err(AsyncError::Endpoint(e)));
+                    }
+                }
+            }
+            Command::MainSend(cid, send_data, reply_send) => {
+                let _ = reply_send.send(endpoint.main_stream_send(&cid, send_data));
+            }
+            Command::RtSend(cid, send_data, last_send_of_time_segment, reply_send) => {
+                let _ = reply_send.send(endpoint.rt_stream_send(
+                    &cid,
+                    Some(send_data),
+                    last_send_of_time_segment,
+                ));
+            }
+            Command::BackgroundSend(cid, send_data, reply_send) => {
+                let _ = reply_send.send(endpoint.background_stream_send(&cid, send_data));
+            }
+            Command::Close(cid, error_code, reply_send) => {
+                let _ = reply_send.send(endpoint.close_connection(&cid, error_code));
+            }
+            Command::Shutdown => {
+                for cid in self.connections.keys().chain(self.pending_connects.keys()) {
+                    let _ = endpoint.close_connection(cid, 0);
+                }
+                self.shutting_down = true;
+                return endpoint.get_num_connections() == 0;
+            }
+        }
+        false
+    }
+}
+
+impl EndpointEventCallbacks for Engine {
+    fn connection_started(
+        &mut self,
+        endpoint: &mut Endpoint,
+        cid: &ConnectionId,
+        peer_identity_opt: Option<PeerIdentity>,
+    ) {
+        // A handshake that finishes during the shutdown has nobody left to hand the connection to
+        if self.shutting_down {
+            let _ = endpoint.close_connection(cid, 0);
+            return;
+        }
+
+        let (main_send, main_recv) = mpsc::channel(RECV_CHANNEL_CAPACITY);
+        let (rt_send, rt_recv) = mpsc::channel(RECV_CHANNEL_CAPACITY);
+        let (background_send, background_recv) = mpsc::channel(RECV_CHANNEL_CAPACITY);
+        let resume = RecvResume {
+            paused: Arc::new(AtomicBool::new(false)),
+            waker: self.waker.clone(),
+        };
+        self.connections.insert(
+            *cid,
+            ConnectionChannels {
+                main_send,
+                main_assembler: MessageAssembler::default(),
+                rt_send,
+                background_send,
+                background_assemblers: HashMap::new(),
+                held_messages: VecDeque::new(),
+                resume: resume.clone(),
+            },
+        );
+
+        let connection = Connection {
+            cid: *cid,
+            peer_identity_opt,
+            command_send: self.command_send.clone(),
+            waker: self.waker.clone(),
+        };
+        let streams = ConnectionStreams {
+            main: MainStream {
+                recv: main_recv,
+                resume: resume.clone(),
+            },
+            rt: RtStream { recv: rt_recv },
+            background: BackgroundStream {
+                recv: background_recv,
+                resume,
+            },
+        };
+        match self.pending_connects.remove(cid) {
+            Some(reply_send) => {
+                let _ = reply_send.send(Ok((connection, streams)));
+            }
+            None => {
+                let _ = self.incoming_send.send((connection, streams));
+            }
+        }
+    }
+
+    fn connection_ended(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        cid: &ConnectionId,
+        reason: ConnectionEndReason,
+        remaining_connections: usize,
+    ) -> bool {
+        // Dropping the channels ends the receive streams
+        self.connections.remove(cid);
+        if let Some(reply_send) = self.pending_connects.remove(cid) {
+            let _ = reply_send.send(Err(AsyncError::ConnectionEnded(reason)));
+        }
+        self.shutting_down && remaining_connections == 0
+    }
+
+    fn tick(&mut self, endpoint: &mut Endpoint) -> bool {
+        if self.shutting_down {
+            return endpoint.get_num_connections() == 0;
+        }
+        // Backup for a wake that raced with the pause
+        self.release_held(endpoint);
+        false
+    }
+
+    fn wake(&mut self, endpoint: &mut Endpoint) -> bool {
+        while let Ok(command) = self.command_recv.try_recv() {
+            if self.handle_command(endpoint, command) {
+                return true;
+            }
+        }
+        self.release_held(endpoint);
+        false
+    }
+
+    fn main_stream_recv(
+        &mut self,
+        endpoint: &mut Endpoint,
+        cid: &ConnectionId,
+        read_data: &[u8],
+    ) -> Option<usize> {
+        let channels = self.connections.get_mut(cid)?;
+        // A message that is too long closes the connection since the main stream is required
+        let (message_opt, next_len) = channels.main_assembler.next(&self.framing, read_data)?;
+        if let Some(message) = message_opt {
+            channels.forward(endpoint, cid, HeldMessage::Main(message));
+        }
+        Some(next_len)
+    }
+
+    fn rt_stream_recv(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        cid: &ConnectionId,
+        read_data: &[u8],
+        _rt_id: u64,
+    ) -> usize {
+        if let Some(channels) = self.connections.get(cid) {
+            if !read_data.is_empty() {
+                // Dropped when the application is behind (just like a late time segment)
+                let _ = channels.rt_send.try_send(read_data.to_vec());
+            }
+        }
+        0 // Always wait for the next finished time segment
+    }
+
+    fn background_stream_recv(
+        &mut self,
+        endpoint: &mut Endpoint,
+        cid: &ConnectionId,
+        read_data: &[u8],
+        bkgd_id: BackgroundStreamId,
+    ) -> Option<usize> {
+        let channels = self.connections.get_mut(cid)?;
+        let assembled_opt = channels
+            .background_assemblers
+            .entry(bkgd_id)
+            .or_default()
+            .next(&self.framing, read_data);
+        match assembled_opt {
+            Some((message_opt, next_len)) => {
+                if let Some(message) = message_opt {
+                    channels.forward(endpoint, cid, HeldMessage::Background(bkgd_id, message));
+                }
+                Some(next_len)
+            }
+            None => {
+                // Returning None only closes the connection for the default background stream
+                channels.background_assemblers.remove(&bkgd_id);
+                if bkgd_id != DEFAULT_BACKGROUND_STREAM {
+                    let _ = endpoint.close_connection(cid, MESSAGE_TOO_LONG_ERROR_CODE);
+                }
+                None
+            }
+        }
+    }
+
+    fn background_stream_recv_ended(
+        &mut self,
+        endpoint: &mut Endpoint,
+        cid: &ConnectionId,
+        bkgd_id: BackgroundStreamId,
+    ) {
+        if let Some(channels) = self.connections.get_mut(cid) {
+            channels.background_assemblers.remove(&bkgd_id);
+        }
+        // Additional background streams are only ever received on through the async adapter
+        let _ = endpoint.finish_background_stream(cid, bkgd_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_framing() -> MessageFraming {
+        MessageFraming {
+            header_len: 2,
+            body_len: |header| u16::from_be_bytes([header[0], header[1]]) as usize,
+            max_message_len: 12,
+        }
+    }
+
+    #[test]
+    fn header_then_body_makes_a_message() {
+        let framing = test_framing();
+        let mut assembler = MessageAssembler::default();
+        assert_eq!(assembler.next(&framing, &[0, 3]), Some((None, 3)));
+        assert_eq!(
+            assembler.next(&framing, &[7, 8, 9]),
+            Some((Some(vec![0, 3, 7, 8, 9]), 2))
+        );
+        assert_eq!(assembler.next(&framing, &[0, 1]), Some((None, 1)));
+    }
+
+    #[test]
+    fn empty_body_is_a_message_right_away() {
+        let framing = test_framing();
+        let mut assembler = MessageAssembler::default();
+        assert_eq!(
+            assembler.next(&framing, &[0, 0]),
+            Some((Some(vec![0, 0]), 2))
+        );
+    }
+
+    #[test]
+    fn partial_reads_are_dropped() {
+        let framing = test_framing();
+        let mut assembler = MessageAssembler::default();
+        assert_eq!(assembler.next(&framing, &[0]), Some((None, 2)));
+
+        assert_eq!(assembler.next(&framing, &[0, 4]), Some((None, 4)));
+        assert_eq!(assembler.next(&framing, &[1, 2]), Some((None, 2)));
+        // The assembler starts over with a header
+        assert_eq!(assembler.next(&framing, &[0, 2]), Some((None, 2)));
+    }
+
+    #[test]
+    fn message_length_is_capped() {
+        let framing = test_framing();
+        let mut assembler = MessageAssembler::default();
+        assert_eq!(assembler.next(&framing, &[0, 10]), Some((None, 10)));
+        assert!(assembler.next(&framing, &[0; 10]).is_some());
+        assert_eq!(assembler.next(&framing, &[0, 11]), None);
+
+        let tiny_framing = MessageFraming {
+            max_message_len: 1,
+            ..framing
+        };
+        assert_eq!(
+            MessageAssembler::default().next(&tiny_framing, &[0, 0]),
+            None
+        );
+    }
+}
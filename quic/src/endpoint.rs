@@ -22,6 +22,9 @@
 
 // Socket Address format used within the library
 pub use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use ring::rand::*;
@@ -29,8 +32,91 @@ use ring::rand::*;
 mod udp;
 use udp::{Socket, SocketError};
 
+/// Datagram Transport Module
+pub mod transport;
+use transport::{Transport, TransportWaker};
+
+/// TLS Certificate Module
+pub mod tls;
+use tls::{ClientVerification, Identity, PeerIdentity, ServerVerification};
+
+mod session;
+use session::SessionCache;
+
+mod admission;
+use admission::{RecvBudget, SourceRateLimiter};
+
+mod table;
+use table::ConnectionTable;
+
 mod connection;
-use connection::{CloseInfo, CloseOrigin, Connection, RecvResult, SendResult, StreamResult};
+use connection::{
+    CloseInfo, CloseOrigin, Connection, RecvResult, SendResult, SendSchedule, StreamResult,
+};
+
+// How long a Retry token is valid for in seconds
+const RETRY_TOKEN_LIFETIME: u64 = 10;
+// Prefix that separates Retry token signatures from connection ID derivations using the same key
+const RETRY_TOKEN_PREFIX: &[u8] = b"swiftlet retry";
+// Prefixes that separate the additional connection IDs and their stateless reset tokens from the first connection ID
+const CONNECTION_ID_PREFIX: &[u8] = b"swiftlet cid";
+const RESET_TOKEN_PREFIX: &[u8] = b"swiftlet reset";
+
+/// Congestion control algorithms available to every connection of an Endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CongestionControl {
+    /// Classic loss-based NewReno
+    Reno,
+    /// Loss-based CUBIC (the quiche default)
+    Cubic,
+    /// Model-based BBR (version 1)
+    Bbr,
+    /// Model-based BBR version 2 which reacts to loss more than BBR
+    Bbr2,
+}
+
+/// Packet pacing behavior for every connection of an Endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pacing {
+    /// Packets are sent as soon as the congestion window allows which can cause bursts
+    Off,
+    /// Packets are spread out over time based on the congestion controller's pacing rate
+    On,
+    /// Pacing is on and the pacing rate never goes above this value (in bytes per second)
+    MaxRate(u64),
+}
+
+/// The stream classes of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamClass {
+    /// The reliable main stream
+    Main,
+    /// The real-time streams
+    Realtime,
+    /// The reliable background streams
+    Background,
+}
+
+/// Identifies one of the reliable background streams of a connection
+pub type BackgroundStreamId = u64;
+
+/// The background stream that every connection starts with
+///
+/// The peer finishing this stream closes the connection, unlike the additional background streams.
+pub const DEFAULT_BACKGROUND_STREAM: BackgroundStreamId = 4;
+
+/// Send priority of a stream class (based on the RFC 9218 Extensible Priority parameters)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamPriority {
+    /// Streams with a lower urgency are always sent before streams with a higher urgency.
+    ///
+    /// RFC 9218 uses values from 0 to 7 (with 3 as the default).
+    pub urgency: u8,
+
+    /// Incremental streams of the same urgency share the bandwidth in a round-robin fashion
+    /// instead of being sent one after another.
+    pub incremental: bool,
+}
 
 /// The Endpoint Configuration Structure
 ///
@@ -87,6 +173,137 @@ pub struct Config {
     ///
     /// If this value is set to 0 it will be changed to 1 during endpoint creation
     pub background_recv_first_bytes: usize,
+
+    /// The real-time datagram send and receive queue lengths (in number of datagrams).
+    ///
+    /// When this value is greater than 0, QUIC DATAGRAM frames are enabled for every connection
+    /// on this endpoint, allowing real-time data to be sent with rt_datagram_send without any stream setup.
+    /// A value of 0 disables datagram frames entirely.
+    /// Both endpoints must enable datagram frames for a connection to be able to use them.
+    pub rt_datagram_queue_len: usize,
+
+    /// The directory to write qlog (JSON-SEQ) event traces into.
+    ///
+    /// When this value is set, every connection writes its events to a "<ConnectionId>.sqlog" file
+    /// inside this (existing) directory which can then be loaded into qvis for inspection.
+    /// None disables qlog output entirely.
+    pub qlog_dir: Option<PathBuf>,
+
+    /// The file to append NSS key-log lines (TLS secrets) to for every connection.
+    ///
+    /// Useful for decrypting packet captures in Wireshark.
+    /// When this value is None the SSLKEYLOGFILE environment variable is used instead (if it is set).
+    /// Key logging is off entirely when neither is set.
+    pub keylog_path: Option<PathBuf>,
+
+    /// The certificate and private key a client Endpoint presents when the server requires client certificates.
+    ///
+    /// Only used by a client Endpoint.
+    pub client_identity: Option<Identity>,
+
+    /// How a server Endpoint verifies client certificates (mutual TLS).
+    ///
+    /// When this value is set every client must present a trusted certificate before connection_started gets called
+    /// and the verified identity is given to connection_started.
+    /// Only used by a server Endpoint.
+    pub client_verification: Option<ClientVerification>,
+
+    /// Validate the address of every new client with a stateless Retry before creating its connection.
+    ///
+    /// The server responds to a client's first Initial packet with a Retry carrying a signed, short-lived token
+    /// and only creates the connection once the client repeats its Initial with that token from the same address.
+    /// This costs one extra round trip per connection but protects against amplification and half-open connection floods.
+    /// Only used by a server Endpoint.
+    pub stateless_retry: bool,
+
+    /// Enable 0-RTT early data for resumed sessions.
+    ///
+    /// A server will accept early data and a client will send early data when it has a cached session for the server name.
+    /// Client sessions are always cached in memory (keyed by server name) so reconnecting to the same server resumes quickly.
+    /// Early data can be replayed by an attacker so it should only hold idempotent messages (like an announcement).
+    /// A client that pins the server certificate (Fingerprints or KnownHosts verification) never sends early data
+    /// since a resumed session skips the certificate check that the pin relies on.
+    pub early_data: bool,
+
+    /// The file to persist client session tickets to so sessions can be resumed across program runs.
+    ///
+    /// The file holds resumption secrets so it should be protected like a private key.
+    /// None keeps the sessions in memory only.
+    /// Only used by a client Endpoint.
+    pub session_cache_path: Option<PathBuf>,
+
+    /// The congestion control algorithm used by every connection.
+    ///
+    /// Loss-based algorithms (Reno and CUBIC) fill up network queues which adds latency to real-time data
+    /// while BBR and BBR2 try to keep queues (and therefore latency) low.
+    pub congestion_control: CongestionControl,
+
+    /// The initial congestion window in number of packets.
+    ///
+    /// None uses the quiche default (10 packets).
+    pub initial_congestion_window: Option<usize>,
+
+    /// The packet pacing behavior used by every connection.
+    ///
+    /// A maximum pacing rate can be used to cap bursts from large transfers.
+    pub pacing: Pacing,
+
+    /// The send priority of the main stream.
+    pub main_stream_priority: StreamPriority,
+
+    /// The send priority of every real-time stream.
+    ///
+    /// Giving this the lowest urgency makes real-time data always preempt the other streams.
+    pub rt_stream_priority: StreamPriority,
+
+    /// The send priority of the background stream.
+    pub background_stream_priority: StreamPriority,
+
+    /// The maximum percentage (1 to 100) of the estimated bandwidth that background stream data can use.
+    ///
+    /// This leaves room for real-time data to arrive without queueing behind a large transfer
+    /// but also limits the background stream when nothing else is sent.
+    /// None lets the background stream use all the remaining bandwidth.
+    pub background_bandwidth_share: Option<u8>,
+
+    /// The maximum age of a real-time time segment before its unsent data gets dropped.
+    ///
+    /// The age starts when the first data of a time segment is given to rt_stream_send.
+    /// When the time segment has not been completely sent by then, the real-time stream
+    /// gets reset so stale data doesn't hold up fresher data (counted in rt_segments_expired of the connection stats).
+    /// None keeps sending a time segment until the next one replaces it.
+    pub rt_max_age: Option<Duration>,
+
+    /// The low-water mark (in bytes) of the main and background stream send queues.
+    ///
+    /// Once a send queue has reached this many queued bytes, the stream_writable callback gets called
+    /// when the queue drops below it again. This allows large transfers to be sent in chunks.
+    /// A value of 0 disables the stream_writable callback.
+    pub send_queue_low_water: usize,
+
+    /// The number of additional background streams the peer is allowed to open on each connection.
+    ///
+    /// Streams that have been finished by both sides count towards this limit until the peer gives more credit
+    /// (which quiche does automatically as streams complete).
+    pub max_background_streams: u64,
+
+    /// Batch the sends and receives of the OS UDP socket together to reduce the number of system calls.
+    ///
+    /// On Linux this uses sendmmsg / recvmmsg along with UDP Generic Segmentation Offload (GSO) for datagrams
+    /// sent to the same address and Generic Receive Offload (GRO). Whatever the kernel does not support
+    /// gets turned off at runtime. Other platforms and custom transports ignore this value.
+    pub udp_batching: bool,
+
+    /// The largest datagram (UDP payload) size in bytes that path MTU discovery probes for.
+    ///
+    /// Some(size) enables Datagram Packetization Layer Path MTU Discovery (RFC 8899) on each connection,
+    /// which starts from the QUIC minimum of 1200 bytes and grows the sent datagrams up to the size confirmed
+    /// by the peer. The size is clamped to between 1200 and 8972 bytes (a 9000 byte jumbo frame MTU over IPv4).
+    /// None keeps every datagram within the 1232 bytes that any IPv6 path can carry without probing.
+    pub max_datagram_size: Option<usize>,
+
+    /// Limits on the connections a server Endpoint accepts and on the data each connection can send it.
+    pub admission: AdmissionLimits,
 }
 
 /// The Quic Endpoint structure
@@ -96,17 +313,77 @@ pub struct Endpoint {
     local_addr: SocketAddr,
     connection_config: connection::Config,
     next_connection_id: u64,
-    connections: Vec<Connection>,
-    last_valid_index: usize,
+    connections: ConnectionTable,
     last_recv_index: Option<usize>,
     stream_process_index: Option<(u64, usize)>,
+    resumed_recv: Vec<(u64, usize)>, // Connections whose queued stream data still needs processing
     rand: SystemRandom,
     config: Config,
     is_server: bool,
-    conn_id_seed_key: ring::hmac::Key, // Value matters ONLY if is_server is true
+    conn_id_seed_key: ring::hmac::Key, // Derives additional connection IDs (and the first ones on a server)
+    keylog_file: Option<std::fs::File>,
+    session_cache: SessionCache, // Only used by a client
+    source_limiter_opt: Option<SourceRateLimiter>, // Only used by a server
+    woken: Arc<AtomicBool>,      // Set by Wakers from other threads
     stats: Stats,
 }
 
+/// Wakes up an Endpoint from another thread
+///
+/// Every clone wakes up the same Endpoint. A woken up EndpointHandler event loop calls the wake callback
+/// right away instead of waiting for the next tick.
+#[derive(Clone)]
+pub struct Waker {
+    woken: Arc<AtomicBool>,
+    transport_waker_opt: Option<Arc<dyn TransportWaker>>,
+}
+
+impl Waker {
+    /// Wake up the Endpoint
+    pub fn wake(&self) {
+        self.woken.store(true, Ordering::Release);
+        if let Some(transport_waker) = &self.transport_waker_opt {
+            transport_waker.wake();
+        }
+    }
+}
+
+/// Admission Limits
+///
+/// Every limit that is None is turned off (AdmissionLimits::default() turns all of them off).
+/// The connection limits only apply to server Endpoints.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AdmissionLimits {
+    /// Maximum number of connections (established or not).
+    ///
+    /// New connections over the limit are closed right away with the ConnectionRefused reason
+    /// so the client knows that the server is full.
+    pub max_connections: Option<usize>,
+
+    /// Maximum number of connections that are still performing their handshake.
+    ///
+    /// Initial packets over the limit are dropped without creating any connection state
+    /// (the client retries them like lost packets).
+    pub max_handshakes: Option<usize>,
+
+    /// Maximum number of new connections per second from a single source IP address.
+    ///
+    /// Initial packets over the limit are dropped the same way as max_handshakes.
+    /// Combine this with the stateless_retry option so the source addresses are validated first.
+    pub max_new_connections_per_ip: Option<u32>,
+
+    /// Maximum number of bytes per second that a connection can send to the Endpoint.
+    ///
+    /// Only the data that the connection accepts (after decryption) counts towards it. Connections over the budget
+    /// are closed with the RecvBudgetExceeded reason and any datagrams that still arrive that second are dropped unread.
+    pub max_recv_bytes_per_sec: Option<u64>,
+
+    /// Maximum number of QUIC packets per second that a connection can send to the Endpoint.
+    ///
+    /// Counted the same way as max_recv_bytes_per_sec.
+    pub max_recv_packets_per_sec: Option<u64>,
+}
+
 /// Endpoint Stats
 #[derive(Debug)]
 pub struct Stats {
@@ -114,6 +391,14 @@ pub struct Stats {
     pub sleep_time: Duration,
     /// Total number of delayed sends
     pub delayed_sends: u64,
+    /// Total number of Initial packets dropped by the max_handshakes and max_new_connections_per_ip limits
+    pub initials_dropped: u64,
+    /// Total number of new connections refused by the max_connections limit
+    pub connections_refused: u64,
+    /// Total number of connections closed for going over their inbound budget
+    pub budget_closes: u64,
+    /// Total number of datagrams dropped unread while their connection was over its inbound budget
+    pub budget_drops: u64,
 }
 
 impl Stats {
@@ -121,10 +406,54 @@ impl Stats {
         Stats {
             sleep_time: Duration::from_millis(0),
             delayed_sends: 0,
+            initials_dropped: 0,
+            connections_refused: 0,
+            budget_closes: 0,
+            budget_drops: 0,
         }
     }
 }
 
+/// Connection Stats
+///
+/// A snapshot of the transport state of a single connection (based on its active network path).
+/// Useful for displaying link quality or adapting the rate of real-time data like audio bitrate.
+#[derive(Debug, Clone)]
+pub struct ConnectionStats {
+    /// Smoothed round trip time estimate
+    pub rtt: Duration,
+    /// Round trip time variation estimate
+    pub rtt_var: Duration,
+    /// Minimum round trip time observed (None until the first sample)
+    pub min_rtt: Option<Duration>,
+    /// Current congestion window size in bytes
+    pub cwnd: usize,
+    /// Most recent delivery rate estimate in bytes per second
+    ///
+    /// This is provided instead of a pacing rate since quiche (0.20) keeps the pacing rate of its
+    /// congestion controller internal. The delivery rate is what the path actually carried recently,
+    /// which is the better input for adapting the bitrate of real-time data anyway.
+    pub delivery_rate: u64,
+    /// Total number of QUIC packets sent
+    pub packets_sent: usize,
+    /// Total number of QUIC packets received
+    pub packets_recv: usize,
+    /// Total number of QUIC packets deemed lost
+    pub packets_lost: usize,
+    /// Total number of QUIC packets with data that needed to be retransmitted
+    pub packets_retransmitted: usize,
+    /// Total number of bytes sent
+    pub bytes_sent: u64,
+    /// Total number of bytes received
+    pub bytes_recv: u64,
+    /// Total number of bytes deemed lost
+    pub bytes_lost: u64,
+    /// Total number of real-time time segments dropped for being older than the Config rt_max_age
+    pub rt_segments_expired: u64,
+    /// Largest datagram (UDP payload) size currently used for sending (grows as path MTU discovery confirms sizes)
+    pub max_datagram_size: usize,
+}
+
 /// A Connection ID used to communicate with the endpoint about a specific connection.
 pub type ConnectionId = u64;
 
@@ -135,6 +464,8 @@ pub enum Error {
     SocketCreation,
     /// Error with the Quic Config Creation
     ConfigCreation,
+    /// Error generating a certificate and private key
+    CertificateCreation,
     /// Error with creating or using the randomness structure / functions
     Randomness,
     /// Error trying to perform a client Endpoint operation on a server Endpoint
@@ -166,6 +497,14 @@ pub enum Error {
     StreamSend,
     /// Error receiving data from the stream
     StreamRecv(connection::Error),
+    /// Error sending a datagram (peer unsupported, datagram too large, or send queue full)
+    DatagramSend(connection::Error),
+    /// The peer does not allow any more background streams to be opened right now
+    BackgroundStreamLimit,
+    /// The background stream is not open (or was already finished) on the connection
+    BackgroundStreamNotFound,
+    /// Error issuing a new connection ID (the handshake is not done yet or the peer uses zero-length connection IDs)
+    ConnectionIdIssue(connection::Error),
 }
 
 /// Based on combination of QUIC Transport Error Codes and Endpoint Error Codes
@@ -211,6 +550,10 @@ pub enum EndpointCloseReason {
     MainStreamFinished,
     /// Background stream finished
     BackgroundStreamFinished,
+    /// Peer certificate not trusted by the verification policy
+    UntrustedCertificate,
+    /// Peer sent more bytes or packets than the inbound budget allows
+    RecvBudgetExceeded,
 
     /// TLS Alert Start
     CryptoErrorStart = 0x0100,
@@ -273,6 +616,12 @@ impl EndpointCloseReason {
             x if x == EndpointCloseReason::BackgroundStreamFinished as u64 => {
                 EndpointCloseReason::BackgroundStreamFinished
             }
+            x if x == EndpointCloseReason::UntrustedCertificate as u64 => {
+                EndpointCloseReason::UntrustedCertificate
+            }
+            x if x == EndpointCloseReason::RecvBudgetExceeded as u64 => {
+                EndpointCloseReason::RecvBudgetExceeded
+            }
 
             // Need to adjust this to cover more errors
             x if x == EndpointCloseReason::CryptoErrorStart as u64 => {
@@ -337,6 +686,7 @@ pub(super) enum NextEvent {
     ConnectionEnded((ConnectionId, ConnectionEndReason)),
     ConnectionEnding((ConnectionId, ConnectionEndReason)),
     ReceivedData,
+    Woken,
 }
 
 pub(super) enum RecvEvent {
@@ -344,10 +694,14 @@ pub(super) enum RecvEvent {
     DoneReceiving,
     ConnectionEnded((ConnectionId, ConnectionEndReason)),
     ConnectionEnding((ConnectionId, ConnectionEndReason)),
-    EstablishedOnce(ConnectionId),
+    EstablishedOnce((ConnectionId, Option<PeerIdentity>)),
+    ConnectionMigrated((ConnectionId, SocketAddr)),
+    StreamWritable((ConnectionId, StreamClass)),
     MainStreamReceived((ConnectionId, usize, Vec<u8>, usize)),
     RealtimeReceived(ConnectionId, usize, Vec<u8>, usize, u64),
-    BackgroundStreamReceived((ConnectionId, usize, Vec<u8>, usize)),
+    RealtimeDatagramReceived((ConnectionId, Vec<u8>)),
+    BackgroundStreamReceived((ConnectionId, usize, BackgroundStreamId, Vec<u8>, usize)),
+    BackgroundStreamRecvEnded((ConnectionId, BackgroundStreamId)),
 }
 
 pub(super) enum ReadInfo {
@@ -357,29 +711,163 @@ pub(super) enum ReadInfo {
     ConnectionEnding(ConnectionEndReason),
 }
 
+fn retry_token_message(peer_addr: SocketAddr, odcid: &[u8], timestamp: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(64 + odcid.len());
+    message.extend_from_slice(RETRY_TOKEN_PREFIX);
+    match peer_addr.ip() {
+        std::net::IpAddr::V4(ip) => message.extend_from_slice(&ip.octets()),
+        std::net::IpAddr::V6(ip) => message.extend_from_slice(&ip.octets()),
+    }
+    message.extend_from_slice(&peer_addr.port().to_be_bytes());
+    message.extend_from_slice(odcid);
+    message.extend_from_slice(timestamp);
+    message
+}
+
+fn unix_time_secs() -> u64 {
+    match std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+// Additional connection IDs are HMAC-SHA256 tags of the first connection ID and a sequence number
+// so they can't be linked to each other (or to the first one) by anyone without the key
+fn derive_connection_id(key: &ring::hmac::Key, first_scid: &[u8], seq: u64) -> ring::hmac::Tag {
+    let mut message = Vec::with_capacity(CONNECTION_ID_PREFIX.len() + first_scid.len() + 8);
+    message.extend_from_slice(CONNECTION_ID_PREFIX);
+    message.extend_from_slice(first_scid);
+    message.extend_from_slice(&seq.to_be_bytes());
+    ring::hmac::sign(key, &message)
+}
+
+// The stateless reset token that goes along with a connection ID
+fn derive_reset_token(key: &ring::hmac::Key, scid: &[u8]) -> u128 {
+    let mut message = Vec::with_capacity(RESET_TOKEN_PREFIX.len() + scid.len());
+    message.extend_from_slice(RESET_TOKEN_PREFIX);
+    message.extend_from_slice(scid);
+    let tag = ring::hmac::sign(key, &message);
+    let mut token = [0; 16];
+    token.copy_from_slice(&tag.as_ref()[..16]);
+    u128::from_be_bytes(token)
+}
+
+// Token Format: [odcid length][odcid][timestamp (8 bytes)][HMAC-SHA256 tag of peer address, odcid and timestamp]
+fn mint_retry_token(key: &ring::hmac::Key, peer_addr: SocketAddr, odcid: &[u8]) -> Vec<u8> {
+    let timestamp = unix_time_secs().to_be_bytes();
+    let tag = ring::hmac::sign(key, &retry_token_message(peer_addr, odcid, &timestamp));
+
+    let mut token = Vec::with_capacity(1 + odcid.len() + timestamp.len() + tag.as_ref().len());
+    token.push(odcid.len() as u8);
+    token.extend_from_slice(odcid);
+    token.extend_from_slice(&timestamp);
+    token.extend_from_slice(tag.as_ref());
+    token
+}
+
+// Returns the original destination connection ID when the token is valid for this peer address
+fn validate_retry_token<'a>(
+    key: &ring::hmac::Key,
+    peer_addr: SocketAddr,
+    token: &'a [u8],
+) -> Option<&'a [u8]> {
+    let odcid_len = *token.first()? as usize;
+    let odcid_end = 1 + odcid_len;
+    let timestamp_end = odcid_end + 8;
+    if token.len() <= timestamp_end {
+        return None;
+    }
+
+    let odcid = &token[1..odcid_end];
+    let timestamp = &token[odcid_end..timestamp_end];
+    let tag = &token[timestamp_end..];
+
+    let message = retry_token_message(peer_addr, odcid, timestamp);
+    ring::hmac::verify(key, &message, tag).ok()?;
+
+    let mut timestamp_bytes = [0; 8];
+    timestamp_bytes.copy_from_slice(timestamp);
+    let token_time = u64::from_be_bytes(timestamp_bytes);
+    if unix_time_secs() > token_time + RETRY_TOKEN_LIFETIME {
+        return None;
+    }
+
+    Some(odcid)
+}
+
 impl Endpoint {
     // Maybe combine new_server and new_client together... but there is hardly any real benefit (and sacrifices readability)
 
     /// Create a QUIC Server Endpoint
+    ///
+    /// The UDP socket gets bound to the first of the bind addresses that works (like std::net::UdpSocket::bind).
+    /// A specific IP address (with a scope ID for a link-local IPv6 address) pins the server to that interface.
+    /// The wildcard IPv6 address `[::]` binds a dual-stack socket that accepts both IPv6 and IPv4 clients.
     pub fn new_server(
-        ipv6_mode: bool,
-        bind_port: u16,
+        bind_addrs: &[SocketAddr],
         alpn: &[u8],
         cert_path: &str,
         pkey_path: &str,
+        config: Config,
+    ) -> Result<Self, Error> {
+        let identity = Identity::PemFiles {
+            cert_path: PathBuf::from(cert_path),
+            pkey_path: PathBuf::from(pkey_path),
+        };
+        Endpoint::new_server_with_identity(bind_addrs, alpn, identity, config)
+    }
+
+    /// Create a QUIC Server Endpoint that identifies itself with the given certificate and private key
+    ///
+    /// The identity can come from files or from memory (see tls::generate_self_signed for quick testing).
+    /// The bind addresses are used the same way as new_server.
+    pub fn new_server_with_identity(
+        bind_addrs: &[SocketAddr],
+        alpn: &[u8],
+        identity: Identity,
+        config: Config,
+    ) -> Result<Self, Error> {
+        match udp::new_os_transport(
+            bind_addrs,
+            config.udp_batching,
+            udp::max_datagram_size(config.max_datagram_size),
+        ) {
+            Ok(transport) => Endpoint::new_server_with_transport(transport, alpn, identity, config),
+            Err(_) => Err(Error::SocketCreation),
+        }
+    }
+
+    /// Create a QUIC Server Endpoint that uses the given datagram transport instead of an OS UDP socket
+    ///
+    /// Useful for running a server and client pair within a single process over a simulated network.
+    pub fn new_server_with_transport(
+        transport: Box<dyn Transport>,
+        alpn: &[u8],
+        identity: Identity,
         mut config: Config,
     ) -> Result<Self, Error> {
-        if let Ok((socket_mgr, local_addr)) = Socket::new(ipv6_mode, bind_port) {
-            let max_payload_size = udp::TARGET_MAX_DATAGRAM_SIZE;
+        if let Ok((socket_mgr, local_addr)) = Socket::new(transport) {
+            let max_payload_size = udp::max_datagram_size(config.max_datagram_size);
+            let keylog_file = Endpoint::open_keylog_file(&config);
 
             let connection_config = match Connection::create_config(
                 &[alpn],
-                cert_path,
-                Some(pkey_path),
+                true,
+                Some(&identity),
+                None,
+                config.client_verification.as_ref(),
                 config.idle_timeout_in_ms,
                 max_payload_size,
                 config.reliable_stream_buffer,
                 config.unreliable_stream_buffer,
+                config.rt_datagram_queue_len,
+                keylog_file.is_some(),
+                config.early_data,
+                config.congestion_control,
+                config.initial_congestion_window,
+                config.pacing,
+                config.max_background_streams,
+                config.max_datagram_size.is_some(),
             ) {
                 Ok(cfg) => cfg,
                 Err(_) => return Err(Error::ConfigCreation),
@@ -399,20 +887,29 @@ impl Endpoint {
                 config.initial_background_recv_size = 1;
             }
 
+            let source_limiter_opt = config
+                .admission
+                .max_new_connections_per_ip
+                .map(SourceRateLimiter::new);
+
             let endpoint_manager = Endpoint {
                 udp: socket_mgr,
                 max_payload_size,
                 local_addr,
                 connection_config,
                 next_connection_id: 1,
-                connections: Vec::new(),
-                last_valid_index: 0,
+                connections: ConnectionTable::new(),
                 last_recv_index: None,
                 stream_process_index: None,
+                resumed_recv: Vec::new(),
                 rand,
                 config,
                 is_server: true,
                 conn_id_seed_key,
+                keylog_file,
+                session_cache: SessionCache::new(None),
+                source_limiter_opt,
+                woken: Arc::new(AtomicBool::new(false)),
                 stats: Stats::new(),
             };
 
@@ -427,19 +924,72 @@ impl Endpoint {
         ipv6_mode: bool,
         alpn: &[u8],
         cert_path: &str,
+        config: Config,
+    ) -> Result<Self, Error> {
+        let verification = ServerVerification::CaFile(PathBuf::from(cert_path));
+        Endpoint::new_client_with_verification(ipv6_mode, alpn, verification, config)
+    }
+
+    /// Create a QUIC Client Endpoint that verifies servers with the given verification method
+    pub fn new_client_with_verification(
+        ipv6_mode: bool,
+        alpn: &[u8],
+        verification: ServerVerification,
+        config: Config,
+    ) -> Result<Self, Error> {
+        let bind_addr = if ipv6_mode {
+            SocketAddr::from((std::net::Ipv6Addr::UNSPECIFIED, 0))
+        } else {
+            SocketAddr::from((std::net::Ipv4Addr::UNSPECIFIED, 0))
+        };
+        match udp::new_os_transport(
+            &[bind_addr],
+            config.udp_batching,
+            udp::max_datagram_size(config.max_datagram_size),
+        ) {
+            Ok(transport) => {
+                Endpoint::new_client_with_transport(transport, alpn, verification, config)
+            }
+            Err(_) => Err(Error::SocketCreation),
+        }
+    }
+
+    /// Create a QUIC Client Endpoint that uses the given datagram transport instead of an OS UDP socket
+    ///
+    /// Useful for running a server and client pair within a single process over a simulated network.
+    pub fn new_client_with_transport(
+        transport: Box<dyn Transport>,
+        alpn: &[u8],
+        verification: ServerVerification,
         mut config: Config,
     ) -> Result<Self, Error> {
-        if let Ok((socket_mgr, local_addr)) = Socket::new(ipv6_mode, 0) {
-            let max_payload_size = udp::TARGET_MAX_DATAGRAM_SIZE;
+        if let Ok((socket_mgr, local_addr)) = Socket::new(transport) {
+            let max_payload_size = udp::max_datagram_size(config.max_datagram_size);
+            let keylog_file = Endpoint::open_keylog_file(&config);
+
+            // No application data goes out before a pinned certificate is checked
+            if !verification.verifies_chain() {
+                config.early_data = false;
+            }
 
             let connection_config = match Connection::create_config(
                 &[alpn],
-                cert_path,
+                false,
+                config.client_identity.as_ref(),
+                Some(&verification),
                 None,
                 config.idle_timeout_in_ms,
                 max_payload_size,
                 config.reliable_stream_buffer,
                 config.unreliable_stream_buffer,
+                config.rt_datagram_queue_len,
+                keylog_file.is_some(),
+                config.early_data,
+                config.congestion_control,
+                config.initial_congestion_window,
+                config.pacing,
+                config.max_background_streams,
+                config.max_datagram_size.is_some(),
             ) {
                 Ok(cfg) => cfg,
                 Err(_) => return Err(Error::ConfigCreation),
@@ -460,20 +1010,26 @@ impl Endpoint {
                 config.initial_background_recv_size = 1;
             }
 
+            let session_cache = SessionCache::new(config.session_cache_path.clone());
+
             let endpoint_manager = Endpoint {
                 udp: socket_mgr,
                 max_payload_size,
                 local_addr,
                 connection_config,
                 next_connection_id: 1,
-                connections: Vec::new(),
-                last_valid_index: 0,
+                connections: ConnectionTable::new(),
                 last_recv_index: None,
                 stream_process_index: None,
+                resumed_recv: Vec::new(),
                 rand,
                 config,
                 is_server: false,
                 conn_id_seed_key,
+                keylog_file,
+                session_cache,
+                source_limiter_opt: None,
+                woken: Arc::new(AtomicBool::new(false)),
                 stats: Stats::new(),
             };
 
@@ -485,19 +1041,7 @@ impl Endpoint {
 
     #[inline]
     fn find_connection_from_cid(&self, cid: ConnectionId) -> Option<usize> {
-        // To be changed to a binary search later depending on how many total connections there are
-        for i in self.last_valid_index..self.connections.len() {
-            if self.connections[i].matches_id(cid) {
-                return Some(i);
-            }
-        }
-        #[allow(clippy::manual_find)]
-        for i in 0..self.last_valid_index {
-            if self.connections[i].matches_id(cid) {
-                return Some(i);
-            }
-        }
-        None
+        self.connections.find_id(cid)
     }
 
     fn send(&mut self, verified_index: usize) -> Result<Option<CloseInfo>, Error> {
@@ -521,9 +1065,11 @@ impl Endpoint {
                 }
                 Ok(SendResult::Done) => {
                     //return Ok((immediate_sends, delayed_sends));
+                    self.udp.flush_send_data();
                     return Ok(None);
                 }
                 Ok(SendResult::CloseInfo(close_info)) => {
+                    self.udp.flush_send_data();
                     return Ok(Some(close_info));
                 }
                 Err(_) => {
@@ -533,34 +1079,76 @@ impl Endpoint {
         }
     }
 
+    // Opens the key log file (in append mode) from the config path or the SSLKEYLOGFILE environment variable
+    fn open_keylog_file(config: &Config) -> Option<std::fs::File> {
+        let keylog_path = match &config.keylog_path {
+            Some(path) => path.clone(),
+            None => PathBuf::from(std::env::var_os("SSLKEYLOGFILE")?),
+        };
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(keylog_path)
+            .ok()
+    }
+
+    // Every connection gets its own handle to the same appending key log file
+    fn create_keylog_writer(keylog_file_opt: &Option<std::fs::File>) -> Option<Box<std::fs::File>> {
+        if let Some(keylog_file) = keylog_file_opt {
+            match keylog_file.try_clone() {
+                Ok(file) => Some(Box::new(file)),
+                Err(_) => None,
+            }
+        } else {
+            None
+        }
+    }
+
+    // Creates the qlog file for a connection if enabled in the config
+    // (Takes the fields instead of self since a received datagram can still be borrowed from the socket)
+    fn create_qlog_writer(config: &Config, connection_id: u64) -> Option<Box<std::fs::File>> {
+        if let Some(qlog_dir) = &config.qlog_dir {
+            let mut qlog_path = qlog_dir.clone();
+            qlog_path.push(format!("{}.sqlog", connection_id));
+            match std::fs::File::create(qlog_path) {
+                Ok(file) => Some(Box::new(file)),
+                Err(_) => None,
+            }
+        } else {
+            None
+        }
+    }
+
     // This is different than closing the connection
     fn remove_connection(&mut self, verified_index: usize) {
-        // Pretty confident that this is truly all there really is to it:
         self.connections.remove(verified_index);
+        // The slot can be reused by the next new connection
+        if self.last_recv_index == Some(verified_index) {
+            self.last_recv_index = None;
+        }
+        if let Some((_, stream_process_ind)) = self.stream_process_index {
+            if stream_process_ind == verified_index {
+                self.stream_process_index = None;
+            }
+        }
     }
 
     /// Add a connection for a Client Endpoint
     ///
-    /// Must be used on a Client and not a Server otherwise an error will be thrown
+    /// Must be used on a Client and not a Server otherwise an error will be thrown.
+    /// A cached session for the server name is resumed and when early data is enabled the returned
+    /// connection can be sent main stream data right away (see is_in_early_data).
     pub fn add_client_connection(
         &mut self,
         peer_addr: SocketAddr,
         server_name: &str,
-    ) -> Result<(), Error> {
+    ) -> Result<ConnectionId, Error> {
         if !self.is_server {
             let mut scid_data = Connection::get_empty_cid();
             if self.rand.fill(&mut scid_data).is_err() {
                 return Err(Error::Randomness);
             }
 
-            let writer_opt = match self.connections.len() {
-                0 => match std::fs::File::create("clientKey.log") {
-                    Ok(file) => Some(Box::new(file)),
-                    Err(_) => None,
-                },
-                _ => None,
-            };
-
             match Connection::new(
                 self.next_connection_id,
                 peer_addr,
@@ -568,16 +1156,27 @@ impl Endpoint {
                 self.local_addr,
                 &scid_data,
                 &mut self.connection_config,
-                writer_opt,
+                Endpoint::create_keylog_writer(&self.keylog_file),
+                Endpoint::create_qlog_writer(&self.config, self.next_connection_id),
+                None,
+                RecvBudget::new(
+                    self.config.admission.max_recv_bytes_per_sec,
+                    self.config.admission.max_recv_packets_per_sec,
+                ),
             ) {
-                Ok(conn_mgr) => {
+                Ok(mut conn_mgr) => {
+                    if let Some(session) = self.session_cache.get(server_name) {
+                        // A rejected session just means a full handshake
+                        let _ = conn_mgr.set_session(session);
+                    }
+
+                    let cid = self.next_connection_id;
                     self.next_connection_id += 1;
-                    self.connections.push(conn_mgr);
-                    let verified_index = self.connections.len() - 1;
+                    let verified_index = self.connections.insert(conn_mgr, None);
                     if self.send(verified_index)?.is_some() {
                         Err(Error::UnexpectedClose(0))
                     } else {
-                        Ok(())
+                        Ok(cid)
                     }
                 }
                 Err(_) => Err(Error::ConnectionCreation),
@@ -606,7 +1205,46 @@ impl Endpoint {
     /// Get the number of connections that the Endpoint is managing
     #[inline]
     pub fn get_num_connections(&self) -> usize {
-        self.connections.len()
+        self.connections.num_connections()
+    }
+
+    /// Get a waker that can wake up this Endpoint from another thread
+    ///
+    /// Useful for having the application react to input from other threads (like a push-to-talk key)
+    /// right away instead of checking for it on every tick.
+    pub fn get_waker(&mut self) -> Waker {
+        Waker {
+            woken: self.woken.clone(),
+            transport_waker_opt: self.udp.create_waker(),
+        }
+    }
+
+    /// Sleep the thread until a Waker wakes up the Endpoint or the timeout duration has passed
+    ///
+    /// Meant for an Endpoint without any connections that waits on the application outside of the
+    /// EndpointHandler event loop (like a client waiting to be told which server to connect to).
+    /// Anything received while there are no connections is dropped and the sleep ends early
+    /// when a connection receives something.
+    /// Returns true if the Endpoint was woken up.
+    pub fn sleep_till_woken(&mut self, timeout_duration: Duration) -> bool {
+        let deadline = Instant::now() + timeout_duration;
+        loop {
+            if self.woken.swap(false, Ordering::AcqRel) {
+                return true;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            if self.udp.sleep_till_recv_data(deadline - now) {
+                if self.connections.num_connections() > 0 {
+                    return false; // The event loop needs to handle it
+                }
+                while self.udp.get_next_recv_data().is_ok() {
+                    self.udp.done_with_recv_data();
+                }
+            }
+        }
     }
 
     /// Update the keep alive duration time
@@ -621,7 +1259,10 @@ impl Endpoint {
         let mut num_pings = 0;
         if let Some(duration) = self.config.keep_alive_timeout {
             let before_instant = Instant::now() - duration;
-            for verified_index in 0..self.connections.len() {
+            for verified_index in 0..self.connections.slot_count() {
+                if !self.connections.contains(verified_index) {
+                    continue;
+                }
                 match self.connections[verified_index].send_ping_if_before_instant(before_instant) {
                     Ok(false) => {}
                     Ok(true) => {
@@ -639,6 +1280,29 @@ impl Endpoint {
         Ok(num_pings)
     }
 
+    fn expire_rt_sends(&mut self) -> Result<(), Error> {
+        if self.config.rt_max_age.is_some() {
+            let now = Instant::now();
+            for verified_index in 0..self.connections.slot_count() {
+                if !self.connections.contains(verified_index) {
+                    continue;
+                }
+                match self.connections[verified_index].rt_stream_expire(now) {
+                    Ok(false) => {}
+                    Ok(true) => {
+                        if self.send(verified_index)?.is_some() {
+                            return Err(Error::UnexpectedClose(12));
+                        }
+                    }
+                    Err(_) => {
+                        return Err(Error::StreamSend);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub(super) fn get_next_event(
         &mut self,
         next_tick_instant: Instant,
@@ -647,6 +1311,7 @@ impl Endpoint {
             next_tick_instant
         } else {
             self.keep_alive()?;
+            self.expire_rt_sends()?;
             return Ok(NextEvent::Tick);
         };
         let mut conn_timeout_opt: Option<usize> = None;
@@ -656,6 +1321,7 @@ impl Endpoint {
                 if send_count > 0 && next_tick_instant <= Instant::now() {
                     self.stats.delayed_sends += send_count;
                     self.keep_alive()?;
+                    self.expire_rt_sends()?;
                     return Ok(NextEvent::Tick);
                 }
             }
@@ -664,12 +1330,14 @@ impl Endpoint {
             }
         }
 
-        for verified_index in 0..self.connections.len() {
+        for verified_index in 0..self.connections.slot_count() {
+            if !self.connections.contains(verified_index) {
+                continue;
+            }
             match self.connections[verified_index].handle_possible_timeout() {
                 None => {
                     if let Some(close_info) = self.send(verified_index)? {
                         let connection_id = close_info.id;
-                        self.last_valid_index = verified_index;
                         let end_reason = ConnectionEndReason::from_close_info(&close_info);
                         if close_info.is_closed {
                             self.remove_connection(verified_index);
@@ -684,7 +1352,6 @@ impl Endpoint {
                                     if self.connections[vi].handle_possible_timeout().is_none() {
                                         if let Some(close_info) = self.send(vi)? {
                                             let connection_id = close_info.id;
-                                            self.last_valid_index = vi;
                                             let end_reason =
                                                 ConnectionEndReason::from_close_info(&close_info);
                                             if close_info.is_closed {
@@ -703,6 +1370,7 @@ impl Endpoint {
                                     return Ok(NextEvent::AlreadyHandled);
                                 } else {
                                     self.keep_alive()?;
+                                    self.expire_rt_sends()?;
                                     return Ok(NextEvent::Tick);
                                 }
                             }
@@ -732,11 +1400,23 @@ impl Endpoint {
             }
         }
 
+        // Connections that resumed reading get their queued stream data processed before sleeping
+        if !self.resumed_recv.is_empty() {
+            return Ok(NextEvent::ReceivedData);
+        }
+
+        // A wake from another thread that came in while this thread was busy gets handled before sleeping
+        if self.woken.swap(false, Ordering::AcqRel) {
+            return Ok(NextEvent::Woken);
+        }
+
         let earlier = Instant::now();
         let sleep_duration = next_instant.duration_since(earlier);
         if self.udp.sleep_till_recv_data(sleep_duration) {
             //self.stats.sleep_time += Instant::now() - earlier;
             Ok(NextEvent::ReceivedData)
+        } else if self.woken.swap(false, Ordering::AcqRel) {
+            Ok(NextEvent::Woken)
         } else if send_check_timeout {
             //self.stats.sleep_time += Instant::now() - earlier;
             match self.udp.send_check() {
@@ -748,7 +1428,6 @@ impl Endpoint {
             if self.connections[vi].handle_possible_timeout().is_none() {
                 if let Some(close_info) = self.send(vi)? {
                     let connection_id = close_info.id;
-                    self.last_valid_index = vi;
                     let end_reason = ConnectionEndReason::from_close_info(&close_info);
                     if close_info.is_closed {
                         self.remove_connection(vi);
@@ -765,6 +1444,7 @@ impl Endpoint {
         } else {
             //self.stats.sleep_time += Instant::now() - earlier;
             self.keep_alive()?;
+            self.expire_rt_sends()?;
             Ok(NextEvent::Tick)
         }
     }
@@ -774,7 +1454,6 @@ impl Endpoint {
         connection_id: u64,
         verified_index: usize,
     ) -> Result<RecvEvent, Error> {
-        self.last_valid_index = verified_index;
         match self.connections[verified_index].stream_process() {
             Ok(StreamResult::NoMore) => {
                 self.stream_process_index = None;
@@ -809,11 +1488,15 @@ impl Endpoint {
                 //     Err(Error::UnexpectedClose)
                 // }
             }
-            Ok(StreamResult::BkgdStreamReadable((data_vec, len))) => {
+            Ok(StreamResult::RealtimeDatagramReadable(data_vec)) => Ok(
+                RecvEvent::RealtimeDatagramReceived((connection_id, data_vec)),
+            ),
+            Ok(StreamResult::BkgdStreamReadable((bkgd_id, data_vec, len))) => {
                 // if self.send(verified_index)?.is_none() {
                 Ok(RecvEvent::BackgroundStreamReceived((
                     connection_id,
                     verified_index,
+                    bkgd_id,
                     data_vec,
                     len,
                 )))
@@ -821,6 +1504,12 @@ impl Endpoint {
                 //     Err(Error::UnexpectedClose)
                 // }
             }
+            Ok(StreamResult::BkgdStreamRecvEnded(bkgd_id)) => Ok(
+                RecvEvent::BackgroundStreamRecvEnded((connection_id, bkgd_id)),
+            ),
+            Ok(StreamResult::StreamWritable(class)) => {
+                Ok(RecvEvent::StreamWritable((connection_id, class)))
+            }
             Ok(StreamResult::Nothing) => Ok(RecvEvent::NoUpdate),
             Ok(StreamResult::MainStreamFinished) => {
                 if let Some(close_info) =
@@ -859,6 +1548,18 @@ impl Endpoint {
     }
 
     pub(super) fn recv(&mut self) -> Result<RecvEvent, Error> {
+        if self.stream_process_index.is_none() {
+            // The connection might have ended since it resumed reading
+            while let Some((connection_id, verified_index)) = self.resumed_recv.pop() {
+                if self.connections.contains(verified_index)
+                    && self.connections[verified_index].get_id() == connection_id
+                {
+                    self.stream_process_index = Some((connection_id, verified_index));
+                    break;
+                }
+            }
+        }
+
         // Gotta Process Coallesced Stream Packets Here!
         if let Some((connection_id, verified_index)) = self.stream_process_index {
             let evt = self.stream_process(connection_id, verified_index)?;
@@ -872,42 +1573,104 @@ impl Endpoint {
             Ok((recv_data, from_addr)) => {
                 // Only bother to look at a datagram that is less than or equal to the target
                 if recv_data.len() <= self.max_payload_size {
-                    if let Some((dcid, new_conn_possibility)) =
+                    if let Some((dcid, initial_info_opt)) =
                         Connection::recv_header_analyze(recv_data, self.is_server)
                     {
-                        let mut verified_index_opt = match self.last_recv_index {
-                            Some(ind) => {
-                                if self.connections[ind].matches_dcid(&dcid) {
-                                    Some(ind)
-                                } else {
-                                    send_ind_opt = Some(ind);
-                                    self.last_recv_index = self
-                                        .connections
-                                        .iter()
-                                        .position(|conn| conn.matches_dcid(&dcid));
-                                    self.last_recv_index
+                        let mut verified_index_opt = self.connections.find_dcid(&dcid);
+                        if let Some(ind) = self.last_recv_index {
+                            // Sends for the last connection that received are done once its datagrams stop
+                            if verified_index_opt != Some(ind) {
+                                send_ind_opt = Some(ind);
+                            }
+                        }
+                        self.last_recv_index = verified_index_opt;
+
+                        let mut refuse_new = false;
+                        if let (None, Some(initial_info)) = (verified_index_opt, initial_info_opt) {
+                            let mut odcid_opt = None;
+                            if self.config.stateless_retry {
+                                if initial_info.token.is_empty() {
+                                    // Validate the client address with a Retry before any connection state is created
+                                    let token =
+                                        mint_retry_token(&self.conn_id_seed_key, from_addr, &dcid);
+                                    let tag = ring::hmac::sign(&self.conn_id_seed_key, &dcid);
+                                    self.udp.done_with_recv_data();
+
+                                    let packet_data = self.udp.get_next_send_data();
+                                    if let Ok(packet_len) = Connection::create_retry_packet(
+                                        &initial_info,
+                                        &dcid,
+                                        tag.as_ref(),
+                                        &token,
+                                        packet_data,
+                                    ) {
+                                        if self
+                                            .udp
+                                            .done_with_send_data(
+                                                from_addr,
+                                                packet_len,
+                                                Instant::now(),
+                                            )
+                                            .is_err()
+                                        {
+                                            return Err(Error::SocketSend);
+                                        }
+                                    }
+                                    return self.finish_recv(send_ind_opt, Ok(RecvEvent::NoUpdate));
+                                }
+
+                                match validate_retry_token(
+                                    &self.conn_id_seed_key,
+                                    from_addr,
+                                    &initial_info.token,
+                                ) {
+                                    Some(odcid) => odcid_opt = Some(odcid.to_vec()),
+                                    None => {
+                                        // Invalid or expired token so drop the packet
+                                        self.udp.done_with_recv_data();
+                                        return self
+                                            .finish_recv(send_ind_opt, Ok(RecvEvent::NoUpdate));
+                                    }
                                 }
                             }
-                            None => {
-                                self.last_recv_index = self
-                                    .connections
-                                    .iter()
-                                    .position(|conn| conn.matches_dcid(&dcid));
-                                self.last_recv_index
+
+                            // Initials over the admission limits get dropped without creating any connection state
+                            let handshakes_allowed = match self.config.admission.max_handshakes {
+                                Some(max_handshakes) => {
+                                    self.connections.num_handshaking() < max_handshakes
+                                }
+                                None => true,
+                            };
+                            let source_allowed = match &mut self.source_limiter_opt {
+                                Some(source_limiter) => {
+                                    handshakes_allowed
+                                        && source_limiter.allow(from_addr.ip(), Instant::now())
+                                }
+                                None => true,
+                            };
+                            if !(handshakes_allowed && source_allowed) {
+                                self.stats.initials_dropped += 1;
+                                self.udp.done_with_recv_data();
+                                return self.finish_recv(send_ind_opt, Ok(RecvEvent::NoUpdate));
                             }
-                        };
+                            refuse_new = match self.config.admission.max_connections {
+                                Some(max_connections) => {
+                                    self.connections.num_connections() >= max_connections
+                                }
+                                None => false,
+                            };
 
-                        if verified_index_opt.is_none() && new_conn_possibility && self.is_server {
-                            let tag = ring::hmac::sign(&self.conn_id_seed_key, &dcid);
+                            // The connection ID is derived from the original destination connection ID
+                            // so it matches the one given to the client in the Retry
+                            let tag = match &odcid_opt {
+                                Some(odcid) => ring::hmac::sign(&self.conn_id_seed_key, odcid),
+                                None => ring::hmac::sign(&self.conn_id_seed_key, &dcid),
+                            };
                             let scid_data = tag.as_ref();
 
-                            let writer_opt = match self.connections.len() {
-                                0 => match std::fs::File::create("key.log") {
-                                    Ok(file) => Some(Box::new(file)),
-                                    Err(_) => None,
-                                },
-                                _ => None,
-                            };
+                            let writer_opt = Endpoint::create_keylog_writer(&self.keylog_file);
+                            let qlog_writer_opt =
+                                Endpoint::create_qlog_writer(&self.config, self.next_connection_id);
 
                             match Connection::new(
                                 self.next_connection_id,
@@ -917,84 +1680,177 @@ impl Endpoint {
                                 scid_data,
                                 &mut self.connection_config,
                                 writer_opt,
+                                qlog_writer_opt,
+                                odcid_opt.as_deref(),
+                                RecvBudget::new(
+                                    self.config.admission.max_recv_bytes_per_sec,
+                                    self.config.admission.max_recv_packets_per_sec,
+                                ),
                             ) {
                                 Ok(conn_mgr) => {
                                     self.next_connection_id += 1;
-                                    verified_index_opt = Some(self.connections.len());
-                                    self.connections.push(conn_mgr);
+                                    verified_index_opt =
+                                        Some(self.connections.insert(conn_mgr, Some(&dcid[..])));
                                 }
                                 Err(_) => {
                                     self.udp.done_with_recv_data();
-                                    return Ok(RecvEvent::NoUpdate);
+                                    return self.finish_recv(send_ind_opt, Ok(RecvEvent::NoUpdate));
                                 }
                             }
                         }
 
                         if let Some(verified_index) = verified_index_opt {
-                            match self.connections[verified_index].recv_data(recv_data, from_addr) {
-                                Ok(RecvResult::StreamProcess(conn_id)) => {
-                                    self.stream_process_index = Some((conn_id, verified_index));
-                                    self.stream_process(conn_id, verified_index)
-                                }
-                                Ok(RecvResult::CloseInfo(close_info)) => {
-                                    let connection_id = close_info.id;
-                                    self.last_valid_index = verified_index;
-                                    let end_reason =
-                                        ConnectionEndReason::from_close_info(&close_info);
-                                    if close_info.is_closed {
-                                        self.remove_connection(verified_index);
-                                        Ok(RecvEvent::ConnectionEnded((connection_id, end_reason)))
-                                    } else {
-                                        Ok(RecvEvent::ConnectionEnding((connection_id, end_reason)))
+                            let now = Instant::now();
+                            if self.connections[verified_index].recv_budget_exceeded(now) {
+                                // Dropped unread since it might not even be from the peer
+                                self.stats.budget_drops += 1;
+                                Ok(RecvEvent::NoUpdate)
+                            } else {
+                                let recv_totals = self.connections[verified_index].recv_totals();
+                                let recv_result = self.connections[verified_index]
+                                    .recv_data(recv_data, from_addr);
+                                let within_budget = self.connections[verified_index]
+                                    .spend_recv_budget(recv_totals, now);
+                                match recv_result {
+                                    // The first Initial was processed so the close can reach the client
+                                    Ok(RecvResult::Nothing) if refuse_new => {
+                                        self.stats.connections_refused += 1;
+                                        self.admission_close(
+                                            verified_index,
+                                            EndpointCloseReason::ConnectionRefused,
+                                        )
                                     }
-                                }
-                                Ok(RecvResult::Established(conn_id)) => {
-                                    if self.send(verified_index)?.is_none() {
-                                        // let mut main_recv_data_old =
-                                        //     Vec::with_capacity(self.config.initial_main_recv_size);
-                                        // main_recv_data_old
-                                        //     .resize(self.config.initial_main_recv_size, 0);
-                                        let main_recv_data =
-                                            vec![0; self.config.initial_main_recv_size]; // Faster according to clippy and github issue discussion but unsure why...?
-
-                                        let rt_recv_data =
-                                            vec![0; self.config.initial_rt_recv_size];
-
-                                        let background_recv_data =
-                                            vec![0; self.config.initial_background_recv_size];
-
-                                        if self.connections[verified_index]
-                                            .finish_establishment(
-                                                main_recv_data,
-                                                self.config.main_recv_first_bytes,
-                                                rt_recv_data,
-                                                self.config.rt_recv_first_bytes,
-                                                background_recv_data,
-                                                self.config.background_recv_first_bytes,
-                                            )
-                                            .is_ok()
+                                    // Only data the peer authenticated goes over the budget
+                                    Ok(RecvResult::Nothing)
+                                    | Ok(RecvResult::StreamProcess(_))
+                                    | Ok(RecvResult::Established(_))
+                                        if !within_budget =>
+                                    {
+                                        self.admission_close(
+                                            verified_index,
+                                            EndpointCloseReason::RecvBudgetExceeded,
+                                        )
+                                    }
+                                    Ok(RecvResult::StreamProcess(conn_id)) => {
+                                        if !self.is_server {
+                                            self.cache_session(verified_index);
+                                        }
+                                        self.update_scids(verified_index);
+                                        self.stream_process_index = Some((conn_id, verified_index));
+                                        match self.connections[verified_index].take_migrated_addr()
                                         {
-                                            self.last_valid_index = verified_index;
-                                            Ok(RecvEvent::EstablishedOnce(conn_id))
+                                            // Streams get processed on the next recv call
+                                            Some(migrated) => {
+                                                Ok(RecvEvent::ConnectionMigrated(migrated))
+                                            }
+                                            None => self.stream_process(conn_id, verified_index),
+                                        }
+                                    }
+                                    Ok(RecvResult::CloseInfo(close_info)) => {
+                                        let connection_id = close_info.id;
+                                        let end_reason =
+                                            ConnectionEndReason::from_close_info(&close_info);
+                                        if close_info.is_closed {
+                                            self.remove_connection(verified_index);
+                                            Ok(RecvEvent::ConnectionEnded((
+                                                connection_id,
+                                                end_reason,
+                                            )))
                                         } else {
-                                            Err(Error::StreamCreation)
+                                            Ok(RecvEvent::ConnectionEnding((
+                                                connection_id,
+                                                end_reason,
+                                            )))
                                         }
-                                    } else {
-                                        Err(Error::UnexpectedClose(3))
                                     }
-                                }
-                                Ok(RecvResult::Nothing) => {
-                                    if self.send(verified_index)?.is_none() {
+                                    Ok(RecvResult::Established(conn_id)) => {
+                                        if !self.verify_peer_cert(verified_index) {
+                                            self.untrusted_close(verified_index)
+                                        } else if self.send(verified_index)?.is_none() {
+                                            // let mut main_recv_data_old =
+                                            //     Vec::with_capacity(self.config.initial_main_recv_size);
+                                            // main_recv_data_old
+                                            //     .resize(self.config.initial_main_recv_size, 0);
+                                            let main_recv_data =
+                                                vec![0; self.config.initial_main_recv_size]; // Faster according to clippy and github issue discussion but unsure why...?
+
+                                            let rt_recv_data =
+                                                vec![0; self.config.initial_rt_recv_size];
+
+                                            let background_recv_data =
+                                                vec![0; self.config.initial_background_recv_size];
+
+                                            if self.connections[verified_index]
+                                                .finish_establishment(
+                                                    main_recv_data,
+                                                    self.config.main_recv_first_bytes,
+                                                    rt_recv_data,
+                                                    self.config.rt_recv_first_bytes,
+                                                    background_recv_data,
+                                                    self.config.background_recv_first_bytes,
+                                                    SendSchedule {
+                                                        main: self.config.main_stream_priority,
+                                                        rt: self.config.rt_stream_priority,
+                                                        bkgd: self
+                                                            .config
+                                                            .background_stream_priority,
+                                                        bkgd_share_opt: self
+                                                            .config
+                                                            .background_bandwidth_share
+                                                            .map(|share| share.clamp(1, 100)),
+                                                        rt_max_age_opt: self.config.rt_max_age,
+                                                        send_low_water: self
+                                                            .config
+                                                            .send_queue_low_water,
+                                                    },
+                                                )
+                                                .is_ok()
+                                            {
+                                                self.connections.finish_handshake(verified_index);
+                                                self.update_scids(verified_index);
+                                                if self.is_server {
+                                                    // Process any early data right after connection_started
+                                                    if self.config.early_data {
+                                                        self.stream_process_index =
+                                                            Some((conn_id, verified_index));
+                                                    }
+                                                } else {
+                                                    self.cache_session(verified_index);
+                                                }
+                                                let peer_identity_opt =
+                                                    self.verified_peer_identity(verified_index);
+                                                Ok(RecvEvent::EstablishedOnce((
+                                                    conn_id,
+                                                    peer_identity_opt,
+                                                )))
+                                            } else {
+                                                Err(Error::StreamCreation)
+                                            }
+                                        } else {
+                                            Err(Error::UnexpectedClose(3))
+                                        }
+                                    }
+                                    Ok(RecvResult::Nothing) => {
+                                        self.update_scids(verified_index);
+                                        if self.send(verified_index)?.is_none() {
+                                            match self.connections[verified_index]
+                                                .take_migrated_addr()
+                                            {
+                                                Some(migrated) => {
+                                                    Ok(RecvEvent::ConnectionMigrated(migrated))
+                                                }
+                                                None => Ok(RecvEvent::NoUpdate),
+                                            }
+                                        } else {
+                                            Err(Error::UnexpectedClose(4))
+                                        }
+                                    }
+                                    Ok(RecvResult::CloseInitiated) => {
+                                        // This is an unexpected spot but allowable
                                         Ok(RecvEvent::NoUpdate)
-                                    } else {
-                                        Err(Error::UnexpectedClose(4))
                                     }
+                                    Err(e) => Err(Error::ConnectionRecv(e)),
                                 }
-                                Ok(RecvResult::CloseInitiated) => {
-                                    // This is an unexpected spot but allowable
-                                    Ok(RecvEvent::NoUpdate)
-                                }
-                                Err(e) => Err(Error::ConnectionRecv(e)),
                             }
                         } else {
                             Ok(RecvEvent::NoUpdate)
@@ -1013,7 +1869,6 @@ impl Endpoint {
                         None => Ok(RecvEvent::DoneReceiving),
                         Some(close_info) => {
                             let connection_id = close_info.id;
-                            self.last_valid_index = last_recv_ind;
                             let end_reason = ConnectionEndReason::from_close_info(&close_info);
                             if close_info.is_closed {
                                 self.remove_connection(last_recv_ind);
@@ -1032,6 +1887,16 @@ impl Endpoint {
         };
         self.udp.done_with_recv_data();
 
+        self.finish_recv(send_ind_opt, res)
+    }
+
+    // Flushes the sends of the connection that previously received (if it changed) before the result is given back
+    // Every datagram that overwrites the last_recv_index has to go through here
+    fn finish_recv(
+        &mut self,
+        send_ind_opt: Option<usize>,
+        res: Result<RecvEvent, Error>,
+    ) -> Result<RecvEvent, Error> {
         if let Some(send_ind) = send_ind_opt {
             if self.send(send_ind)?.is_none() {
                 res
@@ -1043,6 +1908,125 @@ impl Endpoint {
         }
     }
 
+    // Gives the peer a new connection ID (derived from the seed key) to use and routes it to the connection
+    fn issue_scid(
+        &mut self,
+        verified_index: usize,
+        retire_oldest: bool,
+    ) -> Result<(), connection::Error> {
+        let tag = derive_connection_id(
+            &self.conn_id_seed_key,
+            self.connections[verified_index].current_scid(),
+            self.connections[verified_index].scids_issued(),
+        );
+        let mut scid_data = Connection::get_empty_cid();
+        let scid_len = scid_data.len();
+        scid_data.copy_from_slice(&tag.as_ref()[..scid_len]);
+        let reset_token = derive_reset_token(&self.conn_id_seed_key, &scid_data);
+
+        self.connections[verified_index].add_scid(&scid_data, reset_token, retire_oldest)?;
+        self.connections.add_dcid(verified_index, &scid_data);
+        Ok(())
+    }
+
+    // Stops routing the connection IDs that the peer retired and keeps it supplied with spare ones
+    // so it can migrate (or get NAT rebound) to a new path without reusing a connection ID
+    fn update_scids(&mut self, verified_index: usize) {
+        while let Some(retired_scid) = self.connections[verified_index].next_retired_scid() {
+            self.connections.remove_dcid(verified_index, &retired_scid);
+        }
+        if self.connections[verified_index].is_established_once() {
+            while self.connections[verified_index].scids_left() > 0 {
+                // A peer that does not accept more connection IDs just won't be able to migrate
+                if self.issue_scid(verified_index, false).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    // Remembers the session of a client connection (once available) for future resumptions
+    fn cache_session(&mut self, verified_index: usize) {
+        if let Some(session) = self.connections[verified_index].take_new_session() {
+            if let Some(server_name) = self.connections[verified_index].get_server_name() {
+                self.session_cache.insert(server_name, session);
+            }
+        }
+    }
+
+    // Checks the client certificate with the verification policies that the TLS handshake cannot do itself
+    // (Server certificates are fully verified during the handshake)
+    fn verify_peer_cert(&self, verified_index: usize) -> bool {
+        if let Some(client_verification) = &self.config.client_verification {
+            tls::verify_client_cert(
+                client_verification,
+                self.connections[verified_index].get_peer_cert(),
+            )
+        } else {
+            true
+        }
+    }
+
+    // The certificate identity of the peer but only when a verification policy checked it
+    // (A server without a client verification policy never checks client certificates)
+    fn verified_peer_identity(&self, verified_index: usize) -> Option<PeerIdentity> {
+        if self.is_server && self.config.client_verification.is_none() {
+            None
+        } else {
+            self.connections[verified_index]
+                .get_peer_cert()
+                .map(PeerIdentity::from_der)
+        }
+    }
+
+    fn untrusted_close(&mut self, verified_index: usize) -> Result<RecvEvent, Error> {
+        if let Some(close_info) =
+            self.connection_close(verified_index, EndpointCloseReason::UntrustedCertificate)?
+        {
+            let connection_id = close_info.id;
+            let end_reason = ConnectionEndReason::from_close_info(&close_info);
+            if close_info.is_closed {
+                self.remove_connection(verified_index);
+                Ok(RecvEvent::ConnectionEnded((connection_id, end_reason)))
+            } else {
+                Ok(RecvEvent::ConnectionEnding((connection_id, end_reason)))
+            }
+        } else {
+            Ok(RecvEvent::NoUpdate)
+        }
+    }
+
+    // Closes a connection that went over an admission limit or its inbound budget
+    fn admission_close(
+        &mut self,
+        verified_index: usize,
+        reason: EndpointCloseReason,
+    ) -> Result<RecvEvent, Error> {
+        let is_budget = matches!(reason, EndpointCloseReason::RecvBudgetExceeded);
+        if self.connections[verified_index]
+            .close(reason as u64, b"admission")
+            .is_err()
+        {
+            return Ok(RecvEvent::NoUpdate); // Already closing
+        }
+        if is_budget {
+            self.stats.budget_closes += 1;
+        }
+
+        if let Some(close_info) = self.send(verified_index)? {
+            let connection_id = close_info.id;
+            let end_reason = ConnectionEndReason::from_close_info(&close_info);
+            if close_info.is_closed {
+                self.remove_connection(verified_index);
+                Ok(RecvEvent::ConnectionEnded((connection_id, end_reason)))
+            } else {
+                Ok(RecvEvent::ConnectionEnding((connection_id, end_reason)))
+            }
+        } else {
+            Ok(RecvEvent::NoUpdate)
+        }
+    }
+
     // Close a connection with a given error code value
     fn connection_close(
         &mut self,
@@ -1078,6 +2062,28 @@ impl Endpoint {
         }
     }
 
+    /// Give the peer of a connection a new connection ID and ask it to retire the oldest one (when it has no room left)
+    ///
+    /// The connection IDs are derived from a key of the Endpoint so they can't be linked to each other,
+    /// which makes the connection harder to follow (especially across a migration) by anyone watching the network.
+    /// Retired connection IDs are replaced with new ones automatically.
+    pub fn rotate_connection_id(&mut self, cid: &ConnectionId) -> Result<(), Error> {
+        if let Some(verified_index) = self.find_connection_from_cid(*cid) {
+            match self.issue_scid(verified_index, true) {
+                Ok(_) => {
+                    if self.send(verified_index)?.is_some() {
+                        Err(Error::UnexpectedClose(14))
+                    } else {
+                        Ok(())
+                    }
+                }
+                Err(e) => Err(Error::ConnectionIdIssue(e)),
+            }
+        } else {
+            Err(Error::ConnectionNotFound)
+        }
+    }
+
     /// Get the socket address for a connection
     ///
     /// This address could change if a backend connection migration happens (not currently implemented / expected)
@@ -1089,6 +2095,28 @@ impl Endpoint {
         }
     }
 
+    /// Get the transport stats for a connection
+    ///
+    /// These stats are a snapshot so this should be called periodically (not every tick) to monitor a connection
+    pub fn connection_stats(&self, cid: &ConnectionId) -> Result<ConnectionStats, Error> {
+        if let Some(verified_index) = self.find_connection_from_cid(*cid) {
+            Ok(self.connections[verified_index].get_stats())
+        } else {
+            Err(Error::ConnectionNotFound)
+        }
+    }
+
+    /// Check if a client connection is currently sending 0-RTT early data
+    ///
+    /// Main stream data sent while this is true goes out before the handshake finishes.
+    pub fn is_in_early_data(&self, cid: &ConnectionId) -> Result<bool, Error> {
+        if let Some(verified_index) = self.find_connection_from_cid(*cid) {
+            Ok(self.connections[verified_index].is_in_early_data())
+        } else {
+            Err(Error::ConnectionNotFound)
+        }
+    }
+
     /// Send data over the main stream. This data is queued up if it cannot be sent immediately.
     ///
     /// The main stream is a reliable (ordered) stream that focuses on communicating
@@ -1116,6 +2144,18 @@ impl Endpoint {
         }
     }
 
+    /// Get the number of bytes in a stream class send queue that have not been handed off to the connection yet
+    ///
+    /// The connection only takes in about as much data as it is allowed to send (congestion and flow control)
+    /// so this shows how far behind the application is. For the real-time class only the current time segment is queued.
+    pub fn queued_bytes(&self, cid: &ConnectionId, class: StreamClass) -> Result<usize, Error> {
+        if let Some(verified_index) = self.find_connection_from_cid(*cid) {
+            Ok(self.connections[verified_index].queued_bytes(class))
+        } else {
+            Err(Error::ConnectionNotFound)
+        }
+    }
+
     pub(super) fn main_stream_read(
         &mut self,
         verified_index: usize,
@@ -1232,7 +2272,47 @@ impl Endpoint {
         }
     }
 
-    /// Send data over the background stream. This data is queued up if it cannot be sent immediately.
+    /// Send data as a single real-time datagram (QUIC DATAGRAM frame).
+    ///
+    /// Unlike the real-time stream, datagrams require no stream setup or flow control credit and are
+    /// never retransmitted, which makes them ideal for small time-sensitive packets like voice frames.
+    /// The send_data must fit within a single datagram (see rt_datagram_max_len) and the Endpoint Config
+    /// rt_datagram_queue_len must be greater than 0 on both endpoints.
+    ///
+    /// A reminder that the Endpoint connection will be taking ownership of the data so it can be sent out when possible
+    pub fn rt_datagram_send(
+        &mut self,
+        cid: &ConnectionId,
+        send_data: Vec<u8>,
+    ) -> Result<(), Error> {
+        if let Some(verified_index) = self.find_connection_from_cid(*cid) {
+            match self.connections[verified_index].rt_datagram_send(send_data) {
+                Ok(_) => {
+                    if self.send(verified_index)?.is_some() {
+                        Err(Error::UnexpectedClose(11))
+                    } else {
+                        Ok(())
+                    }
+                }
+                Err(e) => Err(Error::DatagramSend(e)),
+            }
+        } else {
+            Err(Error::ConnectionNotFound)
+        }
+    }
+
+    /// Get the maximum number of bytes that can currently be sent in a single real-time datagram.
+    ///
+    /// Returns None if the connection has not negotiated datagram support (yet).
+    pub fn rt_datagram_max_len(&self, cid: &ConnectionId) -> Result<Option<usize>, Error> {
+        if let Some(verified_index) = self.find_connection_from_cid(*cid) {
+            Ok(self.connections[verified_index].rt_datagram_max_len())
+        } else {
+            Err(Error::ConnectionNotFound)
+        }
+    }
+
+    /// Send data over the default background stream. This data is queued up if it cannot be sent immediately.
     ///
     /// The background stream is a reliable (ordered) stream that focuses on communicating
     /// large(ish) messages between the server and client such as a file transfer.
@@ -1243,8 +2323,41 @@ impl Endpoint {
         cid: &ConnectionId,
         send_data: Vec<u8>,
     ) -> Result<(), Error> {
+        self.background_stream_send_on(cid, DEFAULT_BACKGROUND_STREAM, send_data)
+    }
+
+    /// Open an additional background stream on a connection.
+    ///
+    /// Each background stream is delivered in order on its own, so a slow transfer on one
+    /// doesn't hold up the data of the others. The peer learns about the stream once data is sent on it.
+    ///
+    /// The peer limits how many can be open at once with its max_background_streams Config value.
+    pub fn open_background_stream(
+        &mut self,
+        cid: &ConnectionId,
+    ) -> Result<BackgroundStreamId, Error> {
         if let Some(verified_index) = self.find_connection_from_cid(*cid) {
-            match self.connections[verified_index].bkgd_stream_send(send_data) {
+            match self.connections[verified_index].open_bkgd_stream() {
+                Ok(bkgd_id) => Ok(bkgd_id),
+                Err(connection::Error::StreamLimit) => Err(Error::BackgroundStreamLimit),
+                Err(_) => Err(Error::StreamCreation),
+            }
+        } else {
+            Err(Error::ConnectionNotFound)
+        }
+    }
+
+    /// Send data over a specific background stream. This data is queued up if it cannot be sent immediately.
+    ///
+    /// A reminder that the Endpoint connection will be taking ownership of the data so it can be sent out when possible
+    pub fn background_stream_send_on(
+        &mut self,
+        cid: &ConnectionId,
+        bkgd_id: BackgroundStreamId,
+        send_data: Vec<u8>,
+    ) -> Result<(), Error> {
+        if let Some(verified_index) = self.find_connection_from_cid(*cid) {
+            match self.connections[verified_index].bkgd_stream_send(bkgd_id, send_data) {
                 Ok(_) => {
                     if self.send(verified_index)?.is_some() {
                         Err(Error::UnexpectedClose(10))
@@ -1252,6 +2365,38 @@ impl Endpoint {
                         Ok(())
                     }
                 }
+                Err(connection::Error::InvalidStreamState(_)) => {
+                    Err(Error::BackgroundStreamNotFound)
+                }
+                Err(_) => Err(Error::StreamSend),
+            }
+        } else {
+            Err(Error::ConnectionNotFound)
+        }
+    }
+
+    /// Finish sending on an additional background stream once all of its queued data has been sent.
+    ///
+    /// Each side finishes sending on its own, so a stream the peer finished (see the background_stream_recv_ended callback)
+    /// can still be sent on until this is called. The stream is removed once both sides have finished it.
+    /// The default background stream cannot be finished this way.
+    pub fn finish_background_stream(
+        &mut self,
+        cid: &ConnectionId,
+        bkgd_id: BackgroundStreamId,
+    ) -> Result<(), Error> {
+        if let Some(verified_index) = self.find_connection_from_cid(*cid) {
+            match self.connections[verified_index].finish_bkgd_stream(bkgd_id) {
+                Ok(_) => {
+                    if self.send(verified_index)?.is_some() {
+                        Err(Error::UnexpectedClose(13))
+                    } else {
+                        Ok(())
+                    }
+                }
+                Err(connection::Error::InvalidStreamState(_)) => {
+                    Err(Error::BackgroundStreamNotFound)
+                }
                 Err(_) => Err(Error::StreamSend),
             }
         } else {
@@ -1259,9 +2404,62 @@ impl Endpoint {
         }
     }
 
+    /// Abruptly stop an additional background stream in both directions.
+    ///
+    /// Any of its data that is still queued up is dropped and the peer gets told to give up on the stream,
+    /// so a partially sent message is never mistaken as complete. The default background stream cannot be stopped this way.
+    pub fn close_background_stream(
+        &mut self,
+        cid: &ConnectionId,
+        bkgd_id: BackgroundStreamId,
+    ) -> Result<(), Error> {
+        if let Some(verified_index) = self.find_connection_from_cid(*cid) {
+            match self.connections[verified_index].close_bkgd_stream(bkgd_id) {
+                Ok(_) => {
+                    if self.send(verified_index)?.is_some() {
+                        Err(Error::UnexpectedClose(15))
+                    } else {
+                        Ok(())
+                    }
+                }
+                Err(_) => Err(Error::BackgroundStreamNotFound),
+            }
+        } else {
+            Err(Error::ConnectionNotFound)
+        }
+    }
+
+    /// Stop reading the main and background streams of a connection.
+    ///
+    /// The received data stays queued up in the connection so once the stream flow control windows are used up
+    /// the peer has to stop sending. Real-time streams and datagrams are still read. Use resume_stream_recv to continue.
+    pub fn pause_stream_recv(&mut self, cid: &ConnectionId) -> Result<(), Error> {
+        if let Some(verified_index) = self.find_connection_from_cid(*cid) {
+            self.connections[verified_index].set_recv_paused(true);
+            Ok(())
+        } else {
+            Err(Error::ConnectionNotFound)
+        }
+    }
+
+    /// Continue reading the main and background streams of a connection after pause_stream_recv.
+    ///
+    /// The queued up data is handed to the EndpointEventCallbacks as soon as the event loop gets to it.
+    pub fn resume_stream_recv(&mut self, cid: &ConnectionId) -> Result<(), Error> {
+        if let Some(verified_index) = self.find_connection_from_cid(*cid) {
+            if self.connections[verified_index].set_recv_paused(false) {
+                self.resumed_recv.push((*cid, verified_index));
+            }
+            Ok(())
+        } else {
+            Err(Error::ConnectionNotFound)
+        }
+    }
+
     pub(super) fn background_stream_read(
         &mut self,
         verified_index: usize,
+        bkgd_id: BackgroundStreamId,
         data_vec: Vec<u8>,
         target_len_opt: Option<usize>,
     ) -> Result<ReadInfo, Error> {
@@ -1269,11 +2467,11 @@ impl Endpoint {
             if target_len == 0 {
                 target_len = self.config.initial_background_recv_size;
             }
-            match self.connections[verified_index].bkgd_stream_read(data_vec, target_len) {
+            match self.connections[verified_index].bkgd_stream_read(bkgd_id, data_vec, target_len) {
                 Ok(vec_data_opt) => {
                     // if self.send(verified_index)?.is_none() {
-                    if let Some(vec_data) = vec_data_opt {
-                        Ok(ReadInfo::ReadData((vec_data, target_len)))
+                    if let Some((vec_data, len)) = vec_data_opt {
+                        Ok(ReadInfo::ReadData((vec_data, len)))
                     } else {
                         Ok(ReadInfo::DoneReceiving)
                     }
@@ -1282,9 +2480,10 @@ impl Endpoint {
                     // }
                 }
                 Err(connection::Error::Done) => {
-                    if let Some(close_info) = self
-                        .connection_close(verified_index, EndpointCloseReason::MainStreamFinished)?
-                    {
+                    if let Some(close_info) = self.connection_close(
+                        verified_index,
+                        EndpointCloseReason::BackgroundStreamFinished,
+                    )? {
                         let end_reason = ConnectionEndReason::from_close_info(&close_info);
                         if close_info.is_closed {
                             self.remove_connection(verified_index);
@@ -1298,9 +2497,14 @@ impl Endpoint {
                 }
                 Err(_) => Err(Error::StreamSend),
             }
-        } else if let Some(close_info) =
-            self.connection_close(verified_index, EndpointCloseReason::MainStreamFinished)?
-        {
+        } else if bkgd_id != DEFAULT_BACKGROUND_STREAM {
+            // Only the additional background stream is given up on (it might have already been removed)
+            let _ = self.connections[verified_index].close_bkgd_stream(bkgd_id);
+            Ok(ReadInfo::DoneReceiving)
+        } else if let Some(close_info) = self.connection_close(
+            verified_index,
+            EndpointCloseReason::BackgroundStreamFinished,
+        )? {
             let end_reason = ConnectionEndReason::from_close_info(&close_info);
             if close_info.is_closed {
                 self.remove_connection(verified_index);
@@ -1328,3 +2532,71 @@ impl Endpoint {
         self.stats = Stats::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_key() -> ring::hmac::Key {
+        ring::hmac::Key::new(ring::hmac::HMAC_SHA256, &[7; 32])
+    }
+
+    fn peer_addr() -> SocketAddr {
+        "192.0.2.1:4433".parse().unwrap()
+    }
+
+    #[test]
+    fn retry_token_returns_the_original_dcid() {
+        let key = test_key();
+        let odcid = [3; 16];
+        let token = mint_retry_token(&key, peer_addr(), &odcid);
+        assert_eq!(
+            validate_retry_token(&key, peer_addr(), &token),
+            Some(&odcid[..])
+        );
+    }
+
+    #[test]
+    fn retry_token_is_tied_to_the_peer_and_key() {
+        let key = test_key();
+        let token = mint_retry_token(&key, peer_addr(), &[3; 16]);
+        let other_addr = "192.0.2.1:4434".parse().unwrap();
+        assert_eq!(validate_retry_token(&key, other_addr, &token), None);
+
+        let other_key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, &[8; 32]);
+        assert_eq!(validate_retry_token(&other_key, peer_addr(), &token), None);
+    }
+
+    #[test]
+    fn malformed_retry_tokens_are_rejected() {
+        let key = test_key();
+        let mut token = mint_retry_token(&key, peer_addr(), &[3; 16]);
+        assert_eq!(validate_retry_token(&key, peer_addr(), &[]), None);
+        assert_eq!(
+            validate_retry_token(&key, peer_addr(), &token[..1 + 16 + 8]),
+            None
+        );
+
+        let last = token.len() - 1;
+        token[last] ^= 0x01;
+        assert_eq!(validate_retry_token(&key, peer_addr(), &token), None);
+
+        // An odcid length that points past the end of the token
+        token[0] = 255;
+        assert_eq!(validate_retry_token(&key, peer_addr(), &token), None);
+    }
+
+    #[test]
+    fn expired_retry_token_is_rejected() {
+        let key = test_key();
+        let odcid = [3; 16];
+        let timestamp = (unix_time_secs() - RETRY_TOKEN_LIFETIME - 1).to_be_bytes();
+        let tag = ring::hmac::sign(&key, &retry_token_message(peer_addr(), &odcid, &timestamp));
+
+        let mut token = vec![odcid.len() as u8];
+        token.extend_from_slice(&odcid);
+        token.extend_from_slice(&timestamp);
+        token.extend_from_slice(tag.as_ref());
+        assert_eq!(validate_retry_token(&key, peer_addr(), &token), None);
+    }
+}
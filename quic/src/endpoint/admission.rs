@@ -0,0 +1,200 @@
+//Media Enhanced Swiftlet Quic Rust Library for Real-time Internet Communications
+//MIT License
+//Copyright (c) 2024 Jared Loewenthal
+//
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+//
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+//
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+// Connection Admission and Inbound Budget Management
+// Both work with fixed one second windows which is plenty precise for telling floods apart from real peers
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(1);
+// The source map gets cleared of expired windows once it grows to this many addresses
+const SOURCE_PRUNE_LEN: usize = 1024;
+
+struct SourceWindow {
+    start: Instant,
+    count: u32,
+}
+
+// Limits the number of new connections per second from each source IP address
+pub(super) struct SourceRateLimiter {
+    max_per_window: u32,
+    sources: HashMap<IpAddr, SourceWindow>,
+}
+
+impl SourceRateLimiter {
+    pub(super) fn new(max_per_window: u32) -> Self {
+        SourceRateLimiter {
+            max_per_window,
+            sources: HashMap::new(),
+        }
+    }
+
+    // Returns true if a new connection from the source address is allowed (and counts it)
+    pub(super) fn allow(&mut self, source: IpAddr, now: Instant) -> bool {
+        if self.sources.len() >= SOURCE_PRUNE_LEN {
+            self.sources
+                .retain(|_, window| now.duration_since(window.start) < WINDOW);
+        }
+
+        let window = self.sources.entry(source).or_insert(SourceWindow {
+            start: now,
+            count: 0,
+        });
+        if now.duration_since(window.start) >= WINDOW {
+            window.start = now;
+            window.count = 0;
+        }
+
+        if window.count < self.max_per_window {
+            window.count += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// Limits the bytes and packets a single connection can send to the endpoint per second
+// Only what quiche accepted gets charged so forged datagrams cannot use up the budget of a connection
+pub(super) struct RecvBudget {
+    max_bytes: Option<u64>,
+    max_packets: Option<u64>,
+    window_start: Instant,
+    bytes: u64,
+    packets: u64,
+}
+
+impl RecvBudget {
+    pub(super) fn new(max_bytes: Option<u64>, max_packets: Option<u64>) -> Self {
+        RecvBudget {
+            max_bytes,
+            max_packets,
+            window_start: Instant::now(),
+            bytes: 0,
+            packets: 0,
+        }
+    }
+
+    #[inline]
+    pub(super) fn is_limited(&self) -> bool {
+        self.max_bytes.is_some() || self.max_packets.is_some()
+    }
+
+    // Returns true while the connection is over its budget for the current window
+    pub(super) fn is_exceeded(&mut self, now: Instant) -> bool {
+        self.is_limited() && {
+            self.roll_window(now);
+            !self.is_within()
+        }
+    }
+
+    // Charges the accepted bytes and packets and returns false once the connection goes over its budget
+    pub(super) fn spend(&mut self, bytes: u64, packets: u64, now: Instant) -> bool {
+        if !self.is_limited() {
+            return true;
+        }
+
+        self.roll_window(now);
+        self.bytes += bytes;
+        self.packets += packets;
+        self.is_within()
+    }
+
+    fn roll_window(&mut self, now: Instant) {
+        if now.duration_since(self.window_start) >= WINDOW {
+            self.window_start = now;
+            self.bytes = 0;
+            self.packets = 0;
+        }
+    }
+
+    fn is_within(&self) -> bool {
+        let bytes_ok = match self.max_bytes {
+            Some(max_bytes) => self.bytes <= max_bytes,
+            None => true,
+        };
+        let packets_ok = match self.max_packets {
+            Some(max_packets) => self.packets <= max_packets,
+            None => true,
+        };
+        bytes_ok && packets_ok
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_limit_resets_each_window() {
+        let mut limiter = SourceRateLimiter::new(2);
+        let source: IpAddr = "192.0.2.1".parse().unwrap();
+        let other_source: IpAddr = "192.0.2.2".parse().unwrap();
+        let now = Instant::now();
+
+        assert!(limiter.allow(source, now));
+        assert!(limiter.allow(source, now));
+        assert!(!limiter.allow(source, now + Duration::from_millis(500)));
+        assert!(limiter.allow(other_source, now));
+        assert!(limiter.allow(source, now + WINDOW));
+    }
+
+    #[test]
+    fn source_map_gets_pruned() {
+        let mut limiter = SourceRateLimiter::new(1);
+        let now = Instant::now();
+        for num in 0..SOURCE_PRUNE_LEN as u32 {
+            assert!(limiter.allow(IpAddr::from(num.to_be_bytes()), now));
+        }
+        assert_eq!(limiter.sources.len(), SOURCE_PRUNE_LEN);
+
+        let later = now + WINDOW;
+        assert!(limiter.allow("198.51.100.1".parse().unwrap(), later));
+        assert_eq!(limiter.sources.len(), 1);
+    }
+
+    #[test]
+    fn unlimited_budget_is_never_exceeded() {
+        let mut budget = RecvBudget::new(None, None);
+        let now = Instant::now();
+        assert!(budget.spend(u64::MAX / 2, 1_000_000, now));
+        assert!(!budget.is_exceeded(now));
+    }
+
+    #[test]
+    fn budget_is_exceeded_until_the_next_window() {
+        let mut budget = RecvBudget::new(Some(1000), Some(3));
+        let now = budget.window_start;
+
+        assert!(budget.spend(600, 1, now));
+        assert!(!budget.is_exceeded(now));
+        assert!(!budget.spend(600, 1, now));
+        assert!(budget.is_exceeded(now + Duration::from_millis(999)));
+        assert!(!budget.is_exceeded(now + WINDOW));
+
+        let later = now + WINDOW;
+        assert!(budget.spend(0, 3, later));
+        assert!(!budget.spend(0, 1, later));
+    }
+}
@@ -20,27 +20,36 @@
 //OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 //SOFTWARE.
 
-use crate::endpoint::SocketAddr;
+use crate::endpoint::admission::RecvBudget;
+use crate::endpoint::tls::{self, ClientVerification, Identity, ServerVerification};
+use crate::endpoint::{
+    CongestionControl, ConnectionStats, Pacing, SocketAddr, StreamClass, StreamPriority,
+    DEFAULT_BACKGROUND_STREAM,
+};
 use std::collections::VecDeque;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 pub(super) use quiche::Config;
 pub(super) use quiche::Error;
 
 // Communication Connection Constants
 // Bidirectional Stream ID# used for the main reliable communication in the application between the server and the client (started by client)
-// This stream has the first (#1) send priority compared to other streams
 const MAIN_STREAM_ID: u64 = 0;
-const MAIN_STREAM_PRIORITY: u8 = 100; //
 
 // Real-time Unidirectional Stream ID# start constants used for "unreliable" communication in the application
 const SERVER_REALTIME_START_ID: u64 = 3;
 const CLIENT_REALTIME_START_ID: u64 = 2;
 
 // Bidirectional Stream ID# used for the background reliable communication in the application between the server and the client (started by client)
-// This stream has the last send priority compared to other streams
-const BACKGROUND_STREAM_ID: u64 = 4;
-const BACKGROUND_STREAM_PRIORITY: u8 = 200;
+const BACKGROUND_STREAM_ID: u64 = DEFAULT_BACKGROUND_STREAM;
+
+// First Bidirectional Stream ID#s used for additional background streams opened by the application
+const SERVER_BACKGROUND_START_ID: u64 = 1;
+const CLIENT_BACKGROUND_START_ID: u64 = 8;
+
+// Number of connection IDs a peer can give this side of a connection to use (the QUIC default is 2)
+// A couple spare ones let the peer move to a new path or rotate without ever reusing a connection ID
+const ACTIVE_CONNECTION_ID_LIMIT: u64 = 4;
 
 struct StreamRecv {
     captured: usize,
@@ -58,6 +67,41 @@ impl StreamRecv {
     }
 }
 
+// Send priorities of the three stream classes, the background bandwidth share (in percent),
+// the maximum age of a real-time time segment and the low-water mark of the reliable send queues
+#[derive(Clone, Copy)]
+pub(super) struct SendSchedule {
+    pub(super) main: StreamPriority,
+    pub(super) rt: StreamPriority,
+    pub(super) bkgd: StreamPriority,
+    pub(super) bkgd_share_opt: Option<u8>,
+    pub(super) rt_max_age_opt: Option<Duration>,
+    pub(super) send_low_water: usize,
+}
+
+// A reliable background stream (the default background stream is always the first one)
+struct BkgdStream {
+    id: u64,
+    recv: StreamRecv,
+    send_queue: VecDeque<SendBuffer>,
+    recv_finished: bool,
+    finish_requested: bool, // A FIN gets sent once the send queue is empty
+    fin_sent: bool,
+}
+
+impl BkgdStream {
+    fn new(id: u64) -> Self {
+        BkgdStream {
+            id,
+            recv: StreamRecv::empty(),
+            send_queue: VecDeque::with_capacity(4),
+            recv_finished: false,
+            finish_requested: false,
+            fin_sent: false,
+        }
+    }
+}
+
 struct SendBuffer {
     data: Vec<u8>,
     sent: usize,
@@ -99,20 +143,39 @@ impl RealtimeRecv {
 // QUIC Connection (Using the quiche crate)
 pub(super) struct Connection {
     id: u64,                                     // ID to be used by the application
-    current_scid: quiche::ConnectionId<'static>, // Current SCID used by this connection
+    current_scid: quiche::ConnectionId<'static>, // First SCID used by this connection (later ones are derived from it)
+    scids_issued: u64,                           // Number of additional SCIDs given to the peer
     connection: quiche::Connection,              // quiche Connection
     recv_info: quiche::RecvInfo,
+    peer_addr: SocketAddr,                 // Peer address of the active path
+    migrated_addr_opt: Option<SocketAddr>, // Set when the peer migrated and not yet reported
     last_send_instant: Instant, // Used for sending PING / ACK_Elicting if it's been a while
     next_timeout_instant: Option<Instant>,
     established_once: bool,
+    recv_paused: bool, // Main and background stream data is left in quiche so flow control pushes back
     main_recv: StreamRecv,
     main_send_queue: VecDeque<SendBuffer>,
     rt_recv: RealtimeRecv,
     rt_send_queue: VecDeque<SendBuffer>,
     rt_send_finished: bool,
+    rt_send_deadline: Option<Instant>, // When the current real-time time segment expires
+    rt_handed_off: VecDeque<(u64, Instant, usize)>, // Finished real-time streams with data quiche has not sent yet
+    rt_segments_expired: u64,
     rt_send_stream_id: u64,
-    bkgd_recv: StreamRecv,
-    bkgd_send_queue: VecDeque<SendBuffer>,
+    rt_send_stream_len: usize, // Bytes given to quiche on the current real-time stream
+    bkgd_streams: Vec<BkgdStream>,
+    bkgd_recv_ended: VecDeque<u64>, // Additional background streams that nothing more will arrive on
+    next_bkgd_stream_id: u64,       // For the next background stream opened by this side
+    bkgd_recv_sizes: (usize, usize), // Initial buffer size and first target for new background streams
+    send_schedule: Option<SendSchedule>, // Set once established
+    main_above_low_water: bool,
+    bkgd_above_low_water: bool,
+    priorities_applied: bool,
+    rt_priority_applied: bool, // For the current real-time send stream
+    bkgd_budget: usize,        // Bytes the background stream can still send within its share
+    bkgd_budget_instant: Instant,
+    session_taken: bool,
+    recv_budget: RecvBudget, // Inbound bytes / packets allowed per second
 }
 
 pub(super) enum CloseOrigin {
@@ -145,12 +208,22 @@ pub(super) enum RecvResult {
     StreamProcess(u64),
 }
 
+// Information from a server received Initial packet header that could start a new connection
+pub(super) struct InitialInfo {
+    pub(super) scid: quiche::ConnectionId<'static>,
+    pub(super) token: Vec<u8>,
+    pub(super) version: u32,
+}
+
 pub(super) enum StreamResult {
     NoMore,
+    StreamWritable(StreamClass),
     Nothing,
     MainStreamReadable((Vec<u8>, usize)),
     RealtimeStreamReadable((Vec<u8>, usize, u64)),
-    BkgdStreamReadable((Vec<u8>, usize)),
+    RealtimeDatagramReadable(Vec<u8>),
+    BkgdStreamReadable((u64, Vec<u8>, usize)),
+    BkgdStreamRecvEnded(u64),
     MainStreamFinished,
     BkgdStreamFinished,
 }
@@ -158,37 +231,71 @@ pub(super) enum StreamResult {
 impl Connection {
     pub(super) fn create_config(
         alpns: &[&[u8]],
-        cert_path: &str,
-        pkey_path_option: Option<&str>,
+        is_server: bool,
+        identity_opt: Option<&Identity>,
+        verification_opt: Option<&ServerVerification>,
+        client_verification_opt: Option<&ClientVerification>,
         idle_timeout_in_ms: u64,
         max_payload_size: usize,
         reliable_stream_buffer: u64,
         unreliable_stream_buffer: u64,
+        rt_datagram_queue_len: usize,
+        log_keys: bool,
+        early_data: bool,
+        congestion_control: CongestionControl,
+        initial_congestion_window: Option<usize>,
+        pacing: Pacing,
+        max_background_streams: u64,
+        discover_pmtu: bool,
     ) -> Result<Config, Error> {
+        // The TLS context holds the certificates and keys (loaded from files or memory)
+        let ssl_ctx_builder = match tls::create_ssl_context_builder(
+            identity_opt,
+            verification_opt,
+            client_verification_opt,
+        ) {
+            Ok(builder) => builder,
+            Err(_) => return Err(Error::TlsFail),
+        };
+
         // A quiche Config with default values
-        let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+        let mut config =
+            quiche::Config::with_boring_ssl_ctx_builder(quiche::PROTOCOL_VERSION, ssl_ctx_builder)?;
 
         config.set_application_protos(alpns)?;
 
-        // Do different config things if it is a server vs a client based on pkey path availability
-        if let Some(pkey_path) = pkey_path_option {
-            // Maybe not return error immediately here?
-            config.load_cert_chain_from_pem_file(cert_path)?;
-            config.load_priv_key_from_pem_file(pkey_path)?;
-            config.verify_peer(false);
+        // Do different config things if it is a server vs a client
+        if is_server {
+            // Client certificates with a fingerprint allow-list are requested by the TLS context itself
+            match client_verification_opt {
+                Some(client_verification) => {
+                    if client_verification.verifies_chain() {
+                        config.verify_peer(true);
+                    }
+                }
+                None => config.verify_peer(false),
+            }
 
-            config.set_initial_max_streams_bidi(2); // 1 For Main Communication, 2 and 3 for Unordered Alt Data (like file transfers)
+            // 1 For Main Communication, 1 for the default Background Stream, and any additional Background Streams
+            config.set_initial_max_streams_bidi(2 + max_background_streams);
         } else {
-            // Temporary solution for client to verify certificate
-            // Maybe not return error immediately here?
-            config.load_verify_locations_from_file(cert_path)?;
-            config.verify_peer(true);
+            // Pinned certificates are verified by the verify callback of the TLS context instead
+            // (which setting verify_peer would replace)
+            let verifies_chain = match verification_opt {
+                Some(verification) => verification.verifies_chain(),
+                None => true,
+            };
+            if verifies_chain {
+                config.verify_peer(true);
+            }
 
-            config.set_initial_max_streams_bidi(0);
+            config.set_initial_max_streams_bidi(max_background_streams);
         }
 
         // Enable the ability to log the secret keys for wireshark debugging
-        config.log_keys();
+        if log_keys {
+            config.log_keys();
+        }
 
         // Malicious Second Chance Add In Future
         config.set_initial_max_streams_uni(100); // Based on 1 second of 10ms Real-time Streams
@@ -198,6 +305,9 @@ impl Connection {
         config.set_max_recv_udp_payload_size(max_payload_size);
         config.set_max_send_udp_payload_size(max_payload_size);
 
+        // Probes grow the sent datagram size from 1200 bytes up to the max payload size (RFC 8899)
+        config.discover_pmtu(discover_pmtu);
+
         config.set_initial_max_stream_data_bidi_local(reliable_stream_buffer);
         config.set_initial_max_stream_data_bidi_remote(reliable_stream_buffer);
         config.set_initial_max_stream_data_uni(unreliable_stream_buffer);
@@ -205,11 +315,39 @@ impl Connection {
         config
             .set_initial_max_data((reliable_stream_buffer * 2) + (unreliable_stream_buffer * 100));
 
-        config.enable_pacing(true); // Default that I confirm
+        config.set_cc_algorithm(match congestion_control {
+            CongestionControl::Reno => quiche::CongestionControlAlgorithm::Reno,
+            CongestionControl::Cubic => quiche::CongestionControlAlgorithm::CUBIC,
+            CongestionControl::Bbr => quiche::CongestionControlAlgorithm::BBR,
+            CongestionControl::Bbr2 => quiche::CongestionControlAlgorithm::BBR2,
+        });
 
-        config.set_disable_active_migration(true); // Temporary
+        if let Some(packets) = initial_congestion_window {
+            config.set_initial_congestion_window_packets(packets);
+        }
+
+        match pacing {
+            Pacing::Off => config.enable_pacing(false),
+            Pacing::On => config.enable_pacing(true),
+            Pacing::MaxRate(max_rate) => {
+                config.enable_pacing(true);
+                config.set_max_pacing_rate(max_rate);
+            }
+        }
+
+        // Peers can move to a new network path (validated by quiche before it is used)
+        config.set_disable_active_migration(false);
+        config.set_active_connection_id_limit(ACTIVE_CONNECTION_ID_LIMIT);
+
+        // Allow 0-RTT early data when resuming a session
+        if early_data {
+            config.enable_early_data();
+        }
 
         // Enable datagram frames for unreliable data to be sent
+        if rt_datagram_queue_len > 0 {
+            config.enable_dgram(true, rt_datagram_queue_len, rt_datagram_queue_len);
+        }
 
         Ok(config)
     }
@@ -219,26 +357,51 @@ impl Connection {
         [0; quiche::MAX_CONN_ID_LEN]
     }
 
-    // returns true if this packet could be a new connection
+    // returns the initial info if this packet could be a new connection
     pub(super) fn recv_header_analyze(
         data: &mut [u8],
         is_server: bool,
-    ) -> Option<(quiche::ConnectionId<'static>, bool)> {
+    ) -> Option<(quiche::ConnectionId<'static>, Option<InitialInfo>)> {
         if let Ok(packet_header) = quiche::Header::from_slice(data, quiche::MAX_CONN_ID_LEN) {
             if is_server
                 && packet_header.ty == quiche::Type::Initial
                 && quiche::version_is_supported(packet_header.version)
             {
                 // This gets reached even when Type is Handshake... look into further
-                Some((packet_header.dcid, true))
+                let initial_info = InitialInfo {
+                    scid: packet_header.scid,
+                    token: packet_header.token.unwrap_or_default(),
+                    version: packet_header.version,
+                };
+                Some((packet_header.dcid, Some(initial_info)))
             } else {
-                Some((packet_header.dcid, false))
+                Some((packet_header.dcid, None))
             }
         } else {
             None
         }
     }
 
+    // Writes a stateless Retry packet that asks the client to repeat its Initial with the token
+    pub(super) fn create_retry_packet(
+        initial_info: &InitialInfo,
+        dcid: &[u8],
+        new_scid_data: &[u8],
+        token: &[u8],
+        packet_data: &mut [u8],
+    ) -> Result<usize, Error> {
+        let dcid = quiche::ConnectionId::from_ref(dcid);
+        let new_scid = quiche::ConnectionId::from_ref(&new_scid_data[..quiche::MAX_CONN_ID_LEN]);
+        quiche::retry(
+            &initial_info.scid,
+            &dcid,
+            &new_scid,
+            token,
+            initial_info.version,
+            packet_data,
+        )
+    }
+
     pub(super) fn new(
         id: u64,
         peer_addr: SocketAddr,
@@ -247,13 +410,19 @@ impl Connection {
         scid_data: &[u8],
         config: &mut quiche::Config,
         writer_opt: Option<Box<std::fs::File>>,
+        qlog_writer_opt: Option<Box<std::fs::File>>,
+        odcid_opt: Option<&[u8]>,
+        recv_budget: RecvBudget,
     ) -> Result<Self, Error> {
         let recv_info = quiche::RecvInfo {
             from: local_addr,
             to: local_addr,
         };
 
-        // Do some connectionID length testing here in future
+        // Connection IDs always use the maximum length (the data can be longer, like an HMAC tag, but never shorter)
+        if scid_data.len() < quiche::MAX_CONN_ID_LEN {
+            return Err(Error::InvalidState);
+        }
         let scid = quiche::ConnectionId::from_ref(&scid_data[..quiche::MAX_CONN_ID_LEN]);
         let current_scid = scid.into_owned();
 
@@ -268,57 +437,116 @@ impl Connection {
                 connection.set_keylog(writer);
             }
 
+            if let Some(qlog_writer) = qlog_writer_opt {
+                connection.set_qlog(
+                    qlog_writer,
+                    String::from("Swiftlet QUIC Client"),
+                    format!("Client Connection ID {}", id),
+                );
+            }
+
             let conn_mgr = Connection {
                 id,
                 current_scid,
+                scids_issued: 0,
                 connection,
                 recv_info,
+                peer_addr,
+                migrated_addr_opt: None,
                 last_send_instant: Instant::now(),
                 next_timeout_instant: None,
                 established_once: false,
+                recv_paused: false,
                 main_recv: StreamRecv::empty(),
                 main_send_queue: VecDeque::with_capacity(4),
                 rt_recv: RealtimeRecv::empty(false),
                 rt_send_queue: VecDeque::with_capacity(4),
                 rt_send_finished: false,
+                rt_send_deadline: None,
+                rt_handed_off: VecDeque::new(),
+                rt_segments_expired: 0,
                 rt_send_stream_id: CLIENT_REALTIME_START_ID,
-                bkgd_recv: StreamRecv::empty(),
-                bkgd_send_queue: VecDeque::with_capacity(4),
+                rt_send_stream_len: 0,
+                bkgd_streams: vec![BkgdStream::new(BACKGROUND_STREAM_ID)],
+                bkgd_recv_ended: VecDeque::new(),
+                next_bkgd_stream_id: CLIENT_BACKGROUND_START_ID,
+                bkgd_recv_sizes: (0, 0),
+                send_schedule: None,
+                main_above_low_water: false,
+                bkgd_above_low_water: false,
+                priorities_applied: false,
+                rt_priority_applied: false,
+                bkgd_budget: 0,
+                bkgd_budget_instant: Instant::now(),
+                session_taken: false,
+                recv_budget,
             };
 
             Ok(conn_mgr)
         } else {
-            // Create server connection
-            let connection =
-                match quiche::accept(&current_scid, None, local_addr, peer_addr, config) {
-                    Ok(mut conn) => {
-                        if let Some(writer) = writer_opt {
-                            // called before recv
-                            conn.set_keylog(writer);
-                        }
-                        conn
+            // Create server connection (with the original destination connection ID after a Retry)
+            let odcid_cid_opt = odcid_opt.map(quiche::ConnectionId::from_ref);
+            let connection = match quiche::accept(
+                &current_scid,
+                odcid_cid_opt.as_ref(),
+                local_addr,
+                peer_addr,
+                config,
+            ) {
+                Ok(mut conn) => {
+                    if let Some(writer) = writer_opt {
+                        // called before recv
+                        conn.set_keylog(writer);
                     }
-                    Err(err) => {
-                        return Err(err);
+                    if let Some(qlog_writer) = qlog_writer_opt {
+                        conn.set_qlog(
+                            qlog_writer,
+                            String::from("Swiftlet QUIC Server"),
+                            format!("Server Connection ID {}", id),
+                        );
                     }
-                };
+                    conn
+                }
+                Err(err) => {
+                    return Err(err);
+                }
+            };
 
             let conn_mgr = Connection {
                 id,
                 current_scid,
+                scids_issued: 0,
                 connection,
                 recv_info,
+                peer_addr,
+                migrated_addr_opt: None,
                 last_send_instant: Instant::now(),
                 next_timeout_instant: None,
                 established_once: false,
+                recv_paused: false,
                 main_recv: StreamRecv::empty(),
                 main_send_queue: VecDeque::with_capacity(4),
                 rt_recv: RealtimeRecv::empty(true),
                 rt_send_queue: VecDeque::with_capacity(4),
                 rt_send_finished: false,
+                rt_send_deadline: None,
+                rt_handed_off: VecDeque::new(),
+                rt_segments_expired: 0,
                 rt_send_stream_id: SERVER_REALTIME_START_ID,
-                bkgd_recv: StreamRecv::empty(),
-                bkgd_send_queue: VecDeque::with_capacity(4),
+                rt_send_stream_len: 0,
+                bkgd_streams: vec![BkgdStream::new(BACKGROUND_STREAM_ID)],
+                bkgd_recv_ended: VecDeque::new(),
+                next_bkgd_stream_id: SERVER_BACKGROUND_START_ID,
+                bkgd_recv_sizes: (0, 0),
+                send_schedule: None,
+                main_above_low_water: false,
+                bkgd_above_low_water: false,
+                priorities_applied: false,
+                rt_priority_applied: false,
+                bkgd_budget: 0,
+                bkgd_budget_instant: Instant::now(),
+                session_taken: false,
+                recv_budget,
             };
 
             Ok(conn_mgr)
@@ -326,13 +554,102 @@ impl Connection {
     }
 
     #[inline]
-    pub(super) fn matches_id(&self, id: u64) -> bool {
-        self.id == id
+    pub(super) fn get_id(&self) -> u64 {
+        self.id
+    }
+
+    #[inline]
+    pub(super) fn current_scid(&self) -> &[u8] {
+        self.current_scid.as_ref()
+    }
+
+    #[inline]
+    pub(super) fn is_established_once(&self) -> bool {
+        self.established_once
+    }
+
+    // Returns true if the paused state changed
+    #[inline]
+    pub(super) fn set_recv_paused(&mut self, paused: bool) -> bool {
+        let changed = self.recv_paused != paused;
+        self.recv_paused = paused;
+        changed
+    }
+
+    // Returns true while the connection is over its inbound budget (datagrams get dropped unread)
+    #[inline]
+    pub(super) fn recv_budget_exceeded(&mut self, now: Instant) -> bool {
+        self.recv_budget.is_exceeded(now)
+    }
+
+    // Bytes and packets quiche has accepted so far (datagrams that could not be decrypted are not counted)
+    #[inline]
+    pub(super) fn recv_totals(&self) -> (u64, usize) {
+        if self.recv_budget.is_limited() {
+            let stats = self.connection.stats();
+            (stats.recv_bytes, stats.recv)
+        } else {
+            (0, 0) // Nothing gets charged
+        }
+    }
+
+    // Returns false when what quiche accepted since the earlier totals goes over the inbound budget
+    pub(super) fn spend_recv_budget(&mut self, earlier_totals: (u64, usize), now: Instant) -> bool {
+        let (recv_bytes, recv_packets) = self.recv_totals();
+        self.recv_budget.spend(
+            recv_bytes - earlier_totals.0,
+            (recv_packets - earlier_totals.1) as u64,
+            now,
+        )
+    }
+
+    #[inline]
+    pub(super) fn scids_issued(&self) -> u64 {
+        self.scids_issued
     }
 
+    // Number of additional SCIDs the peer has room for right now
     #[inline]
-    pub(super) fn matches_dcid(&self, dcid: &[u8]) -> bool {
-        self.current_scid.as_ref() == dcid
+    pub(super) fn scids_left(&self) -> usize {
+        self.connection.scids_left()
+    }
+
+    // Advertises an additional SCID with NEW_CONNECTION_ID so the peer has one to use on a new path
+    // (the Endpoint connection table routes packets sent to it back to this connection)
+    // When retire_oldest is true the peer is asked to retire the oldest SCID if it has no room left
+    pub(super) fn add_scid(
+        &mut self,
+        scid_data: &[u8],
+        reset_token: u128,
+        retire_oldest: bool,
+    ) -> Result<(), Error> {
+        let scid = quiche::ConnectionId::from_ref(&scid_data[..quiche::MAX_CONN_ID_LEN]);
+        self.connection
+            .new_scid(&scid, reset_token, retire_oldest)?;
+        self.scids_issued += 1;
+        Ok(())
+    }
+
+    // Returns the next SCID that the peer retired with RETIRE_CONNECTION_ID
+    #[inline]
+    pub(super) fn next_retired_scid(&mut self) -> Option<quiche::ConnectionId<'static>> {
+        self.connection.retired_scid_next()
+    }
+
+    // Returns the connection ID and the new peer address once after the peer migrated
+    #[inline]
+    pub(super) fn take_migrated_addr(&mut self) -> Option<(u64, SocketAddr)> {
+        self.migrated_addr_opt.take().map(|addr| (self.id, addr))
+    }
+
+    // Path validation itself (PATH_CHALLENGE / PATH_RESPONSE) is done by quiche
+    fn handle_path_events(&mut self) {
+        while let Some(path_event) = self.connection.path_event_next() {
+            if let quiche::PathEvent::PeerMigrated(_local_addr, peer_addr) = path_event {
+                self.peer_addr = peer_addr;
+                self.migrated_addr_opt = Some(peer_addr);
+            }
+        }
     }
 
     // Better way to write this?
@@ -419,6 +736,7 @@ impl Connection {
                 if send_info.at > self.last_send_instant {
                     self.last_send_instant = send_info.at;
                 }
+                self.rt_handed_off_sent(packet_len);
                 Ok(SendResult::DataToSend((
                     packet_len,
                     send_info.to,
@@ -440,6 +758,21 @@ impl Connection {
         }
     }
 
+    // Credits sent bytes to the finished real-time streams that are still waiting on quiche
+    // The oldest one is sent first (most urgent stream class and lowest stream ID) so it gets credited first.
+    // quiche does not expose how much of a stream it has sent, so this is an estimate that leans towards sent
+    // since the packet length also covers headers, acks and retransmissions.
+    fn rt_handed_off_sent(&mut self, mut sent_len: usize) {
+        while let Some((_, _, unsent_len)) = self.rt_handed_off.front_mut() {
+            if sent_len < *unsent_len {
+                *unsent_len -= sent_len;
+                break;
+            }
+            sent_len -= *unsent_len;
+            self.rt_handed_off.pop_front();
+        }
+    }
+
     // Returns None when a timeout occurred
     pub(super) fn handle_possible_timeout(&mut self) -> Option<Option<Instant>> {
         if let Some(timeout_instant) = self.next_timeout_instant {
@@ -490,10 +823,22 @@ impl Connection {
     }
 
     fn rt_stream_send_next(&mut self) -> Result<usize, Error> {
+        self.rt_stream_expire(Instant::now())?;
+
         let mut total_bytes_sent = 0;
         loop {
             // Finish logic should be correct here based on the internals of stream_send()
             let fin = self.rt_send_finished && (self.rt_send_queue.len() == 1);
+            if !self.rt_send_queue.is_empty() && !self.rt_priority_applied {
+                if let Some(schedule) = &self.send_schedule {
+                    self.connection.stream_priority(
+                        self.rt_send_stream_id,
+                        schedule.rt.urgency,
+                        schedule.rt.incremental,
+                    )?;
+                    self.rt_priority_applied = true;
+                }
+            }
             if let Some(send_buf) = self.rt_send_queue.front_mut() {
                 match self.connection.stream_send(
                     self.rt_send_stream_id,
@@ -503,11 +848,23 @@ impl Connection {
                     Ok(bytes_sent) => {
                         total_bytes_sent += bytes_sent;
                         send_buf.sent += bytes_sent;
+                        self.rt_send_stream_len += bytes_sent;
                         if send_buf.sent >= send_buf.data.len() {
                             self.rt_send_queue.pop_front();
                             if fin {
+                                // quiche may still be holding the data back (like when congestion limited)
+                                // so keep the deadline until all of it has been sent
+                                if let Some(deadline) = self.rt_send_deadline.take() {
+                                    self.rt_handed_off.push_back((
+                                        self.rt_send_stream_id,
+                                        deadline,
+                                        self.rt_send_stream_len,
+                                    ));
+                                }
                                 self.rt_send_stream_id += 4;
+                                self.rt_send_stream_len = 0;
                                 self.rt_send_finished = false;
+                                self.rt_priority_applied = false;
                             }
                         } else {
                             return Ok(total_bytes_sent);
@@ -526,37 +883,174 @@ impl Connection {
         }
     }
 
+    // Adds to the background budget based on its share of the path capacity since the last refill
+    fn refill_bkgd_budget(&mut self, share: u8) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.bkgd_budget_instant);
+        self.bkgd_budget_instant = now;
+
+        if let Some(path_stats) = self.connection.path_stats().find(|ps| ps.active) {
+            // The capacity is a congestion window per smoothed round trip
+            // (The delivery rate can't be used since it is held back by the share itself when background data runs alone)
+            let rate = path_stats.cwnd as f64 / path_stats.rtt.as_secs_f64().max(0.001);
+            let share_ratio = share as f64 / 100.0;
+            let max_budget = (path_stats.cwnd as f64 * share_ratio) as usize;
+            let refill = (rate * share_ratio * elapsed.as_secs_f64()) as usize;
+            self.bkgd_budget = (self.bkgd_budget + refill).min(max_budget);
+        }
+    }
+
     fn bkgd_stream_send_next(&mut self) -> Result<usize, Error> {
+        let share_opt = match &self.send_schedule {
+            Some(schedule) => schedule.bkgd_share_opt,
+            None => None,
+        };
+        if let Some(share) = share_opt {
+            self.refill_bkgd_budget(share);
+        }
+
+        // The budget is shared by all of the background streams
         let mut total_bytes_sent = 0;
-        loop {
-            if let Some(send_buf) = self.bkgd_send_queue.front_mut() {
+        for bkgd_stream in self.bkgd_streams.iter_mut() {
+            while let Some(send_buf) = bkgd_stream.send_queue.front_mut() {
+                let send_end = match share_opt {
+                    Some(_) => send_buf.data.len().min(send_buf.sent + self.bkgd_budget),
+                    None => send_buf.data.len(),
+                };
+                if send_end == send_buf.sent {
+                    break; // Out of budget until the next refill
+                }
                 match self.connection.stream_send(
-                    BACKGROUND_STREAM_ID,
-                    &send_buf.data[send_buf.sent..],
+                    bkgd_stream.id,
+                    &send_buf.data[send_buf.sent..send_end],
                     false,
                 ) {
                     Ok(bytes_sent) => {
+                        if share_opt.is_some() {
+                            self.bkgd_budget -= bytes_sent;
+                        }
                         total_bytes_sent += bytes_sent;
                         send_buf.sent += bytes_sent;
                         if send_buf.sent >= send_buf.data.len() {
-                            self.bkgd_send_queue.pop_front();
+                            bkgd_stream.send_queue.pop_front();
                         } else {
-                            return Ok(total_bytes_sent);
+                            break;
                         }
                     }
                     Err(Error::Done) => {
-                        return Ok(total_bytes_sent);
+                        break;
                     }
                     Err(e) => {
                         return Err(e);
                     }
                 }
-            } else {
-                return Ok(total_bytes_sent);
             }
+
+            if bkgd_stream.finish_requested
+                && !bkgd_stream.fin_sent
+                && bkgd_stream.send_queue.is_empty()
+            {
+                match self.connection.stream_send(bkgd_stream.id, &[], true) {
+                    Ok(_) => bkgd_stream.fin_sent = true,
+                    Err(Error::Done) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        // Additional background streams are forgotten once both sides have finished them
+        self.bkgd_streams
+            .retain(|bkgd_stream| !(bkgd_stream.recv_finished && bkgd_stream.fin_sent));
+
+        Ok(total_bytes_sent)
+    }
+
+    #[inline]
+    fn find_bkgd_stream(&self, stream_id: u64) -> Option<usize> {
+        self.bkgd_streams
+            .iter()
+            .position(|bkgd_stream| bkgd_stream.id == stream_id)
+    }
+
+    fn add_bkgd_stream(&mut self, stream_id: u64) -> usize {
+        let (initial_size, first_bytes) = self.bkgd_recv_sizes;
+        let mut bkgd_stream = BkgdStream::new(stream_id);
+        bkgd_stream.recv.target = first_bytes;
+        bkgd_stream.recv.data = Some(vec![0; initial_size]);
+        self.bkgd_streams.push(bkgd_stream);
+        self.bkgd_streams.len() - 1
+    }
+
+    // Opens an additional background stream and returns its stream ID
+    pub(super) fn open_bkgd_stream(&mut self) -> Result<u64, Error> {
+        let schedule = match &self.send_schedule {
+            Some(schedule) => *schedule,
+            None => return Err(Error::InvalidState),
+        };
+        if self.connection.peer_streams_left_bidi() == 0 {
+            return Err(Error::StreamLimit);
+        }
+
+        let stream_id = self.next_bkgd_stream_id;
+        // Setting the priority creates the stream locally
+        self.connection.stream_priority(
+            stream_id,
+            schedule.bkgd.urgency,
+            schedule.bkgd.incremental,
+        )?;
+        self.next_bkgd_stream_id += 4;
+        self.add_bkgd_stream(stream_id);
+        Ok(stream_id)
+    }
+
+    // Sends a FIN on an additional background stream once everything queued on it has been sent
+    pub(super) fn finish_bkgd_stream(&mut self, stream_id: u64) -> Result<(), Error> {
+        match self.find_bkgd_stream(stream_id) {
+            Some(bkgd_index) if stream_id != BACKGROUND_STREAM_ID => {
+                self.bkgd_streams[bkgd_index].finish_requested = true;
+                self.bkgd_stream_send_next()?;
+                Ok(())
+            }
+            _ => Err(Error::InvalidStreamState(stream_id)),
+        }
+    }
+
+    // Abruptly stops an additional background stream in both directions
+    pub(super) fn close_bkgd_stream(&mut self, stream_id: u64) -> Result<(), Error> {
+        match self.find_bkgd_stream(stream_id) {
+            Some(bkgd_index) if stream_id != BACKGROUND_STREAM_ID => {
+                self.bkgd_streams.remove(bkgd_index);
+                // Either direction might have already finished
+                let _ = self
+                    .connection
+                    .stream_shutdown(stream_id, quiche::Shutdown::Read, 0);
+                let _ = self
+                    .connection
+                    .stream_shutdown(stream_id, quiche::Shutdown::Write, 0);
+                Ok(())
+            }
+            _ => Err(Error::InvalidStreamState(stream_id)),
         }
     }
 
+    // The peer finished sending on an additional background stream
+    // This side keeps sending until the application finishes the stream too
+    fn bkgd_stream_recv_finished(&mut self, bkgd_index: usize) {
+        self.bkgd_streams[bkgd_index].recv_finished = true;
+        self.bkgd_recv_ended
+            .push_back(self.bkgd_streams[bkgd_index].id);
+        if self.bkgd_streams[bkgd_index].fin_sent {
+            self.bkgd_streams.remove(bkgd_index);
+        }
+    }
+
+    // The peer gave up on an additional background stream so it is stopped in both directions
+    fn bkgd_stream_peer_reset(&mut self, stream_id: u64) -> Result<(), Error> {
+        self.close_bkgd_stream(stream_id)?;
+        self.bkgd_recv_ended.push_back(stream_id);
+        Ok(())
+    }
+
     pub(super) fn recv_data(
         &mut self,
         data: &mut [u8],
@@ -578,11 +1072,13 @@ impl Connection {
             }
             return Err(e);
         }
+        self.handle_path_events();
         if let Some(close_info) = self.get_close_info() {
             return Ok(RecvResult::CloseInfo(close_info));
         }
 
         if self.established_once {
+            self.apply_stream_priorities();
             self.main_stream_send_next()?;
             self.rt_stream_send_next()?;
             self.bkgd_stream_send_next()?;
@@ -603,16 +1099,16 @@ impl Connection {
         rt_recv_bytes_initial: usize,
         background_recv_data: Vec<u8>,
         background_recv_bytes: usize,
+        send_schedule: SendSchedule,
     ) -> Result<(), Error> {
+        self.send_schedule = Some(send_schedule);
+
         // Create streams depending on connection type:
         if !self.connection.is_server() {
-            self.connection
-                .stream_priority(MAIN_STREAM_ID, MAIN_STREAM_PRIORITY, true)?;
-            self.connection.stream_priority(
-                BACKGROUND_STREAM_ID,
-                BACKGROUND_STREAM_PRIORITY,
-                true,
-            )?;
+            self.apply_stream_priorities();
+            if !self.priorities_applied {
+                return Err(Error::InvalidStreamState(MAIN_STREAM_ID));
+            }
         }
 
         self.main_recv.target = main_recv_bytes;
@@ -620,16 +1116,56 @@ impl Connection {
         self.rt_recv.initial_target = rt_recv_bytes_initial;
         self.rt_recv.target = self.rt_recv.initial_target;
         self.rt_recv.data = Some(rt_recv_data);
-        self.bkgd_recv.target = background_recv_bytes;
-        self.bkgd_recv.data = Some(background_recv_data);
+        self.bkgd_recv_sizes = (background_recv_data.len(), background_recv_bytes);
+        self.bkgd_streams[0].recv.target = background_recv_bytes;
+        self.bkgd_streams[0].recv.data = Some(background_recv_data);
 
         self.established_once = true;
         Ok(())
     }
 
+    // A server can only prioritize the client started streams once the client has sent on them
+    fn apply_stream_priorities(&mut self) {
+        if !self.priorities_applied {
+            if let Some(schedule) = &self.send_schedule {
+                let main_res = self.connection.stream_priority(
+                    MAIN_STREAM_ID,
+                    schedule.main.urgency,
+                    schedule.main.incremental,
+                );
+                let bkgd_res = self.connection.stream_priority(
+                    BACKGROUND_STREAM_ID,
+                    schedule.bkgd.urgency,
+                    schedule.bkgd.incremental,
+                );
+                self.priorities_applied = main_res.is_ok() && bkgd_res.is_ok();
+            }
+        }
+    }
+
     // A returned Error::InvalidState indicates something went wrong with the read process
     pub(super) fn stream_process(&mut self) -> Result<StreamResult, Error> {
-        if let Some(next_readable_stream) = self.connection.stream_readable_next() {
+        // Reported after any of the stream's remaining data was handed over
+        if let Some(stream_id) = self.bkgd_recv_ended.pop_front() {
+            return Ok(StreamResult::BkgdStreamRecvEnded(stream_id));
+        }
+
+        // Real-time datagrams are processed first since they are the most time sensitive
+        match self.connection.dgram_recv_vec() {
+            Ok(dgram_data) => return Ok(StreamResult::RealtimeDatagramReadable(dgram_data)),
+            Err(Error::Done) => {}
+            Err(e) => return Err(e),
+        }
+
+        let next_readable_opt = if self.recv_paused {
+            // Only the real-time (unidirectional) streams keep being read
+            self.connection
+                .readable()
+                .find(|stream_id| (stream_id & 0x2) != 0)
+        } else {
+            self.connection.stream_readable_next()
+        };
+        if let Some(next_readable_stream) = next_readable_opt {
             if next_readable_stream == MAIN_STREAM_ID {
                 if let Some(mut recv_data) = self.main_recv.data.take() {
                     let (bytes_read, is_finished) = self.connection.stream_recv(
@@ -656,29 +1192,68 @@ impl Connection {
                 } else {
                     Err(Error::InvalidStreamState(11))
                 }
-            } else if next_readable_stream == BACKGROUND_STREAM_ID {
-                if let Some(mut recv_data) = self.bkgd_recv.data.take() {
-                    let (bytes_read, is_finished) = self.connection.stream_recv(
-                        BACKGROUND_STREAM_ID,
-                        &mut recv_data[self.bkgd_recv.captured..self.bkgd_recv.target],
-                    )?; // Shouldn't throw a done since it was stated to be readable
-                    if !is_finished {
-                        self.bkgd_recv.captured += bytes_read;
-                        #[allow(clippy::comparison_chain)]
-                        if self.bkgd_recv.captured == self.bkgd_recv.target {
-                            Ok(StreamResult::BkgdStreamReadable((
+            } else if (next_readable_stream & 0x2) == 0 {
+                // Every other bidirectional stream is a background stream
+                let bkgd_index = match self.find_bkgd_stream(next_readable_stream) {
+                    Some(bkgd_index) => bkgd_index,
+                    None => {
+                        let peer_started =
+                            ((next_readable_stream & 0x1) == 0) == self.connection.is_server();
+                        if peer_started && !self.connection.stream_finished(next_readable_stream) {
+                            if let Some(schedule) = &self.send_schedule {
+                                let _ = self.connection.stream_priority(
+                                    next_readable_stream,
+                                    schedule.bkgd.urgency,
+                                    schedule.bkgd.incremental,
+                                );
+                            }
+                            self.add_bkgd_stream(next_readable_stream)
+                        } else {
+                            // Leftover data of an already closed background stream
+                            return self.discard_stream_data(next_readable_stream);
+                        }
+                    }
+                };
+                let stream_id = self.bkgd_streams[bkgd_index].id;
+                let bkgd_recv = &mut self.bkgd_streams[bkgd_index].recv;
+                if let Some(mut recv_data) = bkgd_recv.data.take() {
+                    let (bytes_read, is_finished) = match self.connection.stream_recv(
+                        stream_id,
+                        &mut recv_data[bkgd_recv.captured..bkgd_recv.target],
+                    ) {
+                        Ok(recv_info) => recv_info,
+                        Err(Error::StreamReset(_)) if stream_id != BACKGROUND_STREAM_ID => {
+                            self.bkgd_stream_peer_reset(stream_id)?;
+                            return Ok(StreamResult::Nothing);
+                        }
+                        Err(e) => return Err(e), // Shouldn't throw a done since it was stated to be readable
+                    };
+                    if is_finished && stream_id == BACKGROUND_STREAM_ID {
+                        return Ok(StreamResult::BkgdStreamFinished);
+                    }
+                    bkgd_recv.captured += bytes_read;
+                    #[allow(clippy::comparison_chain)]
+                    let stream_res = if bkgd_recv.captured == bkgd_recv.target {
+                        StreamResult::BkgdStreamReadable((stream_id, recv_data, bkgd_recv.target))
+                    } else if bkgd_recv.captured < bkgd_recv.target {
+                        if is_finished && bkgd_recv.captured > 0 {
+                            // The peer finished in the middle of a message so the partial message is handed over
+                            StreamResult::BkgdStreamReadable((
+                                stream_id,
                                 recv_data,
-                                self.bkgd_recv.target,
-                            )))
-                        } else if self.bkgd_recv.captured < self.bkgd_recv.target {
-                            self.bkgd_recv.data = Some(recv_data);
-                            Ok(StreamResult::Nothing)
+                                bkgd_recv.captured,
+                            ))
                         } else {
-                            Err(Error::InvalidStreamState(12))
+                            bkgd_recv.data = Some(recv_data);
+                            StreamResult::Nothing
                         }
                     } else {
-                        Ok(StreamResult::BkgdStreamFinished)
+                        return Err(Error::InvalidStreamState(12));
+                    };
+                    if is_finished {
+                        self.bkgd_stream_recv_finished(bkgd_index);
                     }
+                    Ok(stream_res)
                 } else {
                     Err(Error::InvalidStreamState(13))
                 }
@@ -689,24 +1264,67 @@ impl Connection {
                     Err(Error::InvalidStreamState(14))
                 }
             } else {
-                let mut temp_data = [0; 8];
-                match self
-                    .connection
-                    .stream_recv(next_readable_stream, &mut temp_data)
-                {
-                    Err(Error::StreamReset(_)) => {
-                        return Ok(StreamResult::Nothing);
-                    }
-                    Err(Error::Done) => Ok(StreamResult::Nothing),
-                    Err(e) => Err(e),
-                    Ok(_) => Ok(StreamResult::Nothing),
-                }
+                self.discard_stream_data(next_readable_stream)
             }
+        } else if let Some(class) = self.take_writable_class() {
+            Ok(StreamResult::StreamWritable(class))
         } else {
             Ok(StreamResult::NoMore)
         }
     }
 
+    fn discard_stream_data(&mut self, stream_id: u64) -> Result<StreamResult, Error> {
+        let mut temp_data = [0; 8];
+        match self.connection.stream_recv(stream_id, &mut temp_data) {
+            Err(Error::StreamReset(_)) => Ok(StreamResult::Nothing),
+            Err(Error::Done) => Ok(StreamResult::Nothing),
+            Err(e) => Err(e),
+            Ok(_) => Ok(StreamResult::Nothing),
+        }
+    }
+
+    pub(super) fn queued_bytes(&self, class: StreamClass) -> usize {
+        let unsent_bytes = |send_queue: &VecDeque<SendBuffer>| -> usize {
+            send_queue
+                .iter()
+                .map(|send_buf| send_buf.data.len() - send_buf.sent)
+                .sum()
+        };
+        match class {
+            StreamClass::Main => unsent_bytes(&self.main_send_queue),
+            StreamClass::Realtime => unsent_bytes(&self.rt_send_queue),
+            StreamClass::Background => self
+                .bkgd_streams
+                .iter()
+                .map(|bkgd_stream| unsent_bytes(&bkgd_stream.send_queue))
+                .sum(),
+        }
+    }
+
+    #[inline]
+    fn send_low_water(&self) -> usize {
+        match &self.send_schedule {
+            Some(schedule) => schedule.send_low_water,
+            None => 0,
+        }
+    }
+
+    // Reports a reliable stream class once after its send queue dropped below the low-water mark
+    fn take_writable_class(&mut self) -> Option<StreamClass> {
+        let low_water = self.send_low_water();
+        if self.main_above_low_water && self.queued_bytes(StreamClass::Main) < low_water {
+            self.main_above_low_water = false;
+            Some(StreamClass::Main)
+        } else if self.bkgd_above_low_water
+            && self.queued_bytes(StreamClass::Background) < low_water
+        {
+            self.bkgd_above_low_water = false;
+            Some(StreamClass::Background)
+        } else {
+            None
+        }
+    }
+
     fn stream_process_realtime(
         &mut self,
         next_readable_stream: u64,
@@ -864,12 +1482,82 @@ impl Connection {
     }
 
     pub(super) fn get_socket_addr(&self) -> SocketAddr {
-        self.recv_info.from
+        self.peer_addr
+    }
+
+    // Must be called before the first send to attempt a session resumption (with 0-RTT if enabled)
+    #[inline]
+    pub(super) fn set_session(&mut self, session: &[u8]) -> Result<(), Error> {
+        self.connection.set_session(session)
+    }
+
+    // Returns the resumable session once it is available (only the first one for this connection)
+    pub(super) fn take_new_session(&mut self) -> Option<Vec<u8>> {
+        if self.session_taken {
+            return None;
+        }
+        let session = self.connection.session()?.to_vec();
+        self.session_taken = true;
+        Some(session)
+    }
+
+    #[inline]
+    pub(super) fn is_in_early_data(&self) -> bool {
+        self.connection.is_in_early_data()
+    }
+
+    // DER encoded leaf certificate of the peer (available once established)
+    #[inline]
+    pub(super) fn get_peer_cert(&self) -> Option<&[u8]> {
+        self.connection.peer_cert()
+    }
+
+    // The server name this client connection is connecting to (or the SNI on a server connection)
+    #[inline]
+    pub(super) fn get_server_name(&self) -> Option<&str> {
+        self.connection.server_name()
+    }
+
+    pub(super) fn get_stats(&self) -> ConnectionStats {
+        let conn_stats = self.connection.stats();
+
+        let mut stats = ConnectionStats {
+            rtt: Duration::from_millis(0),
+            rtt_var: Duration::from_millis(0),
+            min_rtt: None,
+            cwnd: 0,
+            delivery_rate: 0,
+            packets_sent: conn_stats.sent,
+            packets_recv: conn_stats.recv,
+            packets_lost: conn_stats.lost,
+            packets_retransmitted: conn_stats.retrans,
+            bytes_sent: conn_stats.sent_bytes,
+            bytes_recv: conn_stats.recv_bytes,
+            bytes_lost: conn_stats.lost_bytes,
+            rt_segments_expired: self.rt_segments_expired,
+            max_datagram_size: self.connection.max_send_udp_payload_size(),
+        };
+
+        // Stats of the path currently used for sending (it changes after a migration)
+        if let Some(path_stats) = self.connection.path_stats().find(|ps| ps.active) {
+            stats.rtt = path_stats.rtt;
+            stats.rtt_var = path_stats.rttvar;
+            stats.min_rtt = path_stats.min_rtt;
+            stats.cwnd = path_stats.cwnd;
+            stats.delivery_rate = path_stats.delivery_rate;
+        }
+
+        stats
     }
 
     pub(super) fn main_stream_send(&mut self, data_vec: Vec<u8>) -> Result<usize, Error> {
         self.main_send_queue.push_back(SendBuffer::new(data_vec));
-        self.main_stream_send_next()
+        let bytes_sent = self.main_stream_send_next()?;
+        let low_water = self.send_low_water();
+        if low_water > 0 && self.queued_bytes(StreamClass::Main) >= low_water {
+            self.main_above_low_water = true;
+        }
+        Ok(bytes_sent)
     }
 
     // A returned Error::InvalidState indicates something went wrong with the read process
@@ -882,6 +1570,12 @@ impl Connection {
         if target_len > data_vec.len() {
             data_vec.resize(target_len, 0);
         }
+        if self.recv_paused {
+            self.main_recv.captured = 0;
+            self.main_recv.target = target_len;
+            self.main_recv.data = Some(data_vec);
+            return Ok(None);
+        }
         match self
             .connection
             .stream_recv(MAIN_STREAM_ID, &mut data_vec[..target_len])
@@ -928,9 +1622,19 @@ impl Connection {
             )?;
             // Increment Stream ID
             self.rt_send_stream_id += 4;
+            self.rt_send_stream_len = 0;
             self.rt_send_finished = false;
+            self.rt_priority_applied = false;
+            self.rt_send_deadline = None;
         }
         if let Some(data_vec) = data_vec_opt {
+            if self.rt_send_deadline.is_none() {
+                if let Some(schedule) = &self.send_schedule {
+                    self.rt_send_deadline = schedule
+                        .rt_max_age_opt
+                        .map(|max_age| Instant::now() + max_age);
+                }
+            }
             self.rt_send_queue.push_back(SendBuffer::new(data_vec));
         }
         if last_send_of_time_segment {
@@ -940,6 +1644,53 @@ impl Connection {
         self.rt_stream_send_next()
     }
 
+    // Resets real-time streams when their time segment is past the deadline
+    // Returns true if a stream was reset (so a RESET_STREAM needs to be sent)
+    pub(super) fn rt_stream_expire(&mut self, now: Instant) -> Result<bool, Error> {
+        let mut reset = false;
+        while let Some(&(stream_id, deadline, _)) = self.rt_handed_off.front() {
+            if deadline > now {
+                break;
+            }
+            self.rt_handed_off.pop_front();
+            // Only streams with unsent data are left so the reset drops data that never went out
+            match self
+                .connection
+                .stream_shutdown(stream_id, quiche::Shutdown::Write, stream_id)
+            {
+                Ok(_) => {
+                    self.rt_segments_expired += 1;
+                    reset = true;
+                }
+                Err(Error::Done) | Err(Error::InvalidStreamState(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        match self.rt_send_deadline {
+            Some(deadline) if deadline <= now => {
+                self.rt_send_queue.clear();
+                // Nothing was given to quiche yet when the stream doesn't exist
+                match self.connection.stream_shutdown(
+                    self.rt_send_stream_id,
+                    quiche::Shutdown::Write,
+                    self.rt_send_stream_id,
+                ) {
+                    Ok(_) | Err(Error::Done) | Err(Error::InvalidStreamState(_)) => {}
+                    Err(e) => return Err(e),
+                }
+                self.rt_send_stream_id += 4;
+                self.rt_send_stream_len = 0;
+                self.rt_send_finished = false;
+                self.rt_priority_applied = false;
+                self.rt_send_deadline = None;
+                self.rt_segments_expired += 1;
+                Ok(true)
+            }
+            _ => Ok(reset),
+        }
+    }
+
     // A returned Error::InvalidState indicates something went wrong with the read process
     pub(super) fn rt_stream_read(
         &mut self,
@@ -1035,46 +1786,99 @@ impl Connection {
         }
     }
 
-    pub(super) fn bkgd_stream_send(&mut self, data_vec: Vec<u8>) -> Result<usize, Error> {
-        self.bkgd_send_queue.push_back(SendBuffer::new(data_vec));
-        self.bkgd_stream_send_next()
+    // A returned Error::InvalidState indicates the peer does not support datagram frames
+    // A returned Error::BufferTooShort indicates the data is too large to fit in a single datagram
+    // A returned Error::Done indicates the datagram send queue is full
+    pub(super) fn rt_datagram_send(&mut self, data_vec: Vec<u8>) -> Result<(), Error> {
+        self.connection.dgram_send_vec(data_vec)
+    }
+
+    #[inline]
+    pub(super) fn rt_datagram_max_len(&self) -> Option<usize> {
+        self.connection.dgram_max_writable_len()
+    }
+
+    pub(super) fn bkgd_stream_send(
+        &mut self,
+        stream_id: u64,
+        data_vec: Vec<u8>,
+    ) -> Result<usize, Error> {
+        match self.find_bkgd_stream(stream_id) {
+            Some(bkgd_index) if !self.bkgd_streams[bkgd_index].finish_requested => {
+                self.bkgd_streams[bkgd_index]
+                    .send_queue
+                    .push_back(SendBuffer::new(data_vec));
+            }
+            _ => return Err(Error::InvalidStreamState(stream_id)),
+        }
+        let bytes_sent = self.bkgd_stream_send_next()?;
+        let low_water = self.send_low_water();
+        if low_water > 0 && self.queued_bytes(StreamClass::Background) >= low_water {
+            self.bkgd_above_low_water = true;
+        }
+        Ok(bytes_sent)
     }
 
     // A returned Error::InvalidState indicates something went wrong with the read process
-    // A returned Error::Done indicates the stream finished
+    // A returned Error::Done indicates the default background stream finished
     pub(super) fn bkgd_stream_read(
         &mut self,
+        stream_id: u64,
         mut data_vec: Vec<u8>,
         target_len: usize,
-    ) -> Result<Option<Vec<u8>>, Error> {
+    ) -> Result<Option<(Vec<u8>, usize)>, Error> {
+        let bkgd_index = match self.find_bkgd_stream(stream_id) {
+            Some(bkgd_index) if !self.bkgd_streams[bkgd_index].recv_finished => bkgd_index,
+            _ => return Ok(None), // Nothing more will arrive on this (possibly already removed) stream
+        };
         if target_len > data_vec.len() {
             data_vec.resize(target_len, 0);
         }
+        if self.recv_paused {
+            let bkgd_recv = &mut self.bkgd_streams[bkgd_index].recv;
+            bkgd_recv.captured = 0;
+            bkgd_recv.target = target_len;
+            bkgd_recv.data = Some(data_vec);
+            return Ok(None);
+        }
         match self
             .connection
-            .stream_recv(BACKGROUND_STREAM_ID, &mut data_vec[..target_len])
+            .stream_recv(stream_id, &mut data_vec[..target_len])
         {
             Ok((bytes_read, is_finished)) => {
-                if !is_finished {
-                    #[allow(clippy::comparison_chain)]
-                    if bytes_read == target_len {
-                        Ok(Some(data_vec))
-                    } else if bytes_read < target_len {
-                        self.bkgd_recv.captured = bytes_read;
-                        self.bkgd_recv.target = target_len;
-                        self.bkgd_recv.data = Some(data_vec);
-                        Ok(None)
-                    } else {
-                        Err(Error::InvalidState)
-                    }
+                if is_finished && stream_id == BACKGROUND_STREAM_ID {
+                    return Err(Error::Done);
+                }
+                #[allow(clippy::comparison_chain)]
+                let read_res = if bytes_read == target_len {
+                    Some((data_vec, target_len))
+                } else if is_finished && bytes_read > 0 && bytes_read < target_len {
+                    // The peer finished in the middle of a message so the partial message is handed over
+                    Some((data_vec, bytes_read))
+                } else if bytes_read < target_len {
+                    let bkgd_recv = &mut self.bkgd_streams[bkgd_index].recv;
+                    bkgd_recv.captured = bytes_read;
+                    bkgd_recv.target = target_len;
+                    bkgd_recv.data = Some(data_vec);
+                    None
                 } else {
-                    Err(Error::Done)
+                    return Err(Error::InvalidState);
+                };
+                if is_finished {
+                    // Might remove the stream so it is done last
+                    self.bkgd_stream_recv_finished(bkgd_index);
                 }
+                Ok(read_res)
             }
             Err(Error::Done) => {
-                self.bkgd_recv.captured = 0;
-                self.bkgd_recv.target = target_len;
-                self.bkgd_recv.data = Some(data_vec);
+                let bkgd_recv = &mut self.bkgd_streams[bkgd_index].recv;
+                bkgd_recv.captured = 0;
+                bkgd_recv.target = target_len;
+                bkgd_recv.data = Some(data_vec);
+                Ok(None)
+            }
+            Err(Error::StreamReset(_)) if stream_id != BACKGROUND_STREAM_ID => {
+                self.bkgd_stream_peer_reset(stream_id)?;
                 Ok(None)
             }
             Err(e) => Err(e),
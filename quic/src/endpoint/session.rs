@@ -0,0 +1,265 @@
+//Media Enhanced Swiftlet Quic Rust Library for Real-time Internet Communications
+//MIT License
+//Copyright (c) 2024 Jared Loewenthal
+//
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+//
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+//
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+// Client TLS session cache used for session resumption and 0-RTT early data
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+
+// Sessions keyed by server name (optionally persisted to a file)
+pub(super) struct SessionCache {
+    sessions: HashMap<String, Vec<u8>>,
+    writer_opt: Option<SessionWriter>,
+}
+
+impl SessionCache {
+    // Loads any previously persisted sessions when a file path is given
+    pub(super) fn new(path_opt: Option<PathBuf>) -> Self {
+        let mut sessions = HashMap::new();
+
+        if let Some(path) = &path_opt {
+            if let Ok(file_string) = std::fs::read_to_string(path) {
+                for line in file_string.lines() {
+                    let mut parts = line.split_whitespace();
+                    if let (Some(server_name), Some(session_hex)) = (parts.next(), parts.next()) {
+                        if let Some(session) = hex_to_bytes(session_hex) {
+                            sessions.insert(server_name.to_string(), session);
+                        }
+                    }
+                }
+            }
+        }
+
+        let writer_opt = path_opt.map(SessionWriter::new);
+        SessionCache {
+            sessions,
+            writer_opt,
+        }
+    }
+
+    #[inline]
+    pub(super) fn get(&self, server_name: &str) -> Option<&[u8]> {
+        self.sessions
+            .get(server_name)
+            .map(|session| session.as_slice())
+    }
+
+    // Replaces the session for the server name and has the file rewritten if there is one
+    // Only the file contents are created here so the event loop never waits on the file system
+    pub(super) fn insert(&mut self, server_name: &str, session: Vec<u8>) {
+        self.sessions.insert(server_name.to_string(), session);
+
+        if let Some(writer) = &self.writer_opt {
+            let mut contents = String::new();
+            for (name, session) in &self.sessions {
+                contents.push_str(name);
+                contents.push(' ');
+                contents.push_str(&bytes_to_hex(session));
+                contents.push('\n');
+            }
+            writer.write(contents);
+        }
+    }
+}
+
+// Writes the session file contents on its own thread
+// Contents that queue up while a write is in progress are skipped in favor of the newest
+struct SessionWriter {
+    contents_send_opt: Option<mpsc::Sender<String>>,
+    thread_handle_opt: Option<JoinHandle<()>>,
+}
+
+impl SessionWriter {
+    fn new(path: PathBuf) -> Self {
+        let (contents_send, contents_recv) = mpsc::channel::<String>();
+        let thread_handle = std::thread::spawn(move || {
+            while let Ok(mut contents) = contents_recv.recv() {
+                while let Ok(newer_contents) = contents_recv.try_recv() {
+                    contents = newer_contents;
+                }
+                write_session_file(&path, &contents);
+            }
+        });
+
+        SessionWriter {
+            contents_send_opt: Some(contents_send),
+            thread_handle_opt: Some(thread_handle),
+        }
+    }
+
+    #[inline]
+    fn write(&self, contents: String) {
+        if let Some(contents_send) = &self.contents_send_opt {
+            let _ = contents_send.send(contents);
+        }
+    }
+}
+
+impl Drop for SessionWriter {
+    // Lets the last queued contents get written before the Endpoint goes away
+    fn drop(&mut self) {
+        self.contents_send_opt = None;
+        if let Some(thread_handle) = self.thread_handle_opt.take() {
+            let _ = thread_handle.join();
+        }
+    }
+}
+
+// The file is written to a temporary file first and then renamed so a crash cannot leave it truncated
+fn write_session_file(path: &Path, contents: &str) {
+    let mut temp_path = path.to_path_buf().into_os_string();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    let _ = std::fs::remove_file(&temp_path); // The file mode only applies to a new file
+    if let Ok(mut file) = create_private_file(&temp_path) {
+        if file.write_all(contents.as_bytes()).is_ok() && file.sync_all().is_ok() {
+            let _ = std::fs::rename(&temp_path, path);
+        } else {
+            let _ = std::fs::remove_file(&temp_path);
+        }
+    }
+}
+
+// Session tickets are as sensitive as a private key so only the owner can read or write the file
+fn create_private_file(path: &Path) -> std::io::Result<std::fs::File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)
+}
+
+fn bytes_to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        hex.push_str(&format!("{:02x}", byte));
+    }
+    hex
+}
+
+fn hex_to_bytes(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(hex.len() / 2);
+    for pair in hex.as_bytes().chunks(2) {
+        let pair_str = std::str::from_utf8(pair).ok()?;
+        bytes.push(u8::from_str_radix(pair_str, 16).ok()?);
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A file in the temporary directory that is removed when dropped
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "swiftlet_session_{}_{}",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_file(&path);
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        let bytes = [0x00, 0x0f, 0xa5, 0xff];
+        assert_eq!(bytes_to_hex(&bytes), "000fa5ff");
+        assert_eq!(hex_to_bytes("000fa5ff"), Some(bytes.to_vec()));
+        assert_eq!(hex_to_bytes("000FA5FF"), Some(bytes.to_vec()));
+        assert_eq!(hex_to_bytes(""), Some(Vec::new()));
+    }
+
+    #[test]
+    fn invalid_hex_is_rejected() {
+        assert_eq!(hex_to_bytes("abc"), None);
+        assert_eq!(hex_to_bytes("zz"), None);
+    }
+
+    #[test]
+    fn sessions_persist_across_caches() {
+        let file = TempFile::new("persist");
+        let mut cache = SessionCache::new(Some(file.0.clone()));
+        assert_eq!(cache.get("example.com"), None);
+        cache.insert("example.com", vec![1, 2, 3]);
+        cache.insert("example.org", vec![4]);
+        cache.insert("example.com", vec![5, 6]);
+        assert_eq!(cache.get("example.com"), Some(&[5, 6][..]));
+        drop(cache); // Waits for the writer thread to finish
+
+        let reloaded = SessionCache::new(Some(file.0.clone()));
+        assert_eq!(reloaded.get("example.com"), Some(&[5, 6][..]));
+        assert_eq!(reloaded.get("example.org"), Some(&[4][..]));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&file.0).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        let file = TempFile::new("malformed");
+        std::fs::write(
+            &file.0,
+            "example.com 0102\nmissing-session\nexample.org 123\nexample.net zz\n\nexample.edu ff\n",
+        )
+        .unwrap();
+
+        let cache = SessionCache::new(Some(file.0.clone()));
+        assert_eq!(cache.get("example.com"), Some(&[1, 2][..]));
+        assert_eq!(cache.get("missing-session"), None);
+        assert_eq!(cache.get("example.org"), None);
+        assert_eq!(cache.get("example.net"), None);
+        assert_eq!(cache.get("example.edu"), Some(&[0xff][..]));
+    }
+
+    #[test]
+    fn memory_only_cache_without_a_file() {
+        let mut cache = SessionCache::new(None);
+        cache.insert("example.com", vec![1]);
+        assert_eq!(cache.get("example.com"), Some(&[1][..]));
+    }
+}
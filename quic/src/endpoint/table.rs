@@ -0,0 +1,316 @@
+//Media Enhanced Swiftlet Quic Rust Library for Real-time Internet Communications
+//MIT License
+//Copyright (c) 2024 Jared Loewenthal
+//
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+//
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+//
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+// Connection Table (a slab of connections)
+// A connection keeps the same slot (verified index) for its whole lifetime and both the application facing connection IDs
+// and the QUIC connection IDs (the DCIDs of received packets) map straight to the slot so lookups don't depend on the connection count
+// Until its handshake completes a server connection is also routed to by the original DCID the client picked
+// so retransmitted Initials and 0-RTT packets don't create duplicate connections
+
+use crate::endpoint::connection::Connection;
+use std::collections::HashMap;
+use std::ops::{Index, IndexMut};
+
+struct Slot {
+    connection: Connection,
+    dcids: Vec<Vec<u8>>, // Every QUIC connection ID that routes to this slot
+    handshake_dcid_opt: Option<Vec<u8>>, // Original DCID of the client that only routes here during the handshake
+    handshaking: bool,
+}
+
+pub(super) struct ConnectionTable {
+    slots: Vec<Option<Slot>>,
+    free_slots: Vec<usize>,
+    num_connections: usize,
+    num_handshaking: usize,
+    ids: HashMap<u64, usize>,
+    dcids: HashMap<Vec<u8>, usize>,
+}
+
+impl ConnectionTable {
+    pub(super) fn new() -> Self {
+        ConnectionTable {
+            slots: Vec::new(),
+            free_slots: Vec::new(),
+            num_connections: 0,
+            num_handshaking: 0,
+            ids: HashMap::new(),
+            dcids: HashMap::new(),
+        }
+    }
+
+    #[inline]
+    pub(super) fn num_connections(&self) -> usize {
+        self.num_connections
+    }
+
+    // Connections that haven't completed their handshake yet
+    #[inline]
+    pub(super) fn num_handshaking(&self) -> usize {
+        self.num_handshaking
+    }
+
+    // Slots are indexed from 0 up to this count but some of them can be empty
+    #[inline]
+    pub(super) fn slot_count(&self) -> usize {
+        self.slots.len()
+    }
+
+    #[inline]
+    pub(super) fn contains(&self, slot: usize) -> bool {
+        matches!(self.slots.get(slot), Some(Some(_)))
+    }
+
+    // Returns the slot of the new connection which is routed to by its current SCID
+    // (and by the handshake DCID when given until finish_handshake is called)
+    pub(super) fn insert(
+        &mut self,
+        connection: Connection,
+        handshake_dcid_opt: Option<&[u8]>,
+    ) -> usize {
+        let id = connection.get_id();
+        let scid = connection.current_scid().to_vec();
+        let new_slot = Slot {
+            connection,
+            dcids: Vec::with_capacity(4),
+            handshake_dcid_opt: None,
+            handshaking: true,
+        };
+
+        let slot = match self.free_slots.pop() {
+            Some(slot) => {
+                self.slots[slot] = Some(new_slot);
+                slot
+            }
+            None => {
+                self.slots.push(Some(new_slot));
+                self.slots.len() - 1
+            }
+        };
+        self.num_connections += 1;
+        self.num_handshaking += 1;
+        self.ids.insert(id, slot);
+        self.add_dcid(slot, &scid);
+        if let Some(handshake_dcid) = handshake_dcid_opt {
+            // After a Retry the client already uses the SCID so there is nothing more to route
+            if !self.dcids.contains_key(handshake_dcid) {
+                self.dcids.insert(handshake_dcid.to_vec(), slot);
+                if let Some(Some(slot_data)) = self.slots.get_mut(slot) {
+                    slot_data.handshake_dcid_opt = Some(handshake_dcid.to_vec());
+                }
+            }
+        }
+        slot
+    }
+
+    // Stops routing the handshake DCID to the slot and no longer counts the connection as handshaking
+    pub(super) fn finish_handshake(&mut self, slot: usize) {
+        if let Some(Some(slot_data)) = self.slots.get_mut(slot) {
+            if slot_data.handshaking {
+                slot_data.handshaking = false;
+                self.num_handshaking -= 1;
+            }
+            if let Some(handshake_dcid) = slot_data.handshake_dcid_opt.take() {
+                self.dcids.remove(&handshake_dcid);
+            }
+        }
+    }
+
+    // Removes the connection along with every connection ID that routes to it
+    pub(super) fn remove(&mut self, slot: usize) -> Option<Connection> {
+        let removed = self.slots.get_mut(slot)?.take()?;
+        for dcid in &removed.dcids {
+            self.dcids.remove(dcid);
+        }
+        if let Some(handshake_dcid) = &removed.handshake_dcid_opt {
+            self.dcids.remove(handshake_dcid);
+        }
+        if removed.handshaking {
+            self.num_handshaking -= 1;
+        }
+        self.ids.remove(&removed.connection.get_id());
+        self.free_slots.push(slot);
+        self.num_connections -= 1;
+        Some(removed.connection)
+    }
+
+    #[inline]
+    pub(super) fn find_id(&self, id: u64) -> Option<usize> {
+        self.ids.get(&id).copied()
+    }
+
+    #[inline]
+    pub(super) fn find_dcid(&self, dcid: &[u8]) -> Option<usize> {
+        self.dcids.get(dcid).copied()
+    }
+
+    // Routes packets with this DCID to the slot (a DCID that already routes somewhere else is left alone)
+    pub(super) fn add_dcid(&mut self, slot: usize, dcid: &[u8]) {
+        if let Some(Some(slot_data)) = self.slots.get_mut(slot) {
+            if !self.dcids.contains_key(dcid) {
+                self.dcids.insert(dcid.to_vec(), slot);
+                slot_data.dcids.push(dcid.to_vec());
+            }
+        }
+    }
+
+    // Stops routing packets with this DCID to the slot
+    pub(super) fn remove_dcid(&mut self, slot: usize, dcid: &[u8]) {
+        if let Some(Some(slot_data)) = self.slots.get_mut(slot) {
+            if let Some(position) = slot_data.dcids.iter().position(|id| id == dcid) {
+                slot_data.dcids.swap_remove(position);
+                self.dcids.remove(dcid);
+            }
+        }
+    }
+}
+
+impl Index<usize> for ConnectionTable {
+    type Output = Connection;
+
+    #[inline]
+    fn index(&self, slot: usize) -> &Connection {
+        match &self.slots[slot] {
+            Some(slot_data) => &slot_data.connection,
+            None => panic!("Connection slot {} is empty", slot),
+        }
+    }
+}
+
+impl IndexMut<usize> for ConnectionTable {
+    #[inline]
+    fn index_mut(&mut self, slot: usize) -> &mut Connection {
+        match &mut self.slots[slot] {
+            Some(slot_data) => &mut slot_data.connection,
+            None => panic!("Connection slot {} is empty", slot),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoint::admission::RecvBudget;
+    use crate::endpoint::SocketAddr;
+
+    fn test_connection(id: u64, scid_byte: u8) -> Connection {
+        let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+        let addr: SocketAddr = "127.0.0.1:4433".parse().unwrap();
+        Connection::new(
+            id,
+            addr,
+            Some("test"),
+            addr,
+            &[scid_byte; quiche::MAX_CONN_ID_LEN],
+            &mut config,
+            None,
+            None,
+            None,
+            RecvBudget::new(None, None),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn ids_route_to_the_slot() {
+        let mut table = ConnectionTable::new();
+        let slot = table.insert(test_connection(5, 1), None);
+        assert!(table.contains(slot));
+        assert_eq!(table.num_connections(), 1);
+        assert_eq!(table.find_id(5), Some(slot));
+        assert_eq!(table.find_dcid(&[1; quiche::MAX_CONN_ID_LEN]), Some(slot));
+        assert_eq!(table[slot].get_id(), 5);
+
+        table.add_dcid(slot, &[9; 4]);
+        assert_eq!(table.find_dcid(&[9; 4]), Some(slot));
+        table.remove_dcid(slot, &[9; 4]);
+        assert_eq!(table.find_dcid(&[9; 4]), None);
+    }
+
+    #[test]
+    fn dcids_are_never_taken_over() {
+        let mut table = ConnectionTable::new();
+        let first_slot = table.insert(test_connection(0, 1), None);
+        let second_slot = table.insert(test_connection(1, 2), None);
+        table.add_dcid(second_slot, &[1; quiche::MAX_CONN_ID_LEN]);
+        assert_eq!(
+            table.find_dcid(&[1; quiche::MAX_CONN_ID_LEN]),
+            Some(first_slot)
+        );
+
+        // Removing a DCID that routes elsewhere does nothing
+        table.remove_dcid(second_slot, &[1; quiche::MAX_CONN_ID_LEN]);
+        assert_eq!(
+            table.find_dcid(&[1; quiche::MAX_CONN_ID_LEN]),
+            Some(first_slot)
+        );
+    }
+
+    #[test]
+    fn removed_slots_get_reused() {
+        let mut table = ConnectionTable::new();
+        let first_slot = table.insert(test_connection(0, 1), None);
+        let second_slot = table.insert(test_connection(1, 2), None);
+        table.add_dcid(first_slot, &[7; 4]);
+
+        assert!(table.remove(first_slot).is_some());
+        assert!(table.remove(first_slot).is_none());
+        assert!(!table.contains(first_slot));
+        assert_eq!(table.num_connections(), 1);
+        assert_eq!(table.find_id(0), None);
+        assert_eq!(table.find_dcid(&[1; quiche::MAX_CONN_ID_LEN]), None);
+        assert_eq!(table.find_dcid(&[7; 4]), None);
+
+        let third_slot = table.insert(test_connection(2, 3), None);
+        assert_eq!(third_slot, first_slot);
+        assert_eq!(table.slot_count(), 2);
+        assert_eq!(table.find_id(1), Some(second_slot));
+    }
+
+    #[test]
+    fn handshake_dcid_routes_until_the_handshake_finishes() {
+        let mut table = ConnectionTable::new();
+        let slot = table.insert(test_connection(0, 1), Some(&[4; 8]));
+        let other_slot = table.insert(test_connection(1, 2), None);
+        assert_eq!(table.num_handshaking(), 2);
+        assert_eq!(table.find_dcid(&[4; 8]), Some(slot));
+
+        table.finish_handshake(slot);
+        table.finish_handshake(slot);
+        assert_eq!(table.num_handshaking(), 1);
+        assert_eq!(table.find_dcid(&[4; 8]), None);
+        assert_eq!(table.find_dcid(&[1; quiche::MAX_CONN_ID_LEN]), Some(slot));
+
+        // A connection that ends during its handshake no longer counts
+        table.remove(other_slot);
+        assert_eq!(table.num_handshaking(), 0);
+    }
+
+    #[test]
+    fn handshake_dcid_is_dropped_with_the_connection() {
+        let mut table = ConnectionTable::new();
+        let slot = table.insert(test_connection(0, 1), Some(&[4; 8]));
+        table.remove(slot);
+        assert_eq!(table.find_dcid(&[4; 8]), None);
+        assert_eq!(table.num_handshaking(), 0);
+    }
+}
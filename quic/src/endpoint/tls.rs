@@ -0,0 +1,473 @@
+//Media Enhanced Swiftlet Quic Rust Library for Real-time Internet Communications
+//MIT License
+//Copyright (c) 2024 Jared Loewenthal
+//
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+//
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+//
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+// TLS certificates and private keys used by an Endpoint (from files or from memory)
+
+use crate::endpoint::Error;
+
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use boring::asn1::Asn1Time;
+use boring::bn::{BigNum, MsbOption};
+use boring::ec::{EcGroup, EcKey};
+use boring::error::ErrorStack;
+use boring::hash::MessageDigest;
+use boring::nid::Nid;
+use boring::pkey::PKey;
+use boring::ssl::{NameType, SslContextBuilder, SslFiletype, SslMethod, SslVerifyMode};
+use boring::x509::extension::SubjectAlternativeName;
+use boring::x509::{X509NameBuilder, X509StoreContext, X509StoreContextRef, X509};
+
+// Number of days a generated self-signed certificate is valid for
+const SELF_SIGNED_VALID_DAYS: u32 = 30;
+
+// Common locations of the system certificate authority bundle (checked in order)
+const SYSTEM_CA_BUNDLES: [&str; 6] = [
+    "/etc/ssl/certs/ca-certificates.crt", // Debian / Ubuntu / Arch / Gentoo
+    "/etc/pki/tls/certs/ca-bundle.crt",   // Fedora / RHEL
+    "/etc/ssl/ca-bundle.pem",             // OpenSUSE
+    "/etc/pki/tls/cacert.pem",            // OpenELEC
+    "/etc/ssl/cert.pem",                  // Alpine / macOS
+    "/usr/local/etc/openssl/cert.pem",    // Homebrew
+];
+
+/// A SHA-256 certificate fingerprint (of the DER encoded certificate)
+pub type Fingerprint = [u8; 32];
+
+/// The encoding of certificate or private key data held in memory
+#[derive(Debug, Clone, Copy)]
+pub enum Encoding {
+    /// PEM (Base64 text) encoding which can hold a whole certificate chain
+    Pem,
+    /// DER (binary) encoding which holds a single certificate
+    Der,
+}
+
+/// The certificate (chain) and private key that an Endpoint identifies itself with
+///
+/// Servers always need an identity while clients only need one when the server requires client certificates.
+#[derive(Clone)]
+pub enum Identity {
+    /// PEM encoded certificate chain and private key files
+    PemFiles {
+        /// Location of the certificate chain file
+        cert_path: PathBuf,
+        /// Location of the private key file
+        pkey_path: PathBuf,
+    },
+    /// Certificate (chain) and private key data held in memory
+    Memory {
+        /// The encoding of both the certificate and private key data
+        encoding: Encoding,
+        /// The certificate (chain) data
+        cert_data: Vec<u8>,
+        /// The private key data
+        pkey_data: Vec<u8>,
+    },
+}
+
+impl Identity {
+    /// Get the SHA-256 fingerprint of the leaf certificate
+    ///
+    /// Useful for giving to clients that pin the server certificate.
+    pub fn get_fingerprint(&self) -> Option<Fingerprint> {
+        let leaf_cert = match self {
+            Identity::PemFiles { cert_path, .. } => {
+                let cert_data = std::fs::read(cert_path).ok()?;
+                X509::from_pem(&cert_data).ok()?
+            }
+            Identity::Memory {
+                encoding,
+                cert_data,
+                ..
+            } => certs_from_data(*encoding, cert_data)
+                .ok()?
+                .into_iter()
+                .next()?,
+        };
+        Some(certificate_fingerprint(&leaf_cert.to_der().ok()?))
+    }
+}
+
+/// How a client Endpoint verifies the certificate of the server it connects to
+#[derive(Clone)]
+pub enum ServerVerification {
+    /// Trust the certificate authorities of the operating system
+    ///
+    /// The SSL_CERT_FILE environment variable can be used to point to a different bundle.
+    SystemRoots,
+    /// Trust the certificate authority (or self-signed) certificates in a PEM file (a CA bundle)
+    CaFile(PathBuf),
+    /// Trust the certificate authority (or self-signed) certificates held in memory
+    CaMemory {
+        /// The encoding of the certificate data
+        encoding: Encoding,
+        /// The certificate data
+        cert_data: Vec<u8>,
+    },
+    /// Only trust a server certificate that matches one of these SHA-256 fingerprints (pinning)
+    ///
+    /// The certificate chain and server name are not checked so self-signed certificates can be used.
+    Fingerprints(Vec<Fingerprint>),
+    /// Trust the first certificate seen for a server name and remember it in a known hosts file
+    ///
+    /// Every later connection to that server name must present the same certificate.
+    /// Each line of the file is a server name followed by a hex SHA-256 fingerprint.
+    KnownHosts(PathBuf),
+}
+
+impl ServerVerification {
+    // True when the TLS handshake itself should verify the certificate chain and server name
+    pub(super) fn verifies_chain(&self) -> bool {
+        !matches!(
+            self,
+            ServerVerification::Fingerprints(_) | ServerVerification::KnownHosts(_)
+        )
+    }
+}
+
+/// How a server Endpoint verifies the certificates of its clients (mutual TLS)
+///
+/// Clients that do not present a trusted certificate are disconnected before connection_started gets called.
+#[derive(Clone)]
+pub enum ClientVerification {
+    /// Trust client certificates issued by the certificate authorities in a PEM file
+    CaFile(PathBuf),
+    /// Trust client certificates issued by the certificate authorities held in memory
+    CaMemory {
+        /// The encoding of the certificate data
+        encoding: Encoding,
+        /// The certificate data
+        cert_data: Vec<u8>,
+    },
+    /// Only trust client certificates that match one of these SHA-256 fingerprints (allow-list)
+    ///
+    /// The certificate chain is not checked so self-signed client certificates can be used.
+    Fingerprints(Vec<Fingerprint>),
+}
+
+impl ClientVerification {
+    // True when the TLS handshake itself should verify the certificate chain
+    pub(super) fn verifies_chain(&self) -> bool {
+        !matches!(self, ClientVerification::Fingerprints(_))
+    }
+}
+
+/// The verified certificate identity of a peer
+#[derive(Debug, Clone)]
+pub struct PeerIdentity {
+    /// The SHA-256 fingerprint of the peer leaf certificate
+    pub fingerprint: Fingerprint,
+    /// The subject name of the peer leaf certificate (like "CN=name, O=organization")
+    pub subject: String,
+    /// The DER encoded peer leaf certificate
+    pub cert_der: Vec<u8>,
+}
+
+impl PeerIdentity {
+    pub(super) fn from_der(cert_der: &[u8]) -> Self {
+        let mut subject = String::new();
+        if let Ok(cert) = X509::from_der(cert_der) {
+            for entry in cert.subject_name().entries() {
+                let key = entry.object().nid().short_name().unwrap_or("?");
+                if let Ok(value) = entry.data().as_utf8() {
+                    if !subject.is_empty() {
+                        subject.push_str(", ");
+                    }
+                    subject.push_str(&format!("{}={}", key, value));
+                }
+            }
+        }
+
+        PeerIdentity {
+            fingerprint: certificate_fingerprint(cert_der),
+            subject,
+            cert_der: cert_der.to_vec(),
+        }
+    }
+}
+
+/// Get the SHA-256 fingerprint of a DER encoded certificate
+pub fn certificate_fingerprint(cert_der: &[u8]) -> Fingerprint {
+    let mut fingerprint = [0; 32];
+    fingerprint.copy_from_slice(ring::digest::digest(&ring::digest::SHA256, cert_der).as_ref());
+    fingerprint
+}
+
+/// Convert a fingerprint into a lowercase hex string
+pub fn fingerprint_to_hex(fingerprint: &Fingerprint) -> String {
+    let mut hex = String::with_capacity(64);
+    for byte in fingerprint {
+        hex.push_str(&format!("{:02x}", byte));
+    }
+    hex
+}
+
+/// Parse a hex string (with optional ':' separators) into a fingerprint
+pub fn fingerprint_from_hex(hex: &str) -> Option<Fingerprint> {
+    let digits: Vec<u8> = hex.bytes().filter(|c| *c != b':').collect();
+    if digits.len() != 64 {
+        return None;
+    }
+
+    let mut fingerprint = [0; 32];
+    for (byte, pair) in fingerprint.iter_mut().zip(digits.chunks(2)) {
+        let pair_str = std::str::from_utf8(pair).ok()?;
+        *byte = u8::from_str_radix(pair_str, 16).ok()?;
+    }
+    Some(fingerprint)
+}
+
+// Checks the fingerprint against the known hosts file and remembers it on the first use
+fn known_hosts_check(path: &Path, server_name: &str, fingerprint: &Fingerprint) -> bool {
+    if let Ok(file) = std::fs::File::open(path) {
+        for line in std::io::BufReader::new(file).lines().map_while(Result::ok) {
+            let mut parts = line.split_whitespace();
+            if parts.next() == Some(server_name) {
+                return parts.next().and_then(fingerprint_from_hex) == Some(*fingerprint);
+            }
+        }
+    }
+
+    // First use so trust and remember it (still trusted if the file cannot be written)
+    if let Ok(mut file) = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+    {
+        let _ = writeln!(file, "{} {}", server_name, fingerprint_to_hex(fingerprint));
+    }
+    true
+}
+
+// Checks the server leaf certificate against the pinned fingerprints or the known hosts file
+fn verify_server_cert(
+    verification: &ServerVerification,
+    server_name: Option<&str>,
+    cert_der: &[u8],
+) -> bool {
+    let fingerprint = certificate_fingerprint(cert_der);
+    match verification {
+        ServerVerification::Fingerprints(fingerprints) => fingerprints.contains(&fingerprint),
+        ServerVerification::KnownHosts(path) => {
+            known_hosts_check(path, server_name.unwrap_or(""), &fingerprint)
+        }
+        _ => true, // Verified by the TLS handshake itself
+    }
+}
+
+// Called by the TLS handshake for every certificate of the server chain
+// Only the leaf certificate matters for pinning so the rest of the chain (and its errors) is ignored
+fn verify_server_cert_callback(
+    verification: &ServerVerification,
+    x509_ctx: &mut X509StoreContextRef,
+) -> bool {
+    if x509_ctx.error_depth() > 0 {
+        return true;
+    }
+
+    let cert_der = match x509_ctx.current_cert().map(|cert| cert.to_der()) {
+        Some(Ok(cert_der)) => cert_der,
+        _ => return false,
+    };
+    let server_name = match X509StoreContext::ssl_idx() {
+        Ok(ssl_idx) => x509_ctx
+            .ex_data(ssl_idx)
+            .and_then(|ssl| ssl.servername(NameType::HOST_NAME)),
+        Err(_) => None,
+    };
+    verify_server_cert(verification, server_name, &cert_der)
+}
+
+// Verification done after the handshake since a client certificate is optional for the TLS handshake
+pub(super) fn verify_client_cert(
+    verification: &ClientVerification,
+    cert_der_opt: Option<&[u8]>,
+) -> bool {
+    match cert_der_opt {
+        Some(cert_der) => match verification {
+            ClientVerification::Fingerprints(fingerprints) => {
+                fingerprints.contains(&certificate_fingerprint(cert_der))
+            }
+            _ => true, // Already verified during the handshake
+        },
+        None => false,
+    }
+}
+
+/// Generate an ephemeral self-signed certificate and private key for the given server name.
+///
+/// The certificate uses a new P-256 key, has the server name as its common name and subject alternative name,
+/// and is only valid for a short time. The returned identity is PEM encoded so its cert_data can be saved
+/// and given to clients that need to verify this server.
+pub fn generate_self_signed(server_name: &str) -> Result<Identity, Error> {
+    match create_self_signed(server_name) {
+        Ok((cert_data, pkey_data)) => Ok(Identity::Memory {
+            encoding: Encoding::Pem,
+            cert_data,
+            pkey_data,
+        }),
+        Err(_) => Err(Error::CertificateCreation),
+    }
+}
+
+fn create_self_signed(server_name: &str) -> Result<(Vec<u8>, Vec<u8>), ErrorStack> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    let pkey = PKey::from_ec_key(EcKey::generate(&group)?)?;
+
+    let mut name_builder = X509NameBuilder::new()?;
+    name_builder.append_entry_by_nid(Nid::COMMONNAME, server_name)?;
+    let name = name_builder.build();
+
+    let mut serial = BigNum::new()?;
+    serial.rand(127, MsbOption::MAYBE_ZERO, false)?;
+
+    let mut cert_builder = X509::builder()?;
+    cert_builder.set_version(2)?; // X509 v3
+    cert_builder.set_serial_number(&serial.to_asn1_integer()?)?;
+    cert_builder.set_subject_name(&name)?;
+    cert_builder.set_issuer_name(&name)?;
+    cert_builder.set_pubkey(&pkey)?;
+    cert_builder.set_not_before(&Asn1Time::days_from_now(0)?)?;
+    cert_builder.set_not_after(&Asn1Time::days_from_now(SELF_SIGNED_VALID_DAYS)?)?;
+
+    let mut alt_name = SubjectAlternativeName::new();
+    if server_name.parse::<std::net::IpAddr>().is_ok() {
+        alt_name.ip(server_name);
+    } else {
+        alt_name.dns(server_name);
+    }
+    let alt_name_ext = alt_name.build(&cert_builder.x509v3_context(None, None))?;
+    cert_builder.append_extension(alt_name_ext)?;
+
+    cert_builder.sign(&pkey, MessageDigest::sha256())?;
+    let cert = cert_builder.build();
+
+    Ok((cert.to_pem()?, pkey.private_key_to_pem_pkcs8()?))
+}
+
+fn certs_from_data(encoding: Encoding, cert_data: &[u8]) -> Result<Vec<X509>, ErrorStack> {
+    match encoding {
+        Encoding::Pem => X509::stack_from_pem(cert_data),
+        Encoding::Der => Ok(vec![X509::from_der(cert_data)?]),
+    }
+}
+
+// Creates the TLS context that the quiche Config is built on top of
+pub(super) fn create_ssl_context_builder(
+    identity_opt: Option<&Identity>,
+    verification_opt: Option<&ServerVerification>,
+    client_verification_opt: Option<&ClientVerification>,
+) -> Result<SslContextBuilder, ErrorStack> {
+    let mut builder = SslContextBuilder::new(SslMethod::tls())?;
+
+    if let Some(identity) = identity_opt {
+        match identity {
+            Identity::PemFiles {
+                cert_path,
+                pkey_path,
+            } => {
+                builder.set_certificate_chain_file(cert_path)?;
+                builder.set_private_key_file(pkey_path, SslFiletype::PEM)?;
+            }
+            Identity::Memory {
+                encoding,
+                cert_data,
+                pkey_data,
+            } => {
+                let mut certs = certs_from_data(*encoding, cert_data)?.into_iter();
+                if let Some(leaf_cert) = certs.next() {
+                    builder.set_certificate(&leaf_cert)?;
+                }
+                for chain_cert in certs {
+                    builder.add_extra_chain_cert(chain_cert)?;
+                }
+
+                let pkey = match encoding {
+                    Encoding::Pem => PKey::private_key_from_pem(pkey_data)?,
+                    Encoding::Der => PKey::private_key_from_der(pkey_data)?,
+                };
+                builder.set_private_key(&pkey)?;
+            }
+        }
+        builder.check_private_key()?;
+    }
+
+    if let Some(verification) = verification_opt {
+        match verification {
+            ServerVerification::SystemRoots => {
+                let bundle_opt = match std::env::var_os("SSL_CERT_FILE") {
+                    Some(cert_file) => Some(PathBuf::from(cert_file)),
+                    None => SYSTEM_CA_BUNDLES
+                        .iter()
+                        .map(PathBuf::from)
+                        .find(|path| path.exists()),
+                };
+                match bundle_opt {
+                    Some(bundle_path) => builder.set_ca_file(bundle_path)?,
+                    None => builder.set_default_verify_paths()?,
+                }
+            }
+            ServerVerification::CaFile(ca_path) => {
+                builder.set_ca_file(ca_path)?;
+            }
+            ServerVerification::CaMemory {
+                encoding,
+                cert_data,
+            } => {
+                for cert in certs_from_data(*encoding, cert_data)? {
+                    builder.cert_store_mut().add_cert(cert)?;
+                }
+            }
+            ServerVerification::Fingerprints(_) | ServerVerification::KnownHosts(_) => {
+                // Checked during the handshake so an untrusted server never gets to complete it
+                let pinned_verification = verification.clone();
+                builder.set_verify_callback(SslVerifyMode::PEER, move |_, x509_ctx| {
+                    verify_server_cert_callback(&pinned_verification, x509_ctx)
+                });
+            }
+        }
+    }
+
+    if let Some(client_verification) = client_verification_opt {
+        match client_verification {
+            ClientVerification::CaFile(ca_path) => {
+                builder.set_ca_file(ca_path)?;
+            }
+            ClientVerification::CaMemory {
+                encoding,
+                cert_data,
+            } => {
+                for cert in certs_from_data(*encoding, cert_data)? {
+                    builder.cert_store_mut().add_cert(cert)?;
+                }
+            }
+            ClientVerification::Fingerprints(_) => {
+                // Request a client certificate but accept any chain since the fingerprint is checked later
+                builder.set_verify_callback(SslVerifyMode::PEER, |_, _| true);
+            }
+        }
+    }
+
+    Ok(builder)
+}
@@ -0,0 +1,599 @@
+//Media Enhanced Swiftlet Quic Rust Library for Real-time Internet Communications
+//MIT License
+//Copyright (c) 2024 Jared Loewenthal
+//
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+//
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+//
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+// Datagram Transports that an Endpoint can send and receive QUIC packets with
+
+use crate::endpoint::SocketAddr;
+
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// A datagram transport that an Endpoint sends and receives QUIC packets with.
+///
+/// The operating system UDP sockets implement this trait internally and are used by the
+/// regular Endpoint constructors. Other implementations (like the in-memory virtual link in this module)
+/// can be given to the Endpoint "with_transport" constructors instead.
+pub trait Transport {
+    /// Get the local address that this transport is bound to.
+    fn get_local_address(&self) -> Option<SocketAddr>;
+
+    /// Sleep the thread until there is data to be received or the timeout duration has passed.
+    ///
+    /// Returns true if there is data to be received.
+    /// It should capture "missed" events between calls and return without delay in this case.
+    fn sleep_till_next_recv(&mut self, timeout_duration: Duration) -> bool;
+
+    /// Get the next received datagram and the address it came from.
+    ///
+    /// Returns None when there is nothing (more) to receive right now.
+    fn get_next_recv(&mut self) -> Option<(&mut [u8], SocketAddr)>;
+
+    /// Called when the Endpoint is done with the data from the last get_next_recv call.
+    fn done_with_recv(&mut self);
+
+    /// Get the buffer that the next datagram to send should be written into.
+    ///
+    /// Datagrams are never larger than the buffer, so it should fit the Config max_datagram_size (if any).
+    fn get_next_send(&mut self) -> &mut [u8];
+
+    /// Send the first data_len bytes of the get_next_send buffer to the given address.
+    ///
+    /// A transport may hold the datagram back to send it together with the following ones.
+    fn done_with_send(&mut self, address: SocketAddr, data_len: usize);
+
+    /// Send any datagrams that are being held back from done_with_send calls.
+    ///
+    /// The Endpoint calls this after each burst of sends and before sleeping.
+    fn flush_sends(&mut self) {}
+
+    /// Create a waker that can wake this transport up from another thread.
+    ///
+    /// A woken up sleep_till_next_recv call returns false unless there is also data to be received.
+    /// Transports that return None (the default) only wake up at their sleep timeouts.
+    fn create_waker(&mut self) -> Option<Arc<dyn TransportWaker>> {
+        None
+    }
+}
+
+/// Wakes up a transport from another thread while it sleeps in sleep_till_next_recv.
+pub trait TransportWaker: Send + Sync {
+    /// Make the current sleep_till_next_recv call (or the next one when the transport is not sleeping) return right away.
+    fn wake(&self);
+}
+
+/// The simulated network conditions of one direction of a virtual link.
+#[derive(Clone, Debug)]
+pub struct LinkConfig {
+    /// The fixed one-way delay added to every datagram.
+    pub latency: Duration,
+
+    /// The maximum additional random one-way delay added to every datagram.
+    ///
+    /// Each datagram gets a uniformly distributed extra delay between zero and this value,
+    /// which can reorder datagrams that are sent close together.
+    pub jitter: Duration,
+
+    /// The probability (0.0 to 1.0) that a datagram is lost.
+    pub loss_probability: f64,
+
+    /// The probability (0.0 to 1.0) that a datagram gets held back by the reorder_delay.
+    pub reorder_probability: f64,
+
+    /// The extra delay given to datagrams that get reordered.
+    pub reorder_delay: Duration,
+
+    /// The link bandwidth in bits per second.
+    ///
+    /// Datagrams are serialized onto the link one after another so a burst of datagrams
+    /// will queue up behind each other. None represents a link with unlimited bandwidth.
+    pub bandwidth_bps: Option<u64>,
+
+    /// The seed of the pseudo random number generator used for the loss, jitter and reordering.
+    ///
+    /// Using the same seed with the same send pattern reproduces the same network conditions.
+    pub seed: u64,
+}
+
+impl Default for LinkConfig {
+    // A perfect link with no delay, loss or bandwidth limits
+    fn default() -> Self {
+        LinkConfig {
+            latency: Duration::from_millis(0),
+            jitter: Duration::from_millis(0),
+            loss_probability: 0.0,
+            reorder_probability: 0.0,
+            reorder_delay: Duration::from_millis(0),
+            bandwidth_bps: None,
+            seed: 1,
+        }
+    }
+}
+
+/// Statistics of the datagrams that were sent towards one side of a virtual link.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LinkStats {
+    /// Number of datagrams sent towards this side
+    pub sent: u64,
+    /// Number of datagrams that were dropped by the simulated loss
+    pub lost: u64,
+    /// Number of datagrams that were held back by the simulated reordering
+    pub reordered: u64,
+    /// Number of datagrams that were sent to an address that is not on the link
+    pub unroutable: u64,
+}
+
+/// Create a pair of connected in-memory virtual sockets.
+///
+/// Everything sent from the first socket to the second address arrives at the second socket
+/// (and vice versa) under the simulated network conditions of the link configs.
+/// This allows a server Endpoint and a client Endpoint to run (in separate threads) within a single process
+/// without any real network sockets.
+pub fn virtual_link_pair(
+    first_addr: SocketAddr,
+    second_addr: SocketAddr,
+    first_to_second: LinkConfig,
+    second_to_first: LinkConfig,
+) -> (VirtualSocket, VirtualSocket) {
+    let first_inbox = Arc::new(Inbox::new(second_to_first));
+    let second_inbox = Arc::new(Inbox::new(first_to_second));
+
+    let first = VirtualSocket::new(
+        first_addr,
+        second_addr,
+        first_inbox.clone(),
+        second_inbox.clone(),
+    );
+    let second = VirtualSocket::new(second_addr, first_addr, second_inbox, first_inbox);
+
+    (first, second)
+}
+
+/// One side of an in-memory virtual link created with virtual_link_pair.
+pub struct VirtualSocket {
+    local_addr: SocketAddr,
+    peer_addr: SocketAddr,
+    inbox: Arc<Inbox>,
+    peer_inbox: Arc<Inbox>,
+    recv_packet: Option<InFlightPacket>,
+    send_data: [u8; super::udp::MAX_UDP_LENGTH],
+}
+
+impl VirtualSocket {
+    fn new(
+        local_addr: SocketAddr,
+        peer_addr: SocketAddr,
+        inbox: Arc<Inbox>,
+        peer_inbox: Arc<Inbox>,
+    ) -> Self {
+        VirtualSocket {
+            local_addr,
+            peer_addr,
+            inbox,
+            peer_inbox,
+            recv_packet: None,
+            send_data: [0; super::udp::MAX_UDP_LENGTH],
+        }
+    }
+
+    /// Get a monitor for the datagrams travelling towards this socket.
+    ///
+    /// The monitor stays usable after this socket has been given to an Endpoint.
+    pub fn get_monitor(&self) -> LinkMonitor {
+        LinkMonitor {
+            inbox: self.inbox.clone(),
+        }
+    }
+}
+
+/// Monitors (and adjusts) the direction of a virtual link that travels towards a VirtualSocket.
+#[derive(Clone)]
+pub struct LinkMonitor {
+    inbox: Arc<Inbox>,
+}
+
+impl LinkMonitor {
+    /// Get the statistics of the datagrams that were sent towards the monitored socket.
+    pub fn get_stats(&self) -> LinkStats {
+        self.inbox.lock().stats
+    }
+
+    /// Change the simulated network conditions for any datagrams sent from now on.
+    ///
+    /// The pseudo random number generator is reseeded with the new config seed.
+    pub fn set_config(&self, config: LinkConfig) {
+        let mut state = self.inbox.lock();
+        state.rng = LinkRng::new(config.seed);
+        state.config = config;
+    }
+}
+
+impl Transport for VirtualSocket {
+    fn get_local_address(&self) -> Option<SocketAddr> {
+        Some(self.local_addr)
+    }
+
+    fn sleep_till_next_recv(&mut self, timeout_duration: Duration) -> bool {
+        let deadline = Instant::now() + timeout_duration;
+        let mut state = self.inbox.lock();
+        loop {
+            let now = Instant::now();
+            let wake_instant = match state.packets.peek() {
+                Some(packet) => {
+                    if packet.deliver_instant <= now {
+                        return true;
+                    }
+                    packet.deliver_instant.min(deadline)
+                }
+                None => deadline,
+            };
+            if state.woken {
+                state.woken = false;
+                return false;
+            }
+            if now >= deadline {
+                return false;
+            }
+            state = match self
+                .inbox
+                .condvar
+                .wait_timeout(state, wake_instant.duration_since(now))
+            {
+                Ok((s, _)) => s,
+                Err(poisoned) => poisoned.into_inner().0,
+            };
+        }
+    }
+
+    fn get_next_recv(&mut self) -> Option<(&mut [u8], SocketAddr)> {
+        {
+            let mut state = self.inbox.lock();
+            match state.packets.peek() {
+                Some(packet) if packet.deliver_instant <= Instant::now() => {
+                    self.recv_packet = state.packets.pop();
+                }
+                _ => return None,
+            }
+        }
+        self.recv_packet
+            .as_mut()
+            .map(|packet| (packet.data.as_mut_slice(), packet.from_addr))
+    }
+
+    fn done_with_recv(&mut self) {
+        self.recv_packet = None;
+    }
+
+    fn get_next_send(&mut self) -> &mut [u8] {
+        &mut self.send_data
+    }
+
+    fn done_with_send(&mut self, address: SocketAddr, data_len: usize) {
+        let mut state = self.peer_inbox.lock();
+        if address == self.peer_addr {
+            state.transmit(self.local_addr, &self.send_data[..data_len]);
+            self.peer_inbox.condvar.notify_all();
+        } else {
+            state.stats.unroutable += 1;
+        }
+    }
+
+    fn create_waker(&mut self) -> Option<Arc<dyn TransportWaker>> {
+        Some(Arc::new(VirtualWaker {
+            inbox: self.inbox.clone(),
+        }))
+    }
+}
+
+// Wakes up the side of the virtual link that owns the inbox
+struct VirtualWaker {
+    inbox: Arc<Inbox>,
+}
+
+impl TransportWaker for VirtualWaker {
+    fn wake(&self) {
+        self.inbox.lock().woken = true;
+        self.inbox.condvar.notify_all();
+    }
+}
+
+// Datagrams travelling towards one side of the virtual link
+struct Inbox {
+    state: Mutex<InboxState>,
+    condvar: Condvar,
+}
+
+impl Inbox {
+    fn new(config: LinkConfig) -> Self {
+        Inbox {
+            state: Mutex::new(InboxState::new(config)),
+            condvar: Condvar::new(),
+        }
+    }
+
+    #[inline]
+    fn lock(&self) -> MutexGuard<'_, InboxState> {
+        match self.state.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+struct InboxState {
+    config: LinkConfig,
+    rng: LinkRng,
+    packets: BinaryHeap<InFlightPacket>,
+    next_order: u64,
+    link_free_instant: Instant,
+    stats: LinkStats,
+    woken: bool, // Set by a waker until the next sleep sees it
+}
+
+impl InboxState {
+    fn new(config: LinkConfig) -> Self {
+        let rng = LinkRng::new(config.seed);
+        InboxState {
+            config,
+            rng,
+            packets: BinaryHeap::new(),
+            next_order: 0,
+            link_free_instant: Instant::now(),
+            stats: LinkStats::default(),
+            woken: false,
+        }
+    }
+
+    fn transmit(&mut self, from_addr: SocketAddr, data: &[u8]) {
+        self.stats.sent += 1;
+
+        // Random values are always drawn in the same order to keep the simulation reproducible
+        let lost = self.rng.chance(self.config.loss_probability);
+        let jitter = self.config.jitter.mul_f64(self.rng.next_f64());
+        let reordered = self.rng.chance(self.config.reorder_probability);
+
+        let now = Instant::now();
+        let mut depart_instant = now;
+        if let Some(bandwidth_bps) = self.config.bandwidth_bps {
+            // The datagram is serialized onto the link after any datagrams that are ahead of it
+            if self.link_free_instant > depart_instant {
+                depart_instant = self.link_free_instant;
+            }
+            let serialize_nanos =
+                ((data.len() as u128) * 8 * 1_000_000_000) / (bandwidth_bps.max(1) as u128);
+            depart_instant += Duration::from_nanos(serialize_nanos as u64);
+            self.link_free_instant = depart_instant;
+        }
+
+        if lost {
+            self.stats.lost += 1;
+            return;
+        }
+
+        let mut deliver_instant = depart_instant + self.config.latency + jitter;
+        if reordered {
+            deliver_instant += self.config.reorder_delay;
+            self.stats.reordered += 1;
+        }
+
+        self.packets.push(InFlightPacket {
+            deliver_instant,
+            order: self.next_order,
+            from_addr,
+            data: Vec::from(data),
+        });
+        self.next_order += 1;
+    }
+}
+
+// A datagram that is delivered to the receiving side only AFTER an Instant is reached
+struct InFlightPacket {
+    deliver_instant: Instant,
+    order: u64,
+    from_addr: SocketAddr,
+    data: Vec<u8>,
+}
+
+// In order to find the next datagram to deliver (lowest Instant, then earliest sent) the Ord trait is implemented
+impl Ord for InFlightPacket {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        other
+            .deliver_instant
+            .cmp(&self.deliver_instant)
+            .then_with(|| other.order.cmp(&self.order))
+    }
+}
+
+// The Ord trait requires PartialOrd and Eq be implemented as well
+impl PartialOrd for InFlightPacket {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Eq for InFlightPacket {}
+
+// The Eq trait requires PartialEq be implemented as well
+impl PartialEq for InFlightPacket {
+    fn eq(&self, other: &Self) -> bool {
+        self.deliver_instant == other.deliver_instant && self.order == other.order
+    }
+}
+
+// Small xorshift64* pseudo random number generator so that the link simulation is seedable
+struct LinkRng {
+    state: u64,
+}
+
+impl LinkRng {
+    fn new(seed: u64) -> Self {
+        // The xorshift state must never be zero
+        let state = if seed == 0 {
+            0x9E37_79B9_7F4A_7C15
+        } else {
+            seed
+        };
+        LinkRng { state }
+    }
+
+    #[inline]
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // Uniformly distributed value from 0.0 (inclusive) to 1.0 (exclusive)
+    #[inline]
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    #[inline]
+    fn chance(&mut self, probability: f64) -> bool {
+        self.next_f64() < probability
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIRST_ADDR: &str = "127.0.0.1:5001";
+    const SECOND_ADDR: &str = "127.0.0.1:5002";
+
+    fn link_pair(first_to_second: LinkConfig) -> (VirtualSocket, VirtualSocket) {
+        virtual_link_pair(
+            FIRST_ADDR.parse().unwrap(),
+            SECOND_ADDR.parse().unwrap(),
+            first_to_second,
+            LinkConfig::default(),
+        )
+    }
+
+    fn send_numbered(socket: &mut VirtualSocket, count: u16) {
+        let to_addr = socket.peer_addr;
+        for num in 0..count {
+            socket.get_next_send()[..2].copy_from_slice(&num.to_be_bytes());
+            socket.done_with_send(to_addr, 2);
+        }
+    }
+
+    // Receives until nothing more arrives within the quiet duration
+    fn recv_numbered(socket: &mut VirtualSocket, quiet_duration: Duration) -> Vec<u16> {
+        let mut received = Vec::new();
+        while socket.sleep_till_next_recv(quiet_duration) {
+            while let Some((data, _)) = socket.get_next_recv() {
+                received.push(u16::from_be_bytes([data[0], data[1]]));
+                socket.done_with_recv();
+            }
+        }
+        received
+    }
+
+    #[test]
+    fn same_seed_loses_same_datagrams() {
+        let lossy = LinkConfig {
+            loss_probability: 0.3,
+            seed: 7,
+            ..LinkConfig::default()
+        };
+
+        let mut runs = Vec::new();
+        for _ in 0..2 {
+            let (mut first, mut second) = link_pair(lossy.clone());
+            send_numbered(&mut first, 200);
+            let received = recv_numbered(&mut second, Duration::from_millis(20));
+            let stats = second.get_monitor().get_stats();
+            assert_eq!(stats.sent, 200);
+            assert_eq!(stats.lost + received.len() as u64, 200);
+            assert!(stats.lost > 20 && stats.lost < 100);
+            runs.push(received);
+        }
+        assert_eq!(runs[0], runs[1]);
+
+        let (mut first, mut second) = link_pair(LinkConfig { seed: 8, ..lossy });
+        send_numbered(&mut first, 200);
+        assert_ne!(
+            recv_numbered(&mut second, Duration::from_millis(20)),
+            runs[0]
+        );
+    }
+
+    #[test]
+    fn reordered_datagrams_arrive_late() {
+        let (mut first, mut second) = link_pair(LinkConfig {
+            reorder_probability: 0.5,
+            reorder_delay: Duration::from_millis(50),
+            seed: 3,
+            ..LinkConfig::default()
+        });
+        send_numbered(&mut first, 40);
+        let received = recv_numbered(&mut second, Duration::from_millis(100));
+
+        let reordered = second.get_monitor().get_stats().reordered as usize;
+        assert!(reordered > 0 && reordered < 40);
+        assert_ne!(received, (0..40).collect::<Vec<u16>>());
+
+        // Each group keeps its own send order
+        let mut sorted = received.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..40).collect::<Vec<u16>>());
+        assert!(received[..40 - reordered].windows(2).all(|w| w[0] < w[1]));
+        assert!(received[40 - reordered..].windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn latency_delays_delivery() {
+        let latency = Duration::from_millis(30);
+        let (mut first, mut second) = link_pair(LinkConfig {
+            latency,
+            ..LinkConfig::default()
+        });
+        let start_instant = Instant::now();
+        send_numbered(&mut first, 1);
+        assert!(second.get_next_recv().is_none());
+
+        assert!(second.sleep_till_next_recv(Duration::from_secs(1)));
+        assert!(start_instant.elapsed() >= latency);
+        assert!(second.get_next_recv().is_some());
+    }
+
+    #[test]
+    fn unknown_address_is_unroutable() {
+        let (mut first, second) = link_pair(LinkConfig::default());
+        first.done_with_send("127.0.0.1:5003".parse().unwrap(), 2);
+        let stats = second.get_monitor().get_stats();
+        assert_eq!(stats.sent, 0);
+        assert_eq!(stats.unroutable, 1);
+    }
+
+    #[test]
+    fn waker_interrupts_sleep() {
+        let (_first, mut second) = link_pair(LinkConfig::default());
+        let waker = second.create_waker().unwrap();
+        waker.wake();
+        let start_instant = Instant::now();
+        assert!(!second.sleep_till_next_recv(Duration::from_secs(5)));
+        assert!(start_instant.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -22,6 +22,7 @@
 
 // UDP Management Intended for use with QUIC
 
+use crate::endpoint::transport::{Transport, TransportWaker};
 use crate::endpoint::SocketAddr;
 
 #[cfg_attr(target_os = "windows", path = "udp/windows.rs")]
@@ -31,22 +32,79 @@ mod os;
 //use os::{AudioInput, AudioOutput, AudioOwner};
 
 use std::collections::BinaryHeap;
+use std::sync::Arc;
 use std::time::Instant;
 
-#[allow(dead_code)]
 pub(super) const MAX_UDP_LENGTH: usize = 65536;
 
 // QUIC defines a minimum UDP maximum datagram(payload) size of 1200 bytes for both IPv4 and IPv6
 //  https://datatracker.ietf.org/doc/html/rfc9000#name-datagram-size
-//pub const MIN_MAX_DATAGRAM_SIZE: usize = 1200;
+pub(super) const MIN_MAX_DATAGRAM_SIZE: usize = 1200;
 // The target maximum datagram size is based on the IPv6 standard minimum of 1280 bytes (that cannot be fragmented)
 //  which after the non-extended IPv6 and UDP headers are subtracted becomes 1232 bytes
 // Modern IPv4 networks SHOULD be able to handle this target max datagram size (need source links HERE)
 pub(super) const TARGET_MAX_DATAGRAM_SIZE: usize = 1232;
+// The largest datagram size path MTU discovery can grow to is a 9000 byte jumbo frame MTU
+//  minus the IPv4 and UDP headers (the OS transport send buffers are sized for it)
+pub(super) const MAX_DISCOVERED_DATAGRAM_SIZE: usize = 8972;
 
-// UDP Socket Manager (Using the mio crate)
+// The maximum datagram size used by the Endpoint based on the Config max_datagram_size
+#[inline]
+pub(super) fn max_datagram_size(discovery_max_opt: Option<usize>) -> usize {
+    match discovery_max_opt {
+        Some(discovery_max) => {
+            discovery_max.clamp(MIN_MAX_DATAGRAM_SIZE, MAX_DISCOVERED_DATAGRAM_SIZE)
+        }
+        None => TARGET_MAX_DATAGRAM_SIZE,
+    }
+}
+
+// The operating system UDP sockets are the default transports
+impl Transport for os::UdpSocket {
+    #[inline]
+    fn get_local_address(&self) -> Option<SocketAddr> {
+        os::UdpSocket::get_local_address(self)
+    }
+
+    #[inline]
+    fn sleep_till_next_recv(&mut self, timeout_duration: std::time::Duration) -> bool {
+        os::UdpSocket::sleep_till_next_recv(self, timeout_duration)
+    }
+
+    #[inline]
+    fn get_next_recv(&mut self) -> Option<(&mut [u8], SocketAddr)> {
+        os::UdpSocket::get_next_recv(self)
+    }
+
+    #[inline]
+    fn done_with_recv(&mut self) {
+        os::UdpSocket::done_with_recv(self)
+    }
+
+    #[inline]
+    fn get_next_send(&mut self) -> &mut [u8] {
+        os::UdpSocket::get_next_send(self)
+    }
+
+    #[inline]
+    fn done_with_send(&mut self, address: SocketAddr, data_len: usize) {
+        os::UdpSocket::done_with_send(self, address, data_len)
+    }
+
+    #[inline]
+    fn flush_sends(&mut self) {
+        os::UdpSocket::flush_sends(self)
+    }
+
+    #[inline]
+    fn create_waker(&mut self) -> Option<Arc<dyn TransportWaker>> {
+        os::UdpSocket::create_waker(self)
+    }
+}
+
+// UDP Socket Manager (Using a Transport that is an OS UDP socket by default)
 pub(super) struct Socket {
-    os_socket: os::UdpSocket,
+    transport: Box<dyn Transport>,
     delayed_sends: BinaryHeap<DelayedSendPacket>,
 }
 
@@ -57,20 +115,29 @@ pub(super) enum SocketError {
     RecvBlocked,
 }
 
-impl Socket {
-    pub(super) fn new(ipv6_mode: bool, bind_port: u16) -> Result<(Self, SocketAddr), SocketError> {
-        let os_socket = match os::UdpSocket::new(ipv6_mode, bind_port) {
-            Some(s) => s,
-            None => return Err(SocketError::CouldNotCreate),
-        };
+// Create an OS UDP socket transport bound to the first of the bind addresses that works
+pub(super) fn new_os_transport(
+    bind_addrs: &[SocketAddr],
+    batching: bool,
+    max_datagram_size: usize,
+) -> Result<Box<dyn Transport>, SocketError> {
+    for bind_addr in bind_addrs {
+        if let Some(s) = os::UdpSocket::new(*bind_addr, batching, max_datagram_size) {
+            return Ok(Box::new(s));
+        }
+    }
+    Err(SocketError::CouldNotCreate)
+}
 
-        let local_addr = match os_socket.get_local_address() {
+impl Socket {
+    pub(super) fn new(transport: Box<dyn Transport>) -> Result<(Self, SocketAddr), SocketError> {
+        let local_addr = match transport.get_local_address() {
             Some(la) => la,
             None => return Err(SocketError::BadLocalAddress),
         };
 
         let socket = Socket {
-            os_socket,
+            transport,
             delayed_sends: BinaryHeap::new(),
         };
 
@@ -82,12 +149,13 @@ impl Socket {
     #[inline]
     pub(super) fn sleep_till_recv_data(&mut self, timeout_duration: std::time::Duration) -> bool {
         // Possible timeout_duration parameter (safety) check here in future
-        self.os_socket.sleep_till_next_recv(timeout_duration)
+        self.transport.flush_sends();
+        self.transport.sleep_till_next_recv(timeout_duration)
     }
 
     #[inline]
     pub(super) fn get_next_recv_data(&mut self) -> Result<(&mut [u8], SocketAddr), SocketError> {
-        match self.os_socket.get_next_recv() {
+        match self.transport.get_next_recv() {
             Some((recv_size, addr_from)) => Ok((recv_size, addr_from)),
             None => Err(SocketError::RecvBlocked),
         }
@@ -95,12 +163,22 @@ impl Socket {
 
     #[inline]
     pub(super) fn done_with_recv_data(&mut self) {
-        self.os_socket.done_with_recv();
+        self.transport.done_with_recv();
     }
 
     #[inline]
     pub(super) fn get_next_send_data(&mut self) -> &mut [u8] {
-        self.os_socket.get_next_send()
+        self.transport.get_next_send()
+    }
+
+    #[inline]
+    pub(super) fn flush_send_data(&mut self) {
+        self.transport.flush_sends();
+    }
+
+    #[inline]
+    pub(super) fn create_waker(&mut self) -> Option<Arc<dyn TransportWaker>> {
+        self.transport.create_waker()
     }
 
     pub(super) fn done_with_send_data(
@@ -110,14 +188,11 @@ impl Socket {
         instant: Instant,
     ) -> Result<bool, SocketError> {
         if instant <= Instant::now() {
-            self.os_socket.done_with_send(to_addr, len);
+            self.transport.done_with_send(to_addr, len);
             Ok(true)
         } else {
             let delayed_send_packet = DelayedSendPacket {
-                data: self.os_socket.get_next_send()[..TARGET_MAX_DATAGRAM_SIZE]
-                    .try_into()
-                    .unwrap(),
-                data_len: len,
+                data: self.transport.get_next_send()[..len].to_vec(),
                 to_addr,
                 instant,
             };
@@ -138,25 +213,28 @@ impl Socket {
         let mut sends = 0;
         while let Some(delayed_send_packet) = self.delayed_sends.peek() {
             if delayed_send_packet.instant <= Instant::now() {
-                let next_send = self.os_socket.get_next_send();
-                next_send[..delayed_send_packet.data_len]
-                    .copy_from_slice(&delayed_send_packet.data[..delayed_send_packet.data_len]);
-                self.os_socket
-                    .done_with_send(delayed_send_packet.to_addr, delayed_send_packet.data_len);
+                let next_send = self.transport.get_next_send();
+                let data_len = delayed_send_packet.data.len();
+                next_send[..data_len].copy_from_slice(&delayed_send_packet.data);
+                self.transport
+                    .done_with_send(delayed_send_packet.to_addr, data_len);
                 sends += 1;
                 self.delayed_sends.pop();
             } else {
-                return Ok(sends);
+                break;
             }
         }
+        if sends > 0 {
+            self.transport.flush_sends();
+        }
         Ok(sends)
     }
 }
 
 // A delayed send packet contains data that is sent from the socket only AFTER an Instant is reached
+// The data is only as large as the datagram since path MTU discovery changes the datagram sizes
 struct DelayedSendPacket {
-    data: [u8; TARGET_MAX_DATAGRAM_SIZE],
-    data_len: usize,
+    data: Vec<u8>,
     to_addr: SocketAddr,
     instant: Instant,
 }
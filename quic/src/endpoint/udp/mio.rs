@@ -20,7 +20,19 @@
 //OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 //SOFTWARE.
 
+use crate::endpoint::transport::TransportWaker;
 use crate::endpoint::SocketAddr;
+use std::mem;
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::Arc;
+
+#[cfg(target_os = "linux")]
+#[path = "mmsg.rs"]
+mod mmsg;
+
+const SOCKET_TOKEN: mio::Token = mio::Token(0);
+// The waker uses an eventfd on Linux (and kqueue user events on macOS) registered with the same poll
+const WAKER_TOKEN: mio::Token = mio::Token(1);
 
 // UDP Socket Manager (Using the mio crate)
 pub(super) struct UdpSocket {
@@ -29,29 +41,22 @@ pub(super) struct UdpSocket {
     socket: mio::net::UdpSocket,
     poll: mio::Poll,
     events: mio::Events,
+    waker_opt: Option<Arc<mio::Waker>>, // Only a single waker can be registered with a poll
     read_data: [u8; super::MAX_UDP_LENGTH],
-    packet: [u8; super::TARGET_MAX_DATAGRAM_SIZE],
+    packet: Vec<u8>, // Sized for the largest datagram the Endpoint sends
+    #[cfg(target_os = "linux")]
+    batch_opt: Option<mmsg::Batch>, // Batched sending and receiving when enabled
 }
 
 impl UdpSocket {
-    pub(super) fn new(ipv6_mode: bool, bind_port: u16) -> Option<Self> {
-        let bind_addr = if ipv6_mode {
-            SocketAddr::V6(std::net::SocketAddrV6::new(
-                std::net::Ipv6Addr::UNSPECIFIED,
-                bind_port,
-                0,
-                0,
-            ))
-        } else {
-            SocketAddr::V4(std::net::SocketAddrV4::new(
-                std::net::Ipv4Addr::UNSPECIFIED,
-                bind_port,
-            ))
-        };
-
-        let mut socket = match mio::net::UdpSocket::bind(bind_addr) {
-            Ok(s) => s,
-            Err(_e) => return None,
+    pub(super) fn new(
+        bind_addr: SocketAddr,
+        batching: bool,
+        max_datagram_size: usize,
+    ) -> Option<Self> {
+        let mut socket = match bind_socket(bind_addr) {
+            Some(s) => mio::net::UdpSocket::from_std(s),
+            None => return None,
         };
 
         let poll = match mio::Poll::new() {
@@ -61,20 +66,32 @@ impl UdpSocket {
 
         match poll
             .registry()
-            .register(&mut socket, mio::Token(0), mio::Interest::READABLE)
+            .register(&mut socket, SOCKET_TOKEN, mio::Interest::READABLE)
         {
             Ok(_) => {}
             Err(_e) => return None,
         }
 
+        #[cfg(target_os = "linux")]
+        let batch_opt = if batching {
+            Some(mmsg::Batch::new(&socket))
+        } else {
+            None
+        };
+        #[cfg(not(target_os = "linux"))]
+        let _ = batching; // Only Linux has a batched path
+
         let socket_state = UdpSocket {
-            _is_ipv6: ipv6_mode,
-            _is_server: bind_port != 0,
+            _is_ipv6: bind_addr.is_ipv6(),
+            _is_server: bind_addr.port() != 0,
             socket,
             poll,
             events: mio::Events::with_capacity(1024),
+            waker_opt: None,
             read_data: [0; super::MAX_UDP_LENGTH],
-            packet: [0; super::TARGET_MAX_DATAGRAM_SIZE],
+            packet: vec![0; max_datagram_size],
+            #[cfg(target_os = "linux")]
+            batch_opt,
         };
 
         Some(socket_state)
@@ -89,20 +106,45 @@ impl UdpSocket {
 
     pub(super) fn sleep_till_next_recv(&mut self, timeout_duration: std::time::Duration) -> bool {
         match self.poll.poll(&mut self.events, Some(timeout_duration)) {
-            Ok(_) => !self.events.is_empty(),
+            Ok(_) => self
+                .events
+                .iter()
+                .any(|event| event.token() == SOCKET_TOKEN),
             Err(_) => false,
         }
     }
 
+    pub(super) fn create_waker(&mut self) -> Option<Arc<dyn TransportWaker>> {
+        if self.waker_opt.is_none() {
+            match mio::Waker::new(self.poll.registry(), WAKER_TOKEN) {
+                Ok(waker) => self.waker_opt = Some(Arc::new(waker)),
+                Err(_e) => return None,
+            }
+        }
+        self.waker_opt
+            .clone()
+            .map(|waker| waker as Arc<dyn TransportWaker>)
+    }
+
     pub(super) fn get_next_recv(&mut self) -> Option<(&mut [u8], SocketAddr)> {
-        match self.socket.recv_from(&mut self.read_data) {
-            Ok((recv_size, addr_from)) => Some((&mut self.read_data[..recv_size], addr_from)),
-            Err(e) => {
-                let kind = e.kind();
-                if kind == std::io::ErrorKind::WouldBlock {
-                    None
-                } else {
-                    panic!("UDP Socket MIO Recv From Error: {:?}", kind);
+        #[cfg(target_os = "linux")]
+        if let Some(batch) = &mut self.batch_opt {
+            return batch.next_recv(&self.socket);
+        }
+
+        loop {
+            match self.socket.recv_from(&mut self.read_data) {
+                Ok((recv_size, addr_from)) => {
+                    return Some((&mut self.read_data[..recv_size], addr_from))
+                }
+                Err(e) => {
+                    let kind = e.kind();
+                    if kind == std::io::ErrorKind::WouldBlock {
+                        return None;
+                    } else if !is_transient_error(&e) {
+                        panic!("UDP Socket MIO Recv From Error: {:?}", kind);
+                    }
+                    // Reading continues until it would block so no readiness event is missed
                 }
             }
         }
@@ -111,10 +153,23 @@ impl UdpSocket {
     pub(super) fn done_with_recv(&mut self) {}
 
     pub(super) fn get_next_send(&mut self) -> &mut [u8] {
+        #[cfg(target_os = "linux")]
+        if let Some(batch) = &mut self.batch_opt {
+            return batch.next_send_slot();
+        }
+
         &mut self.packet
     }
 
     pub(super) fn done_with_send(&mut self, address: SocketAddr, data_len: usize) {
+        #[cfg(target_os = "linux")]
+        if let Some(batch) = &mut self.batch_opt {
+            if batch.queue_send(address, data_len) {
+                batch.flush(&self.socket);
+            }
+            return;
+        }
+
         match self.socket.send_to(&self.packet[..data_len], address) {
             Ok(send_size) => {
                 if send_size == data_len {
@@ -123,6 +178,9 @@ impl UdpSocket {
                     panic!("UDP Socket MIO Send Size Wrong!");
                 }
             }
+            Err(e) if is_transient_error(&e) => {
+                // Lost like any other datagram (like a path MTU discovery probe that is too large for the path)
+            }
             Err(e) => {
                 let kind = e.kind();
                 if kind == std::io::ErrorKind::WouldBlock {
@@ -133,4 +191,103 @@ impl UdpSocket {
             }
         }
     }
+
+    pub(super) fn flush_sends(&mut self) {
+        #[cfg(target_os = "linux")]
+        if let Some(batch) = &mut self.batch_opt {
+            batch.flush(&self.socket);
+        }
+    }
+}
+
+// Errors that only affect the datagram at hand (QUIC recovers from them like any other loss)
+fn is_transient_error(e: &std::io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::ENETUNREACH)
+            | Some(libc::EHOSTUNREACH)
+            | Some(libc::EPERM)
+            | Some(libc::EMSGSIZE)
+    )
+}
+
+// A wildcard IPv6 address binds a dual-stack socket that also accepts IPv4 (as IPv4-mapped IPv6 addresses)
+// The scope ID of a link-local IPv6 address pins the socket to that interface
+fn bind_socket(bind_addr: SocketAddr) -> Option<std::net::UdpSocket> {
+    let domain = if bind_addr.is_ipv6() {
+        socket2::Domain::IPV6
+    } else {
+        socket2::Domain::IPV4
+    };
+    let socket =
+        match socket2::Socket::new(domain, socket2::Type::DGRAM, Some(socket2::Protocol::UDP)) {
+            Ok(s) => s,
+            Err(_e) => return None,
+        };
+
+    if bind_addr.is_ipv6() && bind_addr.ip().is_unspecified() && socket.set_only_v6(false).is_err()
+    {
+        return None;
+    }
+
+    if socket.set_nonblocking(true).is_err() {
+        return None;
+    }
+
+    set_dont_fragment(socket.as_raw_fd(), bind_addr.is_ipv6());
+
+    match socket.bind(&bind_addr.into()) {
+        Ok(_) => Some(socket.into()),
+        Err(_e) => None,
+    }
+}
+
+// Path MTU discovery probes need to be dropped when they are too large for the path instead of being fragmented
+// The probe mode also keeps Linux from shrinking datagrams on its own since quiche does the discovery
+// A failure here just means that probes might get fragmented
+#[cfg(target_os = "linux")]
+fn set_dont_fragment(fd: RawFd, is_ipv6: bool) {
+    // IPv4 datagrams sent from a dual-stack IPv6 socket use the IPv4 option
+    let _ip = set_socket_option(
+        fd,
+        libc::IPPROTO_IP,
+        libc::IP_MTU_DISCOVER,
+        libc::IP_PMTUDISC_PROBE,
+    );
+    if is_ipv6 {
+        let _ipv6 = set_socket_option(
+            fd,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_PROBE,
+        );
+        let _dontfrag = set_socket_option(fd, libc::IPPROTO_IPV6, libc::IPV6_DONTFRAG, 1);
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn set_dont_fragment(fd: RawFd, is_ipv6: bool) {
+    let _dontfrag = if is_ipv6 {
+        set_socket_option(fd, libc::IPPROTO_IPV6, libc::IPV6_DONTFRAG, 1)
+    } else {
+        set_socket_option(fd, libc::IPPROTO_IP, libc::IP_DONTFRAG, 1)
+    };
+}
+
+fn set_socket_option(fd: RawFd, level: libc::c_int, name: libc::c_int, value: libc::c_int) -> bool {
+    unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        ) == 0
+    }
+}
+
+impl TransportWaker for mio::Waker {
+    fn wake(&self) {
+        let _ = mio::Waker::wake(self);
+    }
 }
@@ -0,0 +1,499 @@
+//Media Enhanced Swiftlet Quic Rust Library for Real-time Internet Communications
+//MIT License
+//Copyright (c) 2024 Jared Loewenthal
+//
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+//
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+//
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+// Linux Batched UDP (sendmmsg / recvmmsg with UDP Generic Segmentation / Receive Offload)
+// Datagrams sent to the same address one after another are given to the kernel as a single GSO "super" datagram
+// The kernel (or network card) splits it back up into the individual datagrams
+
+use super::{is_transient_error, set_socket_option};
+use crate::endpoint::SocketAddr;
+use std::mem;
+use std::os::fd::{AsRawFd, RawFd};
+use std::ptr;
+
+const SEND_SLOT_SIZE: usize = super::super::MAX_DISCOVERED_DATAGRAM_SIZE; // Room given for each datagram to send
+const SEND_BUFFER_SIZE: usize = 262_144; // Maximum bytes held back before they are sent
+const SEND_BATCH_MSGS: usize = 32; // Maximum messages (GSO or not) per sendmmsg call
+const MAX_GSO_SEGMENTS: usize = 64; // Kernel UDP_MAX_SEGMENTS
+const MAX_GSO_BYTES: usize = 65_000; // A GSO message still needs to fit within a single (maximum size) UDP datagram
+
+const RECV_SLOT_SIZE: usize = super::super::MAX_UDP_LENGTH; // Coalesced GRO messages can be up to the maximum UDP size
+const RECV_BATCH_MSGS: usize = 8; // Maximum messages per recvmmsg call
+
+// Control message buffer (8 byte aligned) big enough for the UDP_SEGMENT / UDP_GRO control messages
+type ControlBuffer = [u64; 8];
+
+// A message to send which contains one or more (equally sized) datagrams to the same address
+struct SendMsg {
+    start: usize,
+    len: usize,
+    segment_size: usize,
+    addr: SocketAddr,
+    is_open: bool, // Another datagram can be added as a segment
+}
+
+// A received message which may contain multiple coalesced datagrams from the same address
+#[derive(Clone, Copy)]
+struct RecvMsg {
+    len: usize,
+    segment_size: usize,
+    addr: SocketAddr,
+}
+
+pub(super) struct Batch {
+    mmsg: bool, // Turned off at runtime if the kernel does not support sendmmsg / recvmmsg
+    gso: bool, // Turned off at runtime if the kernel or network device does not support UDP_SEGMENT
+    send_data: Vec<u8>,
+    send_pos: usize,
+    send_msgs: Vec<SendMsg>,
+    recv_data: Vec<u8>,
+    recv_msgs: Vec<RecvMsg>,
+    recv_msg_index: usize,
+    recv_offset: usize,
+}
+
+impl Batch {
+    pub(super) fn new(socket: &mio::net::UdpSocket) -> Self {
+        let fd = socket.as_raw_fd();
+
+        // The kernel supports UDP_SEGMENT if the option can be read back
+        let mut segment_size: libc::c_int = 0;
+        let mut option_len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let gso = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_UDP,
+                libc::UDP_SEGMENT,
+                &mut segment_size as *mut libc::c_int as *mut libc::c_void,
+                &mut option_len,
+            )
+        } == 0;
+
+        // A failure here just means that received datagrams will not be coalesced
+        let _gro = set_socket_option(fd, libc::SOL_UDP, libc::UDP_GRO, 1);
+
+        Batch {
+            mmsg: true,
+            gso,
+            send_data: vec![0; SEND_BUFFER_SIZE],
+            send_pos: 0,
+            send_msgs: Vec::with_capacity(SEND_BATCH_MSGS),
+            recv_data: vec![0; RECV_SLOT_SIZE * RECV_BATCH_MSGS],
+            recv_msgs: Vec::with_capacity(RECV_BATCH_MSGS),
+            recv_msg_index: 0,
+            recv_offset: 0,
+        }
+    }
+
+    #[inline]
+    pub(super) fn next_send_slot(&mut self) -> &mut [u8] {
+        &mut self.send_data[self.send_pos..self.send_pos + SEND_SLOT_SIZE]
+    }
+
+    // Adds the datagram written into the last send slot to the batch
+    // Returns true when the batch is full and needs to be flushed
+    pub(super) fn queue_send(&mut self, addr: SocketAddr, data_len: usize) -> bool {
+        let gso = self.gso;
+        let joined = match self.send_msgs.last_mut() {
+            Some(last_msg)
+                if gso
+                    && last_msg.is_open
+                    && last_msg.addr == addr
+                    && data_len <= last_msg.segment_size
+                    && last_msg.len + data_len <= MAX_GSO_BYTES
+                    && (last_msg.len / last_msg.segment_size) < MAX_GSO_SEGMENTS =>
+            {
+                last_msg.len += data_len;
+                // Only the last segment of a GSO message can be smaller than the others
+                if data_len < last_msg.segment_size {
+                    last_msg.is_open = false;
+                }
+                true
+            }
+            _ => false,
+        };
+        if !joined {
+            self.send_msgs.push(SendMsg {
+                start: self.send_pos,
+                len: data_len,
+                segment_size: data_len,
+                addr,
+                is_open: data_len > 0,
+            });
+        }
+        self.send_pos += data_len;
+
+        self.send_msgs.len() >= SEND_BATCH_MSGS
+            || self.send_pos + SEND_SLOT_SIZE > self.send_data.len()
+    }
+
+    // Hands every queued datagram to the kernel
+    pub(super) fn flush(&mut self, socket: &mio::net::UdpSocket) {
+        let mut msg_index = 0;
+        while self.mmsg && msg_index < self.send_msgs.len() {
+            match self.sendmmsg(socket.as_raw_fd(), msg_index) {
+                Ok(msgs_sent) => msg_index += msgs_sent,
+                Err(e) => match e.raw_os_error() {
+                    Some(libc::EAGAIN) => {
+                        // The socket send buffer is full so the rest are lost (QUIC will recover them)
+                        msg_index = self.send_msgs.len();
+                    }
+                    Some(libc::ENOSYS) => self.mmsg = false,
+                    Some(libc::EIO) | Some(libc::EINVAL) if self.gso => {
+                        // The network device cannot segment so the rest are sent one datagram at a time
+                        self.gso = false;
+                        self.send_each(socket, msg_index);
+                        msg_index = self.send_msgs.len();
+                    }
+                    Some(libc::EINTR) => {}
+                    _ if is_transient_error(&e) => msg_index += 1, // Only this datagram is lost
+                    _ => panic!("UDP Socket MIO Sendmmsg Error: {:?}", e.kind()),
+                },
+            }
+        }
+        if msg_index < self.send_msgs.len() {
+            self.send_each(socket, msg_index);
+        }
+        self.send_msgs.clear();
+        self.send_pos = 0;
+    }
+
+    // Runtime fallback that sends the datagrams one system call at a time
+    fn send_each(&self, socket: &mio::net::UdpSocket, first_msg_index: usize) {
+        for send_msg in self.send_msgs[first_msg_index..].iter() {
+            let msg_end = send_msg.start + send_msg.len;
+            let mut segment_start = send_msg.start;
+            while segment_start < msg_end {
+                let segment_end = msg_end.min(segment_start + send_msg.segment_size);
+                match socket.send_to(&self.send_data[segment_start..segment_end], send_msg.addr) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        let kind = e.kind();
+                        if kind == std::io::ErrorKind::WouldBlock {
+                            return; // Lost like above
+                        } else if !is_transient_error(&e) {
+                            panic!("UDP Socket MIO Send To Error: {:?}", kind);
+                        }
+                    }
+                }
+                segment_start = segment_end;
+            }
+        }
+    }
+
+    fn sendmmsg(&mut self, fd: RawFd, first_msg_index: usize) -> std::io::Result<usize> {
+        let msgs = &self.send_msgs[first_msg_index..];
+        let mut addrs: [libc::sockaddr_storage; SEND_BATCH_MSGS] = unsafe { mem::zeroed() };
+        let mut iovecs: [libc::iovec; SEND_BATCH_MSGS] = unsafe { mem::zeroed() };
+        let mut controls: [ControlBuffer; SEND_BATCH_MSGS] = [[0; 8]; SEND_BATCH_MSGS];
+        let mut hdrs: [libc::mmsghdr; SEND_BATCH_MSGS] = unsafe { mem::zeroed() };
+
+        for (msg_ind, send_msg) in msgs.iter().enumerate() {
+            let addr_len = socket_addr_to_raw(&send_msg.addr, &mut addrs[msg_ind]);
+            iovecs[msg_ind].iov_base =
+                self.send_data[send_msg.start..].as_mut_ptr() as *mut libc::c_void;
+            iovecs[msg_ind].iov_len = send_msg.len;
+
+            let hdr = &mut hdrs[msg_ind].msg_hdr;
+            hdr.msg_name = &mut addrs[msg_ind] as *mut libc::sockaddr_storage as *mut libc::c_void;
+            hdr.msg_namelen = addr_len;
+            hdr.msg_iov = &mut iovecs[msg_ind];
+            hdr.msg_iovlen = 1;
+            if send_msg.len > send_msg.segment_size {
+                write_segment_size(hdr, &mut controls[msg_ind], send_msg.segment_size as u16);
+            }
+        }
+
+        let res = unsafe { libc::sendmmsg(fd, hdrs.as_mut_ptr(), msgs.len() as libc::c_uint, 0) };
+        if res < 0 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(res as usize)
+        }
+    }
+
+    // Gets the next received datagram (splitting up coalesced GRO messages)
+    pub(super) fn next_recv(
+        &mut self,
+        socket: &mio::net::UdpSocket,
+    ) -> Option<(&mut [u8], SocketAddr)> {
+        // The socket is edge triggered so receiving continues until it would block
+        loop {
+            if self.recv_msg_index >= self.recv_msgs.len() {
+                if !self.recv_more(socket) {
+                    return None;
+                }
+                continue;
+            }
+
+            let recv_msg = self.recv_msgs[self.recv_msg_index];
+            if recv_msg.len == 0 {
+                self.recv_msg_index += 1;
+                continue;
+            }
+            let msg_start = self.recv_msg_index * RECV_SLOT_SIZE;
+            let segment_start = msg_start + self.recv_offset;
+            let segment_end = (msg_start + recv_msg.len).min(segment_start + recv_msg.segment_size);
+            if segment_end >= msg_start + recv_msg.len {
+                self.recv_msg_index += 1;
+                self.recv_offset = 0;
+            } else {
+                self.recv_offset += recv_msg.segment_size;
+            }
+            return Some((
+                &mut self.recv_data[segment_start..segment_end],
+                recv_msg.addr,
+            ));
+        }
+    }
+
+    // Returns false once there is nothing more to receive
+    fn recv_more(&mut self, socket: &mio::net::UdpSocket) -> bool {
+        self.recv_msgs.clear();
+        self.recv_msg_index = 0;
+        self.recv_offset = 0;
+
+        if !self.mmsg {
+            // Runtime fallback that receives a single datagram
+            return match socket.recv_from(&mut self.recv_data[..RECV_SLOT_SIZE]) {
+                Ok((recv_size, addr)) => {
+                    self.recv_msgs.push(RecvMsg {
+                        len: recv_size,
+                        segment_size: recv_size.max(1),
+                        addr,
+                    });
+                    true
+                }
+                Err(e) => {
+                    let kind = e.kind();
+                    if kind == std::io::ErrorKind::WouldBlock {
+                        false
+                    } else if is_transient_error(&e) {
+                        true // Tried again by the caller
+                    } else {
+                        panic!("UDP Socket MIO Recv From Error: {:?}", kind);
+                    }
+                }
+            };
+        }
+
+        let mut addrs: [libc::sockaddr_storage; RECV_BATCH_MSGS] = unsafe { mem::zeroed() };
+        let mut iovecs: [libc::iovec; RECV_BATCH_MSGS] = unsafe { mem::zeroed() };
+        let mut controls: [ControlBuffer; RECV_BATCH_MSGS] = [[0; 8]; RECV_BATCH_MSGS];
+        let mut hdrs: [libc::mmsghdr; RECV_BATCH_MSGS] = unsafe { mem::zeroed() };
+        for msg_ind in 0..RECV_BATCH_MSGS {
+            iovecs[msg_ind].iov_base =
+                self.recv_data[msg_ind * RECV_SLOT_SIZE..].as_mut_ptr() as *mut libc::c_void;
+            iovecs[msg_ind].iov_len = RECV_SLOT_SIZE;
+
+            let hdr = &mut hdrs[msg_ind].msg_hdr;
+            hdr.msg_name = &mut addrs[msg_ind] as *mut libc::sockaddr_storage as *mut libc::c_void;
+            hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+            hdr.msg_iov = &mut iovecs[msg_ind];
+            hdr.msg_iovlen = 1;
+            hdr.msg_control = controls[msg_ind].as_mut_ptr() as *mut libc::c_void;
+            hdr.msg_controllen = mem::size_of::<ControlBuffer>() as _;
+        }
+
+        let res = unsafe {
+            libc::recvmmsg(
+                socket.as_raw_fd(),
+                hdrs.as_mut_ptr(),
+                RECV_BATCH_MSGS as libc::c_uint,
+                libc::MSG_DONTWAIT,
+                ptr::null_mut(),
+            )
+        };
+        if res < 0 {
+            let e = std::io::Error::last_os_error();
+            return match e.raw_os_error() {
+                Some(libc::EAGAIN) => false,
+                Some(libc::EINTR) => true, // Tried again by the caller
+                Some(libc::ENOSYS) => {
+                    self.mmsg = false;
+                    self.recv_more(socket)
+                }
+                _ if is_transient_error(&e) => true, // Tried again by the caller
+                _ => panic!("UDP Socket MIO Recvmmsg Error: {:?}", e.kind()),
+            };
+        }
+
+        for (msg_ind, hdr) in hdrs[..res as usize].iter().enumerate() {
+            // Messages from an unknown address type are skipped over by having no length
+            let (len, addr) = match raw_to_socket_addr(&addrs[msg_ind]) {
+                Some(addr) => (hdr.msg_len as usize, addr),
+                None => (0, SocketAddr::from(([0, 0, 0, 0], 0))),
+            };
+            let segment_size = match read_gro_segment_size(&hdr.msg_hdr) {
+                Some(segment_size) if segment_size > 0 => segment_size,
+                _ => len.max(1),
+            };
+            // Each message stays lined up with its buffer slot
+            self.recv_msgs.push(RecvMsg {
+                len,
+                segment_size,
+                addr,
+            });
+        }
+        true
+    }
+}
+
+fn write_segment_size(hdr: &mut libc::msghdr, control: &mut ControlBuffer, segment_size: u16) {
+    hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    hdr.msg_controllen = unsafe { libc::CMSG_SPACE(mem::size_of::<u16>() as libc::c_uint) } as _;
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(hdr);
+        (*cmsg).cmsg_level = libc::SOL_UDP;
+        (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<u16>() as libc::c_uint) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u16, segment_size);
+    }
+}
+
+fn read_gro_segment_size(hdr: &libc::msghdr) -> Option<usize> {
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(hdr);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_UDP && (*cmsg).cmsg_type == libc::UDP_GRO {
+                let segment_size = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int);
+                return Some(segment_size as usize);
+            }
+            cmsg = libc::CMSG_NXTHDR(hdr, cmsg);
+        }
+    }
+    None
+}
+
+fn socket_addr_to_raw(addr: &SocketAddr, storage: &mut libc::sockaddr_storage) -> libc::socklen_t {
+    match addr {
+        SocketAddr::V4(addr_v4) => {
+            let raw = libc::sockaddr_in {
+                sin_family: libc::AF_INET as libc::sa_family_t,
+                sin_port: addr_v4.port().to_be(),
+                sin_addr: libc::in_addr {
+                    s_addr: u32::from_ne_bytes(addr_v4.ip().octets()),
+                },
+                sin_zero: [0; 8],
+            };
+            unsafe {
+                ptr::write(
+                    storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in,
+                    raw,
+                )
+            };
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t
+        }
+        SocketAddr::V6(addr_v6) => {
+            let raw = libc::sockaddr_in6 {
+                sin6_family: libc::AF_INET6 as libc::sa_family_t,
+                sin6_port: addr_v6.port().to_be(),
+                sin6_flowinfo: addr_v6.flowinfo(),
+                sin6_addr: libc::in6_addr {
+                    s6_addr: addr_v6.ip().octets(),
+                },
+                sin6_scope_id: addr_v6.scope_id(),
+            };
+            unsafe {
+                ptr::write(
+                    storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in6,
+                    raw,
+                )
+            };
+            mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t
+        }
+    }
+}
+
+fn raw_to_socket_addr(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+    match storage.ss_family as libc::c_int {
+        libc::AF_INET => {
+            let raw =
+                unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in) };
+            Some(SocketAddr::V4(std::net::SocketAddrV4::new(
+                std::net::Ipv4Addr::from(raw.sin_addr.s_addr.to_ne_bytes()),
+                u16::from_be(raw.sin_port),
+            )))
+        }
+        libc::AF_INET6 => {
+            let raw = unsafe {
+                &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in6)
+            };
+            Some(SocketAddr::V6(std::net::SocketAddrV6::new(
+                std::net::Ipv6Addr::from(raw.sin6_addr.s6_addr),
+                u16::from_be(raw.sin6_port),
+                raw.sin6_flowinfo,
+                raw.sin6_scope_id,
+            )))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(addr: SocketAddr) -> (Option<SocketAddr>, libc::socklen_t) {
+        let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let raw_len = socket_addr_to_raw(&addr, &mut storage);
+        (raw_to_socket_addr(&storage), raw_len)
+    }
+
+    #[test]
+    fn ipv4_address_round_trip() {
+        let addr = "192.0.2.1:4433".parse().unwrap();
+        let (converted, raw_len) = round_trip(addr);
+        assert_eq!(converted, Some(addr));
+        assert_eq!(raw_len as usize, mem::size_of::<libc::sockaddr_in>());
+    }
+
+    #[test]
+    fn ipv6_address_round_trip() {
+        let addr = SocketAddr::V6(std::net::SocketAddrV6::new(
+            "fe80::1".parse().unwrap(),
+            4433,
+            0x12345,
+            3,
+        ));
+        let (converted, raw_len) = round_trip(addr);
+        assert_eq!(converted, Some(addr));
+        assert_eq!(raw_len as usize, mem::size_of::<libc::sockaddr_in6>());
+    }
+
+    #[test]
+    fn raw_port_is_in_network_order() {
+        let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        socket_addr_to_raw(&"127.0.0.1:258".parse().unwrap(), &mut storage);
+        let raw =
+            unsafe { &*(&storage as *const libc::sockaddr_storage as *const libc::sockaddr_in) };
+        assert_eq!(raw.sin_port.to_ne_bytes(), [1, 2]);
+        assert_eq!(raw.sin_addr.s_addr.to_ne_bytes(), [127, 0, 0, 1]);
+    }
+
+    #[test]
+    fn unknown_family_is_ignored() {
+        let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        storage.ss_family = libc::AF_UNIX as libc::sa_family_t;
+        assert_eq!(raw_to_socket_addr(&storage), None);
+    }
+}
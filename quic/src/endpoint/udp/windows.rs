@@ -20,9 +20,11 @@
 //OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 //SOFTWARE.
 
+use crate::endpoint::transport::TransportWaker;
 use crate::endpoint::SocketAddr;
 use std::mem;
 use std::ptr;
+use std::sync::Arc;
 use windows::core::{PCWSTR, PSTR};
 use windows::Win32::Foundation;
 use windows::Win32::Foundation::{BOOL, HANDLE, WIN32_ERROR};
@@ -72,7 +74,7 @@ enum GetDataError {
     OverlappedResult(WinSock::WSA_ERROR),
 }
 
-const RECV_MSGS: usize = 1024; // Based on 2MiB of 2048 byte messages
+const RECV_MSGS: usize = 1024; // Each message is sized for the largest datagram the Endpoint uses
 const SEND_MSGS: usize = 1024;
 
 pub(super) struct UdpSocket {
@@ -84,13 +86,19 @@ pub(super) struct UdpSocket {
     send_msgs: Vec<AsyncMessage>,
     send_current_msg: usize,
     timer_handle: HANDLE,
+    waker: Arc<EventWaker>,
 }
 
 impl UdpSocket {
-    // if bind_port is 0 dictate that the socket should obtain a random port to bind to (useful for clients)
-    pub(super) fn new(ipv6_mode: bool, bind_port: u16) -> Option<Self> {
+    // A bind address port of 0 dictates that the socket should obtain a random port to bind to (useful for clients)
+    // Sends are already overlapped so there is no separate batched path (yet)
+    pub(super) fn new(
+        bind_addr: SocketAddr,
+        _batching: bool,
+        max_datagram_size: usize,
+    ) -> Option<Self> {
         WINSOCK_STARTUP.call_once(winsock_startup);
-        let is_ipv6 = ipv6_mode;
+        let is_ipv6 = bind_addr.is_ipv6();
         let (address_family, address_length) = match is_ipv6 {
             true => (
                 WinSock::AF_INET6,
@@ -102,6 +110,19 @@ impl UdpSocket {
             ),
         };
 
+        // The messages are created first so the socket doesn't need to be closed if one fails
+        let mut recv_msgs = Vec::with_capacity(RECV_MSGS);
+        for _ in 0..RECV_MSGS {
+            let msg = AsyncMessage::new(is_ipv6, max_datagram_size)?;
+            recv_msgs.push(msg);
+        }
+
+        let mut send_msgs = Vec::with_capacity(SEND_MSGS);
+        for _ in 0..SEND_MSGS {
+            let msg = AsyncMessage::new(is_ipv6, max_datagram_size)?;
+            send_msgs.push(msg);
+        }
+
         let socket = unsafe {
             WinSock::WSASocketW(
                 address_family.0 as i32,
@@ -116,43 +137,82 @@ impl UdpSocket {
             return None;
         }
 
-        let mut recv_msgs = Vec::with_capacity(RECV_MSGS);
-        for _ in 0..RECV_MSGS {
-            let msg = AsyncMessage::new(is_ipv6)?;
-            recv_msgs.push(msg);
-        }
-
-        let mut send_msgs = Vec::with_capacity(SEND_MSGS);
-        for _ in 0..SEND_MSGS {
-            let msg = AsyncMessage::new(is_ipv6)?;
-            send_msgs.push(msg);
-        }
-
-        let mut bind_addr = WinSock::SOCKADDR_IN6 {
+        let mut bind_sockaddr = WinSock::SOCKADDR_IN6 {
             sin6_family: address_family,
             ..Default::default()
         };
-        let is_server = if bind_port == 0 {
-            false
-        } else {
-            let port = u16::from_be_bytes(u16::to_ne_bytes(bind_port));
-            if is_ipv6 {
-                bind_addr.sin6_port = port;
-            } else {
+        match bind_addr {
+            SocketAddr::V6(addr) => {
+                bind_sockaddr.sin6_port = u16::from_be_bytes(u16::to_ne_bytes(addr.port()));
+                bind_sockaddr.sin6_addr = WinSock::IN6_ADDR {
+                    u: WinSock::IN6_ADDR_0 {
+                        Byte: addr.ip().octets(),
+                    },
+                };
+                // Pins a link-local address to its interface
+                bind_sockaddr.Anonymous = WinSock::SOCKADDR_IN6_0 {
+                    sin6_scope_id: addr.scope_id(),
+                };
+            }
+            SocketAddr::V4(addr) => {
                 let ipv4_bind_addr: &mut WinSock::SOCKADDR_IN =
-                    unsafe { mem::transmute(&mut bind_addr) };
-                ipv4_bind_addr.sin_port = port;
+                    unsafe { mem::transmute(&mut bind_sockaddr) };
+                ipv4_bind_addr.sin_port = u16::from_be_bytes(u16::to_ne_bytes(addr.port()));
+                ipv4_bind_addr.sin_addr = WinSock::IN_ADDR {
+                    S_un: WinSock::IN_ADDR_0 {
+                        S_addr: u32::from_ne_bytes(addr.ip().octets()),
+                    },
+                };
             }
-            true
+        }
+        let is_server = bind_addr.port() != 0;
+
+        // Path MTU discovery probes need to be dropped when they are too large for the path instead of being fragmented
+        // A failure here just means that probes might get fragmented
+        let dont_fragment: u32 = 1;
+        let (dont_fragment_level, dont_fragment_name) = match is_ipv6 {
+            true => (WinSock::IPPROTO_IPV6.0, WinSock::IPV6_DONTFRAG),
+            false => (WinSock::IPPROTO_IP.0, WinSock::IP_DONTFRAGMENT),
+        };
+        let _dontfrag = unsafe {
+            WinSock::setsockopt(
+                socket,
+                dont_fragment_level,
+                dont_fragment_name,
+                Some(&dont_fragment.to_ne_bytes()),
+            )
         };
+
+        // Windows IPv6 sockets are IPv6 only by default so a wildcard address turns it off to become dual-stack
+        if is_ipv6 && bind_addr.ip().is_unspecified() {
+            let v6_only: u32 = 0;
+            let wsa_error = unsafe {
+                WinSock::setsockopt(
+                    socket,
+                    WinSock::IPPROTO_IPV6.0,
+                    WinSock::IPV6_V6ONLY,
+                    Some(&v6_only.to_ne_bytes()),
+                )
+            };
+            if wsa_error == WinSock::SOCKET_ERROR {
+                unsafe {
+                    WinSock::closesocket(socket);
+                }
+                return None;
+            }
+        }
+
         let wsa_error = unsafe {
             WinSock::bind(
                 socket,
-                ptr::addr_of!(bind_addr) as *const WinSock::SOCKADDR,
+                ptr::addr_of!(bind_sockaddr) as *const WinSock::SOCKADDR,
                 address_length,
             )
         };
         if wsa_error == WinSock::SOCKET_ERROR {
+            unsafe {
+                WinSock::closesocket(socket);
+            }
             return None;
         }
 
@@ -178,9 +238,27 @@ impl UdpSocket {
             )
         } {
             Ok(h) => h,
-            Err(_e) => return None,
+            Err(_e) => {
+                unsafe {
+                    WinSock::closesocket(socket);
+                }
+                return None;
+            }
         };
 
+        // Auto-reset event so a wake stays signaled until a sleep consumes it
+        let waker =
+            match unsafe { Threading::CreateEventW(None, BOOL(0), BOOL(0), PCWSTR(ptr::null())) } {
+                Ok(h) => Arc::new(EventWaker { event_handle: h }),
+                Err(_e) => {
+                    unsafe {
+                        let _ = Foundation::CloseHandle(timer_handle);
+                        WinSock::closesocket(socket);
+                    }
+                    return None;
+                }
+            };
+
         Some(UdpSocket {
             is_ipv6,
             _is_server: is_server,
@@ -190,6 +268,7 @@ impl UdpSocket {
             send_msgs,
             send_current_msg: 0,
             timer_handle,
+            waker,
         })
     }
 
@@ -236,6 +315,7 @@ impl UdpSocket {
         } {
             Ok(_) => match self.recv_msgs[self.recv_current_msg].wait_for_msg(
                 self.timer_handle,
+                self.waker.event_handle,
                 (timeout_duration.as_millis() as u32) + 100,
             ) {
                 Ok(b) => b,
@@ -290,9 +370,37 @@ impl UdpSocket {
             Ok(false) => {
                 // Do nothing
             }
+            Err(AsyncError::NotInitiated(WinSock::WSAEMSGSIZE)) => {
+                // A path MTU discovery probe that is too large for the path is lost like any other datagram
+            }
             Err(e) => panic!("UDP Socket Windows Send Done Error: {:?}", e),
         }
     }
+
+    pub(super) fn flush_sends(&mut self) {
+        // Every send was already queued with the operating system
+    }
+
+    pub(super) fn create_waker(&mut self) -> Option<Arc<dyn TransportWaker>> {
+        Some(self.waker.clone())
+    }
+}
+
+// Wakes up the socket by signaling an event that every sleep waits on (along with the receive and timer handles)
+struct EventWaker {
+    event_handle: HANDLE,
+}
+
+impl TransportWaker for EventWaker {
+    fn wake(&self) {
+        let _ = unsafe { Threading::SetEvent(self.event_handle) };
+    }
+}
+
+impl Drop for EventWaker {
+    fn drop(&mut self) {
+        let _ = unsafe { Foundation::CloseHandle(self.event_handle) };
+    }
 }
 
 impl Drop for UdpSocket {
@@ -304,10 +412,8 @@ impl Drop for UdpSocket {
     }
 }
 
-const MSG_DATA_SIZE: usize = 2048;
-
 struct AsyncMessage {
-    data: [u8; MSG_DATA_SIZE],
+    data: Vec<u8>, // Fits the largest datagram size the Endpoint uses (heap allocated so it never moves)
     buffers: [WinSock::WSABUF; 1],
     flags: u32,
     address: WinSock::SOCKADDR_IN6,
@@ -317,7 +423,7 @@ struct AsyncMessage {
 }
 
 impl AsyncMessage {
-    fn new(is_ipv6: bool) -> Option<Self> {
+    fn new(is_ipv6: bool, data_size: usize) -> Option<Self> {
         let handle = match unsafe { WinSock::WSACreateEvent() } {
             Ok(h) => h,
             Err(_) => return None,
@@ -338,7 +444,7 @@ impl AsyncMessage {
         };
 
         let mut msg = AsyncMessage {
-            data: [0; MSG_DATA_SIZE],
+            data: vec![0; data_size],
             buffers: [WinSock::WSABUF::default(); 1],
             flags: 0,
             address,
@@ -347,8 +453,8 @@ impl AsyncMessage {
             already_waited: true,
         };
 
-        msg.buffers[0].len = MSG_DATA_SIZE as u32;
-        msg.buffers[0].buf = PSTR::from_raw(ptr::addr_of_mut!(msg.data) as *mut u8);
+        msg.buffers[0].len = data_size as u32;
+        msg.buffers[0].buf = PSTR::from_raw(msg.data.as_mut_ptr());
 
         Some(msg)
     }
@@ -357,8 +463,8 @@ impl AsyncMessage {
     fn recv_queue(&mut self, s: WinSock::SOCKET) -> Result<bool, AsyncError> {
         if self.already_waited {
             let buffers = [WinSock::WSABUF {
-                len: MSG_DATA_SIZE as u32,
-                buf: PSTR::from_raw(ptr::addr_of_mut!(self.data) as *mut u8),
+                len: self.data.len() as u32,
+                buf: PSTR::from_raw(self.data.as_mut_ptr()),
             }];
 
             //let mut bytes_recv =
@@ -390,21 +496,22 @@ impl AsyncMessage {
         }
     }
 
-    // Returns true when recv event triggered, false on timeout
+    // Returns true when recv event triggered, false on timeout or wake
     fn wait_for_msg(
         &mut self,
         timer_handle: HANDLE,
+        wake_handle: HANDLE,
         backup_timeout_in_ms: u32,
     ) -> Result<bool, WaitError> {
         if self.already_waited {
             Ok(true)
         } else {
-            let handles = [self.overlapped.hEvent, timer_handle];
+            let handles = [self.overlapped.hEvent, timer_handle, wake_handle];
             let wait_event = unsafe {
                 Threading::WaitForMultipleObjects(&handles, BOOL(0), backup_timeout_in_ms)
             };
-            if wait_event == Foundation::WAIT_EVENT(1) {
-                //Timeout
+            if wait_event == Foundation::WAIT_EVENT(1) || wait_event == Foundation::WAIT_EVENT(2) {
+                //Timeout or Wake
                 Ok(false)
             } else if wait_event == Foundation::WAIT_OBJECT_0 {
                 self.already_waited = true;
@@ -541,7 +648,7 @@ impl AsyncMessage {
 
             let buffers = [WinSock::WSABUF {
                 len: length,
-                buf: PSTR::from_raw(ptr::addr_of_mut!(self.data) as *mut u8),
+                buf: PSTR::from_raw(self.data.as_mut_ptr()),
             }];
             let mut bytes_sent = 0;
             if unsafe {
@@ -31,8 +31,14 @@
 
 /// QUIC Endpoint Module
 pub mod endpoint;
+
+/// Async (tokio) Endpoint Adapter Module
+#[cfg(feature = "tokio")]
+pub mod async_endpoint;
+use endpoint::tls::PeerIdentity;
 use endpoint::{
-    ConnectionEndReason, ConnectionId, Endpoint, Error, NextEvent, ReadInfo, RecvEvent,
+    BackgroundStreamId, ConnectionEndReason, ConnectionId, Endpoint, Error, NextEvent, ReadInfo,
+    RecvEvent, SocketAddr, StreamClass, Waker,
 };
 
 use std::time::{Duration, Instant};
@@ -44,7 +50,16 @@ use std::time::{Duration, Instant};
 /// for all processing cases.
 pub trait EndpointEventCallbacks {
     /// Called when a new connection is started and is application ready.
-    fn connection_started(&mut self, endpoint: &mut Endpoint, cid: &ConnectionId);
+    ///
+    /// The peer identity is only given when its certificate was verified by the Endpoint
+    /// (always for the server of a client connection and with a Config client_verification for the clients of a server).
+    /// It can be None for a resumed client connection since the server does not present its certificate again.
+    fn connection_started(
+        &mut self,
+        endpoint: &mut Endpoint,
+        cid: &ConnectionId,
+        peer_identity: Option<PeerIdentity>,
+    );
 
     /// Called when a connection has ended and should be cleaned up.
     ///
@@ -72,12 +87,54 @@ pub trait EndpointEventCallbacks {
         // Do nothing by default
     }
 
+    /// Called when the peer of a connection has moved to a new (validated) network path.
+    ///
+    /// This happens when a client switches networks (ie. Wi-Fi to Ethernet) or gets rebound by a NAT.
+    /// The new_addr is the peer address that the connection now sends to.
+    ///
+    /// By default, this function does nothing when called.
+    fn connection_migrated(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        _new_addr: SocketAddr,
+    ) {
+        // Do nothing by default
+    }
+
+    /// Called when the send queue of a reliable stream class (main or background) dropped below
+    /// the Endpoint Config send_queue_low_water mark after having reached it.
+    ///
+    /// This is the time to send the next chunk of a large transfer without queueing up all of it at once.
+    /// The number of bytes still queued can be checked with queued_bytes.
+    ///
+    /// By default, this function does nothing when called.
+    fn stream_writable(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        _class: StreamClass,
+    ) {
+        // Do nothing by default
+    }
+
     /// Called when the next tick occurrs based on the tick duration given to the run_event_loop call.
     ///
     /// Return true if you want the Endpoint Handler event loop to exit.
     /// The event loop will return an Ok(false) indicating that the tick callback function caused the exit.
     fn tick(&mut self, endpoint: &mut Endpoint) -> bool;
 
+    /// Called when the Endpoint was woken up by a Waker from another thread (see get_waker).
+    ///
+    /// Useful for handling application commands from other threads immediately instead of on the next tick.
+    /// Return true if you want the Endpoint Handler event loop to exit.
+    /// The event loop will return an Ok(false) just like with the tick callback function.
+    ///
+    /// By default, this function does nothing when called.
+    fn wake(&mut self, _endpoint: &mut Endpoint) -> bool {
+        false
+    }
+
     /// Called when there is something to read on the main stream.
     ///
     /// The main stream is a reliable (ordered) stream that focuses on communicating
@@ -127,6 +184,22 @@ pub trait EndpointEventCallbacks {
         0
     }
 
+    /// Called when a real-time datagram has been received.
+    ///
+    /// Real-time datagrams are sent with QUIC DATAGRAM frames which are unreliable and unordered,
+    /// so each call represents exactly one datagram sent by the peer with rt_datagram_send.
+    /// This is only called when the Endpoint Config rt_datagram_queue_len is greater than 0.
+    ///
+    /// By default, this function does nothing when called.
+    fn rt_datagram_recv(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        _read_data: &[u8],
+    ) {
+        // Do nothing by default
+    }
+
     /// Called when there is something to read on the background stream.
     ///
     /// The background stream is a reliable (ordered) stream that focuses on communicating
@@ -139,9 +212,15 @@ pub trait EndpointEventCallbacks {
     /// Return the number of bytes you want to read the next time this callback is called.
     /// If the optional usize value is set to zero (0) it will be interpreted as the as the Endpoint Config
     /// initial_background_recv_size value.
-    /// Returning a None will close the background stream but since the background stream is required,
+    /// Returning a None will close the background stream but since the default background stream is required,
     /// the connection will start the close process.
     ///
+    /// The bkgd_id tells which background stream the data arrived on. Each background stream is read
+    /// on its own, starting with the Endpoint Config background_recv_first_bytes. Returning a None for an additional
+    /// background stream (not the DEFAULT_BACKGROUND_STREAM) only stops that stream.
+    /// If the peer finishes an additional background stream in the middle of a message, the read_data
+    /// holds the partial message (shorter than asked for) and nothing more arrives on that stream.
+    ///
     /// By default, this function will return None, which translates to a connection closure as indicated above.
     /// This function should be overwritten to handle cases where a connection might send
     /// information over the background stream to prevent accidental connection closures.
@@ -150,10 +229,27 @@ pub trait EndpointEventCallbacks {
         _endpoint: &mut Endpoint,
         _cid: &ConnectionId,
         _read_data: &[u8],
+        _bkgd_id: BackgroundStreamId,
     ) -> Option<usize> {
         // Return None by default since the background stream is not managed
         None
     }
+
+    /// Called when nothing more will arrive on an additional background stream.
+    ///
+    /// Either the peer finished sending on it (after any remaining data was given to background_stream_recv)
+    /// or the peer abruptly stopped it. A stream the peer finished can still be sent on, so call
+    /// finish_background_stream once this side is done with it too. A stream the peer stopped is already gone.
+    ///
+    /// By default, this function does nothing when called.
+    fn background_stream_recv_ended(
+        &mut self,
+        _endpoint: &mut Endpoint,
+        _cid: &ConnectionId,
+        _bkgd_id: BackgroundStreamId,
+    ) {
+        // Do nothing by default
+    }
 }
 
 /// Main library structure that handles the QUIC Endpoint
@@ -174,6 +270,13 @@ impl<'a> EndpointHandler<'a> {
         }
     }
 
+    /// Get a waker that wakes up the event loop from another thread
+    ///
+    /// Each wake results in a wake callback from the event loop thread.
+    pub fn get_waker(&mut self) -> Waker {
+        self.endpoint.get_waker()
+    }
+
     /// QUIC Endpoint Handler Event Loop
     ///
     /// Allows the endpoint handler to take control of the thread!
@@ -204,6 +307,11 @@ impl<'a> EndpointHandler<'a> {
                         return Ok(false);
                     }
                 }
+                NextEvent::Woken => {
+                    if self.events.wake(self.endpoint) {
+                        return Ok(false);
+                    }
+                }
                 NextEvent::ConnectionEnded((cid, reason)) => {
                     let remaining_connections = self.endpoint.get_num_connections();
                     if self.events.connection_ended(
@@ -293,9 +401,13 @@ impl<'a> EndpointHandler<'a> {
                     }
                     // self.endpoint.connection_send(verified_index)?;
                 }
+                RecvEvent::RealtimeDatagramReceived((cid, data_vec)) => {
+                    self.events.rt_datagram_recv(self.endpoint, &cid, &data_vec);
+                }
                 RecvEvent::BackgroundStreamReceived((
                     cid,
                     verified_index,
+                    bkgd_id,
                     mut data_vec,
                     mut len,
                 )) => {
@@ -304,9 +416,11 @@ impl<'a> EndpointHandler<'a> {
                             self.endpoint,
                             &cid,
                             &data_vec[..len],
+                            bkgd_id,
                         );
                         match self.endpoint.background_stream_read(
                             verified_index,
+                            bkgd_id,
                             data_vec,
                             target_len_opt,
                         )? {
@@ -338,6 +452,10 @@ impl<'a> EndpointHandler<'a> {
                     }
                     // self.endpoint.connection_send(verified_index)?;
                 }
+                RecvEvent::BackgroundStreamRecvEnded((cid, bkgd_id)) => {
+                    self.events
+                        .background_stream_recv_ended(self.endpoint, &cid, bkgd_id);
+                }
                 RecvEvent::ConnectionEnded((cid, reason)) => {
                     let remaining_connections = self.endpoint.get_num_connections();
                     if self.events.connection_ended(
@@ -353,8 +471,16 @@ impl<'a> EndpointHandler<'a> {
                     self.events
                         .connection_ending_warning(self.endpoint, &cid, reason);
                 }
-                RecvEvent::EstablishedOnce(cid) => {
-                    self.events.connection_started(self.endpoint, &cid);
+                RecvEvent::EstablishedOnce((cid, peer_identity_opt)) => {
+                    self.events
+                        .connection_started(self.endpoint, &cid, peer_identity_opt);
+                }
+                RecvEvent::StreamWritable((cid, class)) => {
+                    self.events.stream_writable(self.endpoint, &cid, class);
+                }
+                RecvEvent::ConnectionMigrated((cid, new_addr)) => {
+                    self.events
+                        .connection_migrated(self.endpoint, &cid, new_addr);
                 }
                 RecvEvent::NoUpdate => {
                     // Do nothing and call recv again
@@ -363,3 +489,356 @@ impl<'a> EndpointHandler<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use endpoint::tls::{self, Encoding, Identity, ServerVerification};
+    use endpoint::transport::{virtual_link_pair, LinkConfig, LinkStats};
+    use endpoint::{AdmissionLimits, Config, CongestionControl, Pacing, StreamPriority};
+
+    const ALPN_NAME: &[u8] = b"pair-test";
+    const SERVER_NAME: &str = "localhost";
+
+    const MAIN_MESSAGES: u32 = 50;
+    const MAIN_MESSAGE_LEN: usize = 8;
+    const FINAL_MESSAGE_INDEX: u32 = u32::MAX; // Sent after the last real-time segment
+    const BKGD_CHUNK_LEN: usize = 4096;
+    const BKGD_LEN: usize = BKGD_CHUNK_LEN * 48;
+    const RT_SEGMENTS: u64 = 40;
+    const RT_SEGMENT_LEN: usize = 160;
+    const MAX_TICKS: u64 = 4000; // Fails the test instead of hanging it (20 seconds)
+
+    fn test_config(keep_alive_timeout: Option<Duration>) -> Config {
+        Config {
+            idle_timeout_in_ms: 5000,
+            reliable_stream_buffer: 65536,
+            unreliable_stream_buffer: 65536,
+            keep_alive_timeout,
+            initial_main_recv_size: 65536,
+            main_recv_first_bytes: MAIN_MESSAGE_LEN,
+            initial_rt_recv_size: 65536,
+            rt_recv_first_bytes: 0,
+            initial_background_recv_size: 65536,
+            background_recv_first_bytes: BKGD_CHUNK_LEN,
+            rt_datagram_queue_len: 0,
+            qlog_dir: None,
+            keylog_path: None,
+            client_identity: None,
+            client_verification: None,
+            stateless_retry: false,
+            early_data: false,
+            session_cache_path: None,
+            congestion_control: CongestionControl::Cubic,
+            initial_congestion_window: None,
+            pacing: Pacing::Off,
+            main_stream_priority: StreamPriority {
+                urgency: 1,
+                incremental: false,
+            },
+            rt_stream_priority: StreamPriority {
+                urgency: 0,
+                incremental: false,
+            },
+            background_stream_priority: StreamPriority {
+                urgency: 6,
+                incremental: true,
+            },
+            background_bandwidth_share: None,
+            rt_max_age: None,
+            send_queue_low_water: 0,
+            max_background_streams: 0,
+            udp_batching: false,
+            max_datagram_size: None,
+            admission: AdmissionLimits::default(),
+        }
+    }
+
+    fn main_message(index: u32) -> Vec<u8> {
+        let mut message = index.to_be_bytes().to_vec();
+        message.extend_from_slice(&[0xAA; MAIN_MESSAGE_LEN - 4]);
+        message
+    }
+
+    fn bkgd_byte(position: usize) -> u8 {
+        (position % 251) as u8
+    }
+
+    #[derive(Default)]
+    struct ServerState {
+        main_indexes: Vec<u32>,
+        final_received: bool,
+        bkgd_data: Vec<u8>,
+        rt_ids: Vec<u64>,
+        rt_broken: bool,
+        closed: bool,
+        ticks: u64,
+    }
+
+    impl ServerState {
+        fn close_when_done(&mut self, endpoint: &mut Endpoint, cid: &ConnectionId) {
+            if !self.closed && self.final_received && self.bkgd_data.len() == BKGD_LEN {
+                self.closed = endpoint.close_connection(cid, 0).is_ok();
+            }
+        }
+    }
+
+    impl EndpointEventCallbacks for ServerState {
+        fn connection_started(
+            &mut self,
+            _endpoint: &mut Endpoint,
+            _cid: &ConnectionId,
+            _peer_identity_opt: Option<PeerIdentity>,
+        ) {
+        }
+
+        fn connection_ended(
+            &mut self,
+            _endpoint: &mut Endpoint,
+            _cid: &ConnectionId,
+            _reason: ConnectionEndReason,
+            _remaining_connections: usize,
+        ) -> bool {
+            true
+        }
+
+        fn tick(&mut self, _endpoint: &mut Endpoint) -> bool {
+            self.ticks += 1;
+            self.ticks > MAX_TICKS
+        }
+
+        fn main_stream_recv(
+            &mut self,
+            endpoint: &mut Endpoint,
+            cid: &ConnectionId,
+            read_data: &[u8],
+        ) -> Option<usize> {
+            let index =
+                u32::from_be_bytes([read_data[0], read_data[1], read_data[2], read_data[3]]);
+            if index == FINAL_MESSAGE_INDEX {
+                self.final_received = true;
+                self.close_when_done(endpoint, cid);
+            } else {
+                self.main_indexes.push(index);
+            }
+            Some(MAIN_MESSAGE_LEN)
+        }
+
+        fn rt_stream_recv(
+            &mut self,
+            _endpoint: &mut Endpoint,
+            _cid: &ConnectionId,
+            read_data: &[u8],
+            rt_id: u64,
+        ) -> usize {
+            let intact = read_data.len() == RT_SEGMENT_LEN
+                && read_data.iter().all(|byte| *byte == rt_id as u8);
+            self.rt_broken |= !intact;
+            self.rt_ids.push(rt_id);
+            0
+        }
+
+        fn background_stream_recv(
+            &mut self,
+            endpoint: &mut Endpoint,
+            cid: &ConnectionId,
+            read_data: &[u8],
+            _bkgd_id: BackgroundStreamId,
+        ) -> Option<usize> {
+            self.bkgd_data.extend_from_slice(read_data);
+            self.close_when_done(endpoint, cid);
+            Some(BKGD_CHUNK_LEN)
+        }
+    }
+
+    #[derive(Default)]
+    struct ClientState {
+        cid_opt: Option<ConnectionId>,
+        rt_sent: u64,
+        ticks: u64,
+    }
+
+    impl EndpointEventCallbacks for ClientState {
+        fn connection_started(
+            &mut self,
+            endpoint: &mut Endpoint,
+            cid: &ConnectionId,
+            _peer_identity_opt: Option<PeerIdentity>,
+        ) {
+            self.cid_opt = Some(*cid);
+            for index in 0..MAIN_MESSAGES {
+                endpoint.main_stream_send(cid, main_message(index)).unwrap();
+            }
+            let bkgd_data = (0..BKGD_LEN).map(bkgd_byte).collect();
+            endpoint.background_stream_send(cid, bkgd_data).unwrap();
+        }
+
+        fn connection_ended(
+            &mut self,
+            _endpoint: &mut Endpoint,
+            _cid: &ConnectionId,
+            _reason: ConnectionEndReason,
+            remaining_connections: usize,
+        ) -> bool {
+            remaining_connections == 0
+        }
+
+        fn tick(&mut self, endpoint: &mut Endpoint) -> bool {
+            self.ticks += 1;
+            if let Some(cid) = &self.cid_opt {
+                // A 10ms real-time segment every other tick
+                if self.rt_sent < RT_SEGMENTS && (self.ticks % 2) == 0 {
+                    let segment = vec![self.rt_sent as u8; RT_SEGMENT_LEN];
+                    let _ = endpoint.rt_stream_send(cid, Some(segment), true);
+                    self.rt_sent += 1;
+                    if self.rt_sent == RT_SEGMENTS {
+                        let _ = endpoint.main_stream_send(cid, main_message(FINAL_MESSAGE_INDEX));
+                    }
+                }
+            }
+            self.ticks > MAX_TICKS
+        }
+
+        fn main_stream_recv(
+            &mut self,
+            _endpoint: &mut Endpoint,
+            _cid: &ConnectionId,
+            _read_data: &[u8],
+        ) -> Option<usize> {
+            Some(0)
+        }
+    }
+
+    // Runs a server and client Endpoint pair over a virtual link with the same conditions in both directions
+    // The link is driven by the seed of the config so every run sees the same losses and reordering
+    fn run_pair(link: LinkConfig) -> (ServerState, LinkStats, LinkStats) {
+        let server_address: SocketAddr = "[fd00::1]:9001".parse().unwrap();
+        let client_address: SocketAddr = "[fd00::2]:50000".parse().unwrap();
+        let (server_socket, client_socket) =
+            virtual_link_pair(server_address, client_address, link.clone(), link);
+        let server_monitor = server_socket.get_monitor();
+        let client_monitor = client_socket.get_monitor();
+
+        let server_identity = tls::generate_self_signed(SERVER_NAME).unwrap();
+        let client_verification = match &server_identity {
+            Identity::Memory { cert_data, .. } => ServerVerification::CaMemory {
+                encoding: Encoding::Pem,
+                cert_data: cert_data.clone(),
+            },
+            Identity::PemFiles { cert_path, .. } => ServerVerification::CaFile(cert_path.clone()),
+        };
+
+        let server_thread_handle = std::thread::spawn(move || {
+            let mut endpoint = Endpoint::new_server_with_transport(
+                Box::new(server_socket),
+                ALPN_NAME,
+                server_identity,
+                test_config(None),
+            )
+            .unwrap();
+            let mut server_state = ServerState::default();
+            EndpointHandler::new(&mut endpoint, &mut server_state)
+                .run_event_loop(Duration::from_millis(5))
+                .unwrap();
+            server_state
+        });
+
+        let mut endpoint = Endpoint::new_client_with_transport(
+            Box::new(client_socket),
+            ALPN_NAME,
+            client_verification,
+            test_config(Some(Duration::from_millis(1000))),
+        )
+        .unwrap();
+        endpoint
+            .add_client_connection(server_address, SERVER_NAME)
+            .unwrap();
+        let mut client_state = ClientState::default();
+        EndpointHandler::new(&mut endpoint, &mut client_state)
+            .run_event_loop(Duration::from_millis(5))
+            .unwrap();
+        assert!(client_state.ticks <= MAX_TICKS, "client timed out");
+
+        let server_state = server_thread_handle.join().unwrap();
+        assert!(server_state.ticks <= MAX_TICKS, "server timed out");
+        (
+            server_state,
+            server_monitor.get_stats(),
+            client_monitor.get_stats(),
+        )
+    }
+
+    fn assert_delivered(server_state: &ServerState) {
+        // Reliable streams arrive complete and in order no matter the link
+        assert_eq!(
+            server_state.main_indexes,
+            (0..MAIN_MESSAGES).collect::<Vec<u32>>()
+        );
+        assert!(server_state.final_received);
+        assert_eq!(server_state.bkgd_data.len(), BKGD_LEN);
+        assert!(server_state
+            .bkgd_data
+            .iter()
+            .enumerate()
+            .all(|(position, byte)| *byte == bkgd_byte(position)));
+
+        // Real-time segments can be skipped but the ones that arrive are whole and never go backwards
+        assert!(!server_state.rt_ids.is_empty());
+        assert!(server_state.rt_ids.len() as u64 <= RT_SEGMENTS);
+        assert!(server_state.rt_ids.windows(2).all(|ids| ids[0] < ids[1]));
+        assert!(!server_state.rt_broken);
+    }
+
+    #[test]
+    fn pair_delivers_over_lossy_link() {
+        let (server_state, server_bound, client_bound) = run_pair(LinkConfig {
+            loss_probability: 0.1,
+            seed: 11,
+            ..LinkConfig::default()
+        });
+        assert_delivered(&server_state);
+        assert!(server_bound.lost > 0);
+        assert!(client_bound.lost > 0);
+    }
+
+    #[test]
+    fn pair_delivers_over_reordering_link() {
+        let (server_state, server_bound, client_bound) = run_pair(LinkConfig {
+            reorder_probability: 0.2,
+            reorder_delay: Duration::from_millis(15),
+            seed: 12,
+            ..LinkConfig::default()
+        });
+        assert_delivered(&server_state);
+        assert!(server_bound.reordered > 0);
+        assert!(client_bound.reordered > 0);
+    }
+
+    #[test]
+    fn pair_delivers_over_delayed_link() {
+        let (server_state, server_bound, _) = run_pair(LinkConfig {
+            latency: Duration::from_millis(40),
+            jitter: Duration::from_millis(10),
+            bandwidth_bps: Some(10_000_000),
+            seed: 13,
+            ..LinkConfig::default()
+        });
+        assert_delivered(&server_state);
+        assert_eq!(server_bound.lost, 0);
+    }
+
+    #[test]
+    fn pair_delivers_over_combined_link() {
+        let (server_state, server_bound, _) = run_pair(LinkConfig {
+            latency: Duration::from_millis(20),
+            jitter: Duration::from_millis(5),
+            loss_probability: 0.05,
+            reorder_probability: 0.05,
+            reorder_delay: Duration::from_millis(10),
+            bandwidth_bps: None,
+            seed: 14,
+        });
+        assert_delivered(&server_state);
+        assert!(server_bound.lost > 0 || server_bound.reordered > 0);
+    }
+}
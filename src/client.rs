@@ -369,7 +369,7 @@ impl ClientTerminal {
                                 } else if uc == 'V' {
                                     if let Some(ind) = self.client.my_conn_ind {
                                         let state_change = self.client.connections[ind].state ^ 4;
-                                        let _ = self.network_channels.command_send.push(
+                                        let _ = self.network_channels.send_command(
                                             NetworkCommand::Client(ClientCommand::StateChange(
                                                 state_change,
                                             )),
@@ -378,7 +378,7 @@ impl ClientTerminal {
                                 } else if uc == 'L' {
                                     if let Some(ind) = self.client.my_conn_ind {
                                         let state_change = self.client.connections[ind].state ^ 8;
-                                        let _ = self.network_channels.command_send.push(
+                                        let _ = self.network_channels.send_command(
                                             NetworkCommand::Client(ClientCommand::StateChange(
                                                 state_change,
                                             )),
@@ -391,7 +391,7 @@ impl ClientTerminal {
                                         if let Some(opus_data) =
                                             OpusData::create_from_ogg_file(&bytes, 45)
                                         {
-                                            let _ = self.network_channels.command_send.push(
+                                            let _ = self.network_channels.send_command(
                                                 NetworkCommand::Client(
                                                     ClientCommand::MusicTransfer(opus_data),
                                                 ),
@@ -400,14 +400,13 @@ impl ClientTerminal {
                                         }
                                     }
                                 } else if uc == 'U' {
-                                    let _ = self
-                                        .network_channels
-                                        .command_send
-                                        .push(NetworkCommand::Client(ClientCommand::UploadTest(8)));
+                                    let _ = self.network_channels.send_command(
+                                        NetworkCommand::Client(ClientCommand::UploadTest(8)),
+                                    );
                                 } else if uc == 'S' {
                                     if let Some(ind) = self.client.my_conn_ind {
                                         let state_change = self.client.connections[ind].state ^ 2;
-                                        let _ = self.network_channels.command_send.push(
+                                        let _ = self.network_channels.send_command(
                                             NetworkCommand::Client(ClientCommand::StateChange(
                                                 state_change,
                                             )),
@@ -509,10 +508,7 @@ impl ClientTerminal {
             }
         }
 
-        let _ = self
-            .network_channels
-            .command_send
-            .push(NetworkCommand::Stop(42));
+        let _ = self.network_channels.send_command(NetworkCommand::Stop(42));
 
         let _ = self
             .audio_channels
@@ -24,16 +24,38 @@ use rtrb::RingBuffer;
 pub(crate) use rtrb::{Consumer, PopError, Producer, PushError};
 //RecvTimeoutError
 
+use swiftlet_quic::endpoint::Waker;
+
 pub(crate) struct NetworkTerminalThreadChannels {
     pub(crate) command_recv: Consumer<NetworkCommand>,
     pub(crate) state_send: Producer<NetworkStateMessage>,
     pub(crate) debug_send: Producer<String>, // String so that non-static debug messages can be made!
+    pub(crate) waker_send: Producer<Waker>,  // Sent once the network endpoint is created
 }
 
 pub(crate) struct TerminalNetworkThreadChannels {
-    pub(crate) command_send: Producer<NetworkCommand>,
+    command_send: Producer<NetworkCommand>,
     pub(crate) state_recv: Consumer<NetworkStateMessage>,
     pub(crate) debug_recv: Consumer<String>,
+    waker_recv: Consumer<Waker>,
+    network_waker: Option<Waker>,
+}
+
+impl TerminalNetworkThreadChannels {
+    // Sends the command and wakes up the network thread so it gets handled right away
+    pub(crate) fn send_command(
+        &mut self,
+        command: NetworkCommand,
+    ) -> Result<(), PushError<NetworkCommand>> {
+        while let Ok(waker) = self.waker_recv.pop() {
+            self.network_waker = Some(waker);
+        }
+        self.command_send.push(command)?;
+        if let Some(waker) = &self.network_waker {
+            waker.wake();
+        }
+        Ok(())
+    }
 }
 
 pub(crate) fn create_networking_channels(
@@ -41,16 +63,20 @@ pub(crate) fn create_networking_channels(
     let (command_send, command_recv) = RingBuffer::new(64);
     let (state_send, state_recv) = RingBuffer::new(64);
     let (debug_send, debug_recv) = RingBuffer::new(256);
+    let (waker_send, waker_recv) = RingBuffer::new(4);
 
     let network_channels = NetworkTerminalThreadChannels {
         command_recv,
         state_send,
         debug_send,
+        waker_send,
     };
     let console_channels = TerminalNetworkThreadChannels {
         command_send,
         state_recv,
         debug_recv,
+        waker_recv,
+        network_waker: None,
     };
 
     (network_channels, console_channels)
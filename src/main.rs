@@ -62,18 +62,30 @@ struct Arguments {
 
     /// Optional Server Address that the program will attempt to connect to while operating as a Client.
     /// If not specified then the program will operate as a Server.
-    /// Must be in `127.0.0.1:443` or `[::1]:443` format.
-    /// If the port is set to 0 then the port argument will be used instead (program default if unset).
-    #[bpaf(short, long, argument("SOCKET"))]
-    address: Option<SocketAddr>,
+    /// Can be in `127.0.0.1:443`, `[::1]:443` or `example.com:443` format.
+    /// A host name is also the name the server certificate gets verified against.
+    /// If the port is set to 0 or missing then the port argument will be used instead (program default if unset).
+    #[bpaf(short, long, argument("HOST"))]
+    address: Option<String>,
 
     /// Port to use when operating as a Server or the default value to use with the connecting address
     #[bpaf(short, long, argument("NUM"), fallback(PORT_DEFAULT))]
     port: u16,
 
-    /// Use IPv4 when the program is operating as a Server
+    /// Local address to bind to when operating as a Server (can be repeated, the first one that works is used).
+    /// Can be in `0.0.0.0`, `192.168.1.2:443`, `[::]:443` or `[fe80::1%2]:443` (link-local with an interface index) format.
+    /// Defaults to the dual-stack `[::]` (accepting both IPv6 and IPv4 clients) and then `0.0.0.0`.
+    /// If the port is set to 0 or missing then the port argument will be used instead.
+    #[bpaf(long, argument("ADDR"))]
+    bind: Vec<String>,
+
+    /// Only trust a server certificate with this SHA-256 fingerprint (hex) when operating as a Client
+    #[bpaf(long, argument("SHA256"))]
+    pin: Option<String>,
+
+    /// Trust the server certificate on first use and remember it when operating as a Client
     #[bpaf(long)]
-    ipv4: bool,
+    tofu: bool,
 
     /// Enable Rust Backtrace.
     /// Only useful when program was built in debug mode
@@ -98,13 +110,26 @@ fn main() -> std::io::Result<()> {
     // Check if the program started as a Client or a Server
     match args.address {
         // This is a client because we have an address to connect to
-        Some(mut server_address) => {
-            if server_address.port() == 0 {
-                server_address.set_port(args.port);
-            }
+        Some(address) => {
+            let (server_address, server_name) = match resolve_server_address(&address, args.port) {
+                Some(resolved) => resolved,
+                None => {
+                    println!("Could not resolve server address: {}", address);
+                    return Ok(());
+                }
+            };
 
             #[cfg(feature = "client")]
             {
+                let server_verification =
+                    match network::create_server_verification(args.pin, args.tofu) {
+                        Some(verification) => verification,
+                        None => {
+                            println!("Invalid server certificate fingerprint");
+                            return Ok(());
+                        }
+                    };
+
                 let (audio_channels, network_audio_channels, terminal_audio_channels) =
                     communication::create_audio_channels();
 
@@ -118,6 +143,8 @@ fn main() -> std::io::Result<()> {
                 let network_thread_handler = thread::spawn(move || {
                     network::client_thread(
                         server_address,
+                        server_name,
+                        server_verification,
                         args.name,
                         network_terminal_channels,
                         network_audio_channels,
@@ -142,10 +169,31 @@ fn main() -> std::io::Result<()> {
         None => {
             // No server address was provided, so the program is operating as a Server
 
+            let mut bind_addrs = Vec::with_capacity(args.bind.len());
+            for bind in &args.bind {
+                match parse_bind_address(bind, args.port) {
+                    Some(bind_addr) => bind_addrs.push(bind_addr),
+                    None => {
+                        println!("Invalid bind address: {}", bind);
+                        return Ok(());
+                    }
+                }
+            }
+            if bind_addrs.is_empty() {
+                bind_addrs.push(SocketAddr::from((
+                    std::net::Ipv6Addr::UNSPECIFIED,
+                    args.port,
+                )));
+                bind_addrs.push(SocketAddr::from((
+                    std::net::Ipv4Addr::UNSPECIFIED,
+                    args.port,
+                )));
+            }
+
             // Start Network Thread
             let server_name = args.name.clone();
             let network_thread_handler = thread::spawn(move || {
-                network::server_thread(args.ipv4, args.port, server_name, network_terminal_channels)
+                network::server_thread(bind_addrs, server_name, network_terminal_channels)
             });
 
             // Start Console
@@ -160,6 +208,65 @@ fn main() -> std::io::Result<()> {
     Ok(())
 }
 
+// Resolves the address argument into a socket address and the server name to verify the certificate against
+fn resolve_server_address(address: &str, default_port: u16) -> Option<(SocketAddr, String)> {
+    use std::net::{IpAddr, ToSocketAddrs};
+
+    // IP addresses (with or without a port) always use the default server name on the certificate
+    // since a TLS server name (SNI) cannot be an IP address literal
+    let ip_address_opt = match address.parse::<SocketAddr>() {
+        Ok(mut server_address) => {
+            if server_address.port() == 0 {
+                server_address.set_port(default_port);
+            }
+            Some(server_address)
+        }
+        Err(_) => {
+            let ip_str = address
+                .strip_prefix('[')
+                .and_then(|bracketed| bracketed.strip_suffix(']'))
+                .unwrap_or(address);
+            match ip_str.parse::<IpAddr>() {
+                Ok(ip) => Some(SocketAddr::new(ip, default_port)),
+                Err(_) => None,
+            }
+        }
+    };
+    if let Some(server_address) = ip_address_opt {
+        return Some((server_address, network::SERVER_NAME.to_string()));
+    }
+
+    // Anything else is a host name with an optional port
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port_str)) => match port_str.parse::<u16>() {
+            Ok(0) => (host, default_port),
+            Ok(port) => (host, port),
+            Err(_) => return None,
+        },
+        None => (address, default_port),
+    };
+    if host.is_empty() || host.contains(':') {
+        return None;
+    }
+
+    let server_address = (host, port).to_socket_addrs().ok()?.next()?;
+    Some((server_address, host.to_string()))
+}
+
+fn parse_bind_address(bind: &str, default_port: u16) -> Option<SocketAddr> {
+    let mut bind_addr = match bind.parse::<SocketAddr>() {
+        Ok(bind_addr) => bind_addr,
+        Err(_) => match bind.parse::<std::net::IpAddr>() {
+            Ok(ip) => SocketAddr::new(ip, default_port),
+            Err(_) => return None,
+        },
+    };
+    if bind_addr.port() == 0 {
+        bind_addr.set_port(default_port);
+    }
+    Some(bind_addr)
+}
+
 struct ConsoleStateCommon {
     title_string: String,
     debug_string: String,
@@ -350,9 +457,7 @@ fn run_console_server(
         }
     }
 
-    let _ = terminal_channels
-        .command_send
-        .push(NetworkCommand::Stop(42));
+    let _ = terminal_channels.send_command(NetworkCommand::Stop(42));
 
     // Cleanup Console Here:
     std::io::stdout().execute(crossterm::terminal::LeaveAlternateScreen)?;
@@ -24,14 +24,19 @@
 // The protocol used in this program is called "swiftlet"
 
 const ALPN_NAME: &[u8] = b"swiftlet"; // Application-Layer Protocol Negotiation Name used to define the Quic-Application Protocol used in this program
-const SERVER_NAME: &str = "localhost"; // Server "Name" / Domain Name that should ideally be on the server certificate that the client connects to
-const CERT_PATH: &str = "security/cert.pem"; // Location of the certificate for the server to use (temporarily used by client to verify server)
+pub(crate) const SERVER_NAME: &str = "localhost"; // Server "Name" / Domain Name that should ideally be on the server certificate that the client connects to
+const CERT_PATH: &str = "security/cert.pem"; // Location of the certificate for the server to use (used by client to verify server when it exists)
 const PKEY_PATH: &str = "security/pkey.pem"; // Location of the private key for the server to use
+const KNOWN_HOSTS_PATH: &str = "known_hosts"; // Location of the trust-on-first-use server fingerprints for the client to use
+const SESSION_CACHE_PATH: &str = "session_cache"; // Location of the resumable TLS sessions for the client to use
 
 // IPv6 Addresses and Sockets used when sending the client an initial connection addresss
 //#[cfg(feature = "client")]
 use std::time::{Duration, Instant};
 
+#[cfg(feature = "client")]
+use std::collections::VecDeque;
+
 // Use Inter-Thread Communication Definitions
 #[cfg(feature = "client")]
 use crate::communication::{ClientCommand, NetworkAudioOutPackets, NetworkAudioThreadChannels};
@@ -39,14 +44,26 @@ use crate::communication::{
     NetworkCommand, NetworkStateConnection, NetworkStateMessage, NetworkTerminalThreadChannels,
     PopError, PushError, ServerCommand,
 };
+#[cfg(feature = "client")]
+use swiftlet_quic::endpoint::tls::ServerVerification;
 
 // Use quic sub-library for internet communications
 use swiftlet_quic::{
-    endpoint::{Config, ConnectionEndReason, ConnectionId, Endpoint, SocketAddr},
+    endpoint::{
+        tls::{self, Identity, PeerIdentity},
+        AdmissionLimits, BackgroundStreamId, Config, CongestionControl, ConnectionEndReason,
+        ConnectionId, Endpoint, Pacing, SocketAddr, StreamClass, StreamPriority,
+        DEFAULT_BACKGROUND_STREAM,
+    },
     EndpointEventCallbacks, EndpointHandler,
 };
 
 const BUFFER_SIZE_PER_CONNECTION: usize = 4_194_304 * 3; // 4 MiB
+const TRANSFER_CHUNK_SIZE: usize = 262_144; // 256 KiB chunks for background transfers (also the send queue low-water mark)
+const MAX_BACKGROUND_STREAMS: u64 = 4; // Concurrent transfers (each one gets its own background stream)
+const MAX_DATAGRAM_SIZE: usize = 8972; // Path MTU discovery settles on less than jumbo frames when the path needs it
+const MAX_CONNECTIONS: usize = 250; // Client indices are sent as a single byte
+const MAX_RECV_BYTES_PER_SEC: u64 = 64 * 1024 * 1024; // Per client (leaves plenty of room for LAN music transfers)
 
 mod protocol;
 use protocol::{set_stream_msg_size, StreamMsgType, TransferIntention};
@@ -134,10 +151,18 @@ struct TransferInfo {
     first_recv_instant: Option<Instant>,
 }
 
+// Message being received on a background stream (with the transfer ID of transfer data)
+struct BkgdRecv {
+    bkgd_id: BackgroundStreamId,
+    msg_type: StreamMsgType,
+    transfer_id: Option<u16>,
+    len: usize, // Message length announced by the header
+}
+
 struct ClientState {
     cid: ConnectionId,
     main_recv_type: Option<StreamMsgType>,
-    bkgd_recv_type: Option<StreamMsgType>,
+    bkgd_recvs: Vec<BkgdRecv>,
     transfers: Vec<TransferInfo>,
     transfer_id_recv: Option<u16>,
     user_name: [u8; MAX_CHAR_LENGTH * 4],
@@ -151,7 +176,7 @@ impl ClientState {
         let mut cs = ClientState {
             cid,
             main_recv_type: None,
-            bkgd_recv_type: None,
+            bkgd_recvs: Vec::new(),
             transfers: Vec::new(),
             transfer_id_recv: None,
             user_name: [0; MAX_CHAR_LENGTH * 4],
@@ -193,7 +218,6 @@ struct ServerState {
     name: [u8; MAX_CHAR_LENGTH * 4],
     name_len: usize,
     terminal_channels: NetworkTerminalThreadChannels,
-    command_handler_tick: u64,
     potential_clients: Vec<ConnectionId>,
     client_states: Vec<ClientState>,
     next_transfer_id: u16,
@@ -226,7 +250,6 @@ impl ServerState {
             name,
             name_len,
             terminal_channels,
-            command_handler_tick: 0,
             potential_clients: Vec::new(),
             client_states: Vec::new(),
             next_transfer_id: 1,
@@ -499,8 +522,17 @@ impl ServerState {
 }
 
 impl EndpointEventCallbacks for ServerState {
-    fn connection_started(&mut self, endpoint: &mut Endpoint, cid: &ConnectionId) {
-        // Nothing to do until a server gets the first recv data from a potential client
+    fn connection_started(
+        &mut self,
+        endpoint: &mut Endpoint,
+        cid: &ConnectionId,
+        peer_identity_opt: Option<PeerIdentity>,
+    ) {
+        // Nothing else to do until a server gets the first recv data from a potential client
+        if let Some(peer_identity) = peer_identity_opt {
+            let identity_text = format!("Client Certificate: {}\n", peer_identity.subject);
+            self.send_debug_text(&identity_text);
+        }
     }
 
     fn connection_ended(
@@ -534,6 +566,16 @@ impl EndpointEventCallbacks for ServerState {
         let _ = self.terminal_channels.debug_send.push(ending_reason);
     }
 
+    fn connection_migrated(
+        &mut self,
+        endpoint: &mut Endpoint,
+        cid: &ConnectionId,
+        new_addr: SocketAddr,
+    ) {
+        let migrated_text = format!("Server Connection {} Migrated To: {}\n", cid, new_addr);
+        let _ = self.terminal_channels.debug_send.push(migrated_text);
+    }
+
     fn tick(&mut self, endpoint: &mut Endpoint) -> bool {
         if let Some(playback) = &mut self.music_playback {
             playback.tick += 1; // 4 ticks should be the 20ms music currently hidden requirement
@@ -583,27 +625,26 @@ impl EndpointEventCallbacks for ServerState {
             }
         }
 
-        self.command_handler_tick += 1;
-        if self.command_handler_tick >= 10 {
-            loop {
-                match self.terminal_channels.command_recv.pop() {
-                    Err(PopError::Empty) => break,
-                    Ok(NetworkCommand::Server(server_cmd)) => {
-                        self.handle_commands(endpoint, server_cmd)
-                    }
-                    Ok(NetworkCommand::Stop(int)) => {
-                        for cs in &self.client_states {
-                            let _ = endpoint.close_connection(&cs.cid, 4);
-                        }
-                        return true;
+        false
+    }
+
+    fn wake(&mut self, endpoint: &mut Endpoint) -> bool {
+        loop {
+            match self.terminal_channels.command_recv.pop() {
+                Err(PopError::Empty) => break,
+                Ok(NetworkCommand::Server(server_cmd)) => {
+                    self.handle_commands(endpoint, server_cmd)
+                }
+                Ok(NetworkCommand::Stop(int)) => {
+                    for cs in &self.client_states {
+                        let _ = endpoint.close_connection(&cs.cid, 4);
                     }
-                    #[cfg(feature = "client")]
-                    Ok(NetworkCommand::Client(_)) => {}
+                    return true;
                 }
+                #[cfg(feature = "client")]
+                Ok(NetworkCommand::Client(_)) => {}
             }
-            self.command_handler_tick = 0;
         }
-
         false
     }
 
@@ -747,10 +788,28 @@ impl EndpointEventCallbacks for ServerState {
         endpoint: &mut Endpoint,
         cid: &ConnectionId,
         read_data: &[u8],
+        bkgd_id: BackgroundStreamId,
     ) -> Option<usize> {
         if let Some(vi) = self.find_connection_index_from_cid(cid) {
-            if let Some(msg_type) = self.client_states[vi].bkgd_recv_type.take() {
-                if self.handle_stream_msg(endpoint, vi, msg_type, read_data) {
+            if let Some(recv_ind) = self.client_states[vi]
+                .bkgd_recvs
+                .iter()
+                .position(|bkgd_recv| bkgd_recv.bkgd_id == bkgd_id)
+            {
+                let bkgd_recv = self.client_states[vi].bkgd_recvs.swap_remove(recv_ind);
+                if read_data.len() < bkgd_recv.len {
+                    // The client finished the background stream partway through the message
+                    let info_string = format!(
+                        "Incomplete background message from {} ({} of {} Bytes)\n",
+                        cid,
+                        read_data.len(),
+                        bkgd_recv.len
+                    );
+                    let _ = self.terminal_channels.debug_send.push(info_string);
+                    return None;
+                }
+                self.client_states[vi].transfer_id_recv = bkgd_recv.transfer_id;
+                if self.handle_stream_msg(endpoint, vi, bkgd_recv.msg_type, read_data) {
                     Some(protocol::MESSAGE_HEADER_SIZE)
                 } else {
                     None // Close Connection
@@ -769,8 +828,12 @@ impl EndpointEventCallbacks for ServerState {
                                     Some(Instant::now());
                                 let trans_size =
                                     self.client_states[vi].transfers[transfer_ind].size;
-                                self.client_states[vi].bkgd_recv_type = Some(new_msg_type);
-                                self.client_states[vi].transfer_id_recv = Some(trans_id);
+                                self.client_states[vi].bkgd_recvs.push(BkgdRecv {
+                                    bkgd_id,
+                                    msg_type: new_msg_type,
+                                    transfer_id: Some(trans_id),
+                                    len: trans_size,
+                                });
 
                                 self.client_states[vi].state |= 0x01;
                                 self.update_client_state(endpoint, vi);
@@ -787,7 +850,12 @@ impl EndpointEventCallbacks for ServerState {
                             }
                         }
                         _ => {
-                            self.client_states[vi].bkgd_recv_type = Some(new_msg_type);
+                            self.client_states[vi].bkgd_recvs.push(BkgdRecv {
+                                bkgd_id,
+                                msg_type: new_msg_type,
+                                transfer_id: None,
+                                len: size as usize,
+                            });
                             Some(size as usize)
                         }
                     }
@@ -801,20 +869,54 @@ impl EndpointEventCallbacks for ServerState {
             None // Close Connection
         }
     }
+
+    fn background_stream_recv_ended(
+        &mut self,
+        endpoint: &mut Endpoint,
+        cid: &ConnectionId,
+        bkgd_id: BackgroundStreamId,
+    ) {
+        if let Some(vi) = self.find_connection_index_from_cid(cid) {
+            // A message the client stopped partway through is never completed
+            self.client_states[vi]
+                .bkgd_recvs
+                .retain(|bkgd_recv| bkgd_recv.bkgd_id != bkgd_id);
+        }
+        // The server never sends on the background streams that clients open
+        let _ = endpoint.finish_background_stream(cid, bkgd_id);
+    }
+}
+
+// Data of a background stream transfer
+#[cfg(feature = "client")]
+enum TransferSource {
+    Data(Vec<u8>),
+    Test, // Sent from the test data of the ClientHandler so it doesn't need to be copied
+}
+
+// Granted transfer being sent on its own background stream
+#[cfg(feature = "client")]
+struct TransferSend {
+    bkgd_id: BackgroundStreamId,
+    source: TransferSource,
+    bytes_sent: usize,
 }
 
 #[cfg(feature = "client")]
 struct ClientHandler {
     user_name: String,
+    server_name: String,
     terminal_channels: NetworkTerminalThreadChannels,
-    command_handler_tick: u64,
-    cid_option: Option<ConnectionId>, // Focus Connection ID
+    cid_option: Option<ConnectionId>,         // Focus Connection ID
+    early_announce_cid: Option<ConnectionId>, // Connection already announced through 0-RTT
     main_recv_type: Option<StreamMsgType>,
     rt_recv_type: Option<StreamMsgType>,
     rt_recv_expected_id: u64,
     avg_voice_send: u64,
     background_recv_type: Option<StreamMsgType>,
-    transfer_data: Option<Vec<u8>>,
+    transfer_requests: VecDeque<TransferSource>, // Waiting for the server to grant the transfers (in order)
+    transfer_waits: VecDeque<(TransferSource, [u8; 2])>, // Granted transfers waiting for a free background stream
+    transfer_sends: Vec<TransferSend>,
     test_data: Vec<u8>,
     test_count: u64,
     audio_channels: NetworkAudioThreadChannels,
@@ -829,6 +931,7 @@ const TEST_DATA_SIZE: usize = 2097152 * 4;
 impl ClientHandler {
     fn new(
         user_name: String,
+        server_name: String,
         terminal_channels: NetworkTerminalThreadChannels,
         audio_channels: NetworkAudioThreadChannels,
     ) -> Self {
@@ -837,15 +940,18 @@ impl ClientHandler {
 
         ClientHandler {
             user_name,
+            server_name,
             terminal_channels,
-            command_handler_tick: 0,
             cid_option: None,
+            early_announce_cid: None,
             main_recv_type: None,
             rt_recv_type: None,
             rt_recv_expected_id: 0,
             avg_voice_send: 0,
             background_recv_type: None,
-            transfer_data: None,
+            transfer_requests: VecDeque::new(),
+            transfer_waits: VecDeque::new(),
+            transfer_sends: Vec::new(),
             test_data,
             test_count: 0,
             audio_channels,
@@ -873,6 +979,23 @@ impl ClientHandler {
         let _ = self.send_debug(text.to_string());
     }
 
+    // Adds the server connection and announces right away when a resumed session allows early data
+    fn connect(
+        &mut self,
+        endpoint: &mut Endpoint,
+        server_address: SocketAddr,
+    ) -> Result<(), swiftlet_quic::endpoint::Error> {
+        let cid = endpoint.add_client_connection(server_address, &self.server_name)?;
+        if let Ok(true) = endpoint.is_in_early_data(&cid) {
+            self.send_debug_text("Announcing Self to Server in 0-RTT!\n");
+            let mut send_data = self.create_announce_data();
+            set_stream_msg_size(&mut send_data);
+            let _ = endpoint.main_stream_send(&cid, send_data);
+            self.early_announce_cid = Some(cid);
+        }
+        Ok(())
+    }
+
     fn handle_commands(&mut self, endpoint: &mut Endpoint, cmd: ClientCommand) {
         match cmd {
             ClientCommand::StateChange(new_state_requested) => {
@@ -884,33 +1007,33 @@ impl ClientHandler {
                 }
             }
             ClientCommand::ServerConnect(server_address) => {
-                let _ = endpoint.add_client_connection(server_address, SERVER_NAME);
+                let _ = self.connect(endpoint, server_address);
             }
             ClientCommand::MusicTransfer(od) => {
                 if let Some(cid) = &self.cid_option {
-                    if self.transfer_data.is_none() {
-                        let mut transfer_data = StreamMsgType::TransferData.get_send_data_vec(None);
-                        od.add_to_vec(&mut transfer_data);
-                        let size_in_bytes =
-                            (transfer_data.len() - protocol::MESSAGE_HEADER_SIZE).to_ne_bytes();
+                    // Music can be transferred alongside other transfers since each has its own background stream
+                    let mut transfer_data = StreamMsgType::TransferData.get_send_data_vec(None);
+                    od.add_to_vec(&mut transfer_data);
+                    let size_in_bytes =
+                        (transfer_data.len() - protocol::MESSAGE_HEADER_SIZE).to_ne_bytes();
 
-                        let mut send_data = StreamMsgType::TransferRequest.get_send_data_vec(None);
-                        send_data.push(size_in_bytes[0]);
-                        send_data.push(size_in_bytes[1]);
-                        send_data.push(size_in_bytes[2]);
-                        send_data.push(TransferIntention::Music as u8);
+                    let mut send_data = StreamMsgType::TransferRequest.get_send_data_vec(None);
+                    send_data.push(size_in_bytes[0]);
+                    send_data.push(size_in_bytes[1]);
+                    send_data.push(size_in_bytes[2]);
+                    send_data.push(TransferIntention::Music as u8);
 
-                        self.transfer_data = Some(transfer_data);
+                    self.transfer_requests
+                        .push_back(TransferSource::Data(transfer_data));
 
-                        set_stream_msg_size(&mut send_data);
-                        let _ = endpoint.main_stream_send(cid, send_data);
-                    }
+                    set_stream_msg_size(&mut send_data);
+                    let _ = endpoint.main_stream_send(cid, send_data);
                 }
             }
             ClientCommand::UploadTest(test_num) => {
                 if let Some(cid) = &self.cid_option {
                     self.test_count += test_num as u64;
-                    if self.transfer_data.is_none() {
+                    if !self.is_test_transferring() {
                         // let info_string = format!("Send Test Request!\n",);
                         // let _ = self.terminal_channels.debug_send.push(info_string);
 
@@ -924,8 +1047,148 @@ impl ClientHandler {
                         send_data.push(TransferIntention::Deletion as u8);
                         let _ = endpoint.main_stream_send(cid, send_data);
 
-                        self.transfer_data = Some(self.test_data.clone());
+                        self.transfer_requests.push_back(TransferSource::Test);
+                    }
+                }
+            }
+        }
+    }
+
+    // Upload tests run one after another
+    fn is_test_transferring(&self) -> bool {
+        self.transfer_requests
+            .iter()
+            .any(|source| matches!(source, TransferSource::Test))
+            || self
+                .transfer_waits
+                .iter()
+                .any(|(source, _)| matches!(source, TransferSource::Test))
+            || self
+                .transfer_sends
+                .iter()
+                .any(|transfer| matches!(transfer.source, TransferSource::Test))
+    }
+
+    // Starts sending a granted transfer once a background stream is free for it
+    fn start_transfer(
+        &mut self,
+        endpoint: &mut Endpoint,
+        cid: &ConnectionId,
+        source: TransferSource,
+        trans_id_bytes: &[u8],
+    ) {
+        self.transfer_waits
+            .push_back((source, [trans_id_bytes[0], trans_id_bytes[1]]));
+        self.send_transfer_chunks(endpoint, cid);
+    }
+
+    // Gives the waiting transfers their own background stream
+    // When the server allows no more, the default background stream carries one transfer at a time
+    // so the messages on it never get interleaved
+    fn start_waiting_transfers(&mut self, endpoint: &mut Endpoint, cid: &ConnectionId) {
+        while !self.transfer_waits.is_empty() {
+            let bkgd_id = match endpoint.open_background_stream(cid) {
+                Ok(bkgd_id) => bkgd_id,
+                Err(_) => {
+                    if self
+                        .transfer_sends
+                        .iter()
+                        .any(|transfer| transfer.bkgd_id == DEFAULT_BACKGROUND_STREAM)
+                    {
+                        break; // Started once a background stream frees up
+                    }
+                    DEFAULT_BACKGROUND_STREAM
+                }
+            };
+            if let Some((source, trans_id_bytes)) = self.transfer_waits.pop_front() {
+                let data = match &source {
+                    TransferSource::Data(data) => data,
+                    TransferSource::Test => &self.test_data,
+                };
+                // The header gets the transfer ID so the shared test data is never changed
+                let mut header = data[..protocol::MESSAGE_HEADER_SIZE].to_vec();
+                header[1] = trans_id_bytes[0];
+                header[2] = trans_id_bytes[1];
+                match endpoint.background_stream_send_on(cid, bkgd_id, header) {
+                    Ok(_) => {
+                        self.transfer_sends.push(TransferSend {
+                            bkgd_id,
+                            source,
+                            bytes_sent: protocol::MESSAGE_HEADER_SIZE,
+                        });
+                    }
+                    Err(err) => {
+                        self.abort_transfer(endpoint, cid, bkgd_id);
+                        self.send_debug_text(&format!("Transfer Start Error: {:?}\n", err));
+                    }
+                }
+            }
+        }
+    }
+
+    // A transfer that stops partway leaves a partial message behind, so the peer has to be told to drop it
+    fn abort_transfer(
+        &mut self,
+        endpoint: &mut Endpoint,
+        cid: &ConnectionId,
+        bkgd_id: BackgroundStreamId,
+    ) {
+        if bkgd_id == DEFAULT_BACKGROUND_STREAM {
+            // The messages that follow on the default background stream can't be told apart anymore
+            self.transfer_waits.clear();
+            self.transfer_sends.clear();
+            let _ = endpoint.close_connection(cid, 45);
+        } else {
+            let _ = endpoint.close_background_stream(cid, bkgd_id);
+        }
+    }
+
+    // Queues chunks of the granted transfers (taking turns) until the background send queues reach the low-water mark
+    // (the rest gets sent from the stream_writable callback)
+    fn send_transfer_chunks(&mut self, endpoint: &mut Endpoint, cid: &ConnectionId) {
+        self.start_waiting_transfers(endpoint, cid);
+        let mut transfer_ind = 0;
+        while !self.transfer_sends.is_empty() {
+            match endpoint.queued_bytes(cid, StreamClass::Background) {
+                Ok(queued_bytes) if queued_bytes < TRANSFER_CHUNK_SIZE => {}
+                Ok(_) => break,
+                Err(_) => {
+                    self.transfer_waits.clear();
+                    self.transfer_sends.clear();
+                    break;
+                }
+            }
+            transfer_ind %= self.transfer_sends.len();
+            let transfer = &mut self.transfer_sends[transfer_ind];
+            let data = match &transfer.source {
+                TransferSource::Data(data) => data,
+                TransferSource::Test => &self.test_data,
+            };
+            let chunk_end = data.len().min(transfer.bytes_sent + TRANSFER_CHUNK_SIZE);
+            let is_done = chunk_end == data.len();
+            let bkgd_id = transfer.bkgd_id;
+            let send_res = endpoint.background_stream_send_on(
+                cid,
+                bkgd_id,
+                data[transfer.bytes_sent..chunk_end].to_vec(),
+            );
+            transfer.bytes_sent = chunk_end;
+            match send_res {
+                Ok(_) if is_done => {
+                    if bkgd_id != DEFAULT_BACKGROUND_STREAM {
+                        let _ = endpoint.finish_background_stream(cid, bkgd_id);
                     }
+                    self.transfer_sends.swap_remove(transfer_ind);
+                    // The finished transfer might have freed up the default background stream
+                    self.start_waiting_transfers(endpoint, cid);
+                }
+                Ok(_) => {
+                    transfer_ind += 1;
+                }
+                Err(err) => {
+                    self.transfer_sends.swap_remove(transfer_ind);
+                    self.abort_transfer(endpoint, cid, bkgd_id);
+                    self.send_debug_text(&format!("Transfer Send Error: {:?}\n", err));
                 }
             }
         }
@@ -936,7 +1199,7 @@ impl ClientHandler {
             match self.terminal_channels.command_recv.pop() {
                 Err(PopError::Empty) => break,
                 Ok(NetworkCommand::Client(ClientCommand::ServerConnect(server_address))) => {
-                    let _ = endpoint.add_client_connection(server_address, SERVER_NAME);
+                    let _ = self.connect(endpoint, server_address);
                     return true;
                 }
                 Ok(NetworkCommand::Stop(int)) => return true,
@@ -965,16 +1228,13 @@ impl ClientHandler {
                 self.handle_client_new_state(read_data);
             }
             StreamMsgType::TransferGranted => {
-                if let Some(mut t_data) = self.transfer_data.take() {
-                    //self.send_debug_text("Got Here\n");
-                    t_data[1] = read_data[0];
-                    t_data[2] = read_data[1];
-                    let _ = endpoint.background_stream_send(cid, t_data);
+                // The server grants the transfers in the order they were requested
+                if let Some(source) = self.transfer_requests.pop_front() {
+                    self.start_transfer(endpoint, cid, source, read_data);
                 }
             }
             StreamMsgType::TransferRecv => {
-                // Not accounting for the off-chance self.transfer_data.is_some()
-                if self.test_count > 0 {
+                if self.test_count > 0 && !self.is_test_transferring() {
                     self.test_count -= 1;
 
                     let size_in_bytes = TEST_DATA_SIZE.to_ne_bytes();
@@ -985,7 +1245,7 @@ impl ClientHandler {
                     send_data.push(TransferIntention::Deletion as u8);
                     let _ = endpoint.main_stream_send(cid, send_data);
 
-                    self.transfer_data = Some(self.test_data.clone());
+                    self.transfer_requests.push_back(TransferSource::Test);
                 }
             }
             StreamMsgType::MusicIdReady => {
@@ -1084,7 +1344,15 @@ impl ClientHandler {
 
 #[cfg(feature = "client")]
 impl EndpointEventCallbacks for ClientHandler {
-    fn connection_started(&mut self, endpoint: &mut Endpoint, cid: &ConnectionId) {
+    fn connection_started(
+        &mut self,
+        endpoint: &mut Endpoint,
+        cid: &ConnectionId,
+        _peer_identity: Option<PeerIdentity>,
+    ) {
+        if self.early_announce_cid.take() == Some(*cid) {
+            return; // Already announced in early data
+        }
         let _ = self
             .terminal_channels
             .debug_send
@@ -1105,6 +1373,9 @@ impl EndpointEventCallbacks for ClientHandler {
             if *my_conn_id == *cid {
                 self.cid_option = None;
                 self.main_recv_type = None;
+                self.transfer_requests.clear();
+                self.transfer_waits.clear();
+                self.transfer_sends.clear();
                 let ended_reason = format!("Client Connection Ended Reason: {:?}\n", reason);
                 let _ = self.terminal_channels.debug_send.push(ended_reason);
             }
@@ -1130,6 +1401,12 @@ impl EndpointEventCallbacks for ClientHandler {
         }
     }
 
+    fn stream_writable(&mut self, endpoint: &mut Endpoint, cid: &ConnectionId, class: StreamClass) {
+        if class == StreamClass::Background {
+            self.send_transfer_chunks(endpoint, cid);
+        }
+    }
+
     fn tick(&mut self, endpoint: &mut Endpoint) -> bool {
         self.callback_count += 1;
         let current_instant = Instant::now();
@@ -1189,29 +1466,28 @@ impl EndpointEventCallbacks for ClientHandler {
             self.avg_voice_send = 0;
         }
 
-        self.command_handler_tick += 1;
-        if self.command_handler_tick >= 25 {
-            loop {
-                match self.terminal_channels.command_recv.pop() {
-                    Err(PopError::Empty) => break,
-                    Ok(NetworkCommand::Client(client_cmd)) => {
-                        self.handle_commands(endpoint, client_cmd);
-                    }
-                    Ok(NetworkCommand::Stop(int)) => {
-                        if let Some(cid) = &self.cid_option {
-                            let _ = endpoint.close_connection(cid, 8);
-                        }
-                        return true;
+        endpoint.clear_stats();
+
+        self.last_instant = current_instant;
+        false
+    }
+
+    fn wake(&mut self, endpoint: &mut Endpoint) -> bool {
+        loop {
+            match self.terminal_channels.command_recv.pop() {
+                Err(PopError::Empty) => break,
+                Ok(NetworkCommand::Client(client_cmd)) => {
+                    self.handle_commands(endpoint, client_cmd);
+                }
+                Ok(NetworkCommand::Stop(int)) => {
+                    if let Some(cid) = &self.cid_option {
+                        let _ = endpoint.close_connection(cid, 8);
                     }
-                    Ok(NetworkCommand::Server(_)) => {}
+                    return true;
                 }
+                Ok(NetworkCommand::Server(_)) => {}
             }
-            self.command_handler_tick = 0;
         }
-
-        endpoint.clear_stats();
-
-        self.last_instant = current_instant;
         false
     }
 
@@ -1342,6 +1618,7 @@ impl EndpointEventCallbacks for ClientHandler {
         endpoint: &mut Endpoint,
         cid: &ConnectionId,
         read_data: &[u8],
+        _bkgd_id: BackgroundStreamId,
     ) -> Option<usize> {
         if let Some(my_cid) = &mut self.cid_option {
             if *my_cid == *cid {
@@ -1371,8 +1648,7 @@ impl EndpointEventCallbacks for ClientHandler {
 }
 
 pub(crate) fn server_thread(
-    use_ipv4: bool,
-    port: u16,
+    bind_addrs: Vec<SocketAddr>,
     server_name: String,
     mut terminal_channels: NetworkTerminalThreadChannels,
 ) {
@@ -1387,10 +1663,72 @@ pub(crate) fn server_thread(
         rt_recv_first_bytes: 0,
         initial_background_recv_size: BUFFER_SIZE_PER_CONNECTION,
         background_recv_first_bytes: protocol::MESSAGE_HEADER_SIZE,
+        rt_datagram_queue_len: 0,
+        qlog_dir: None,
+        keylog_path: None,
+        client_identity: None,
+        client_verification: None,
+        stateless_retry: true,
+        early_data: true,
+        session_cache_path: None,
+        congestion_control: CongestionControl::Cubic,
+        initial_congestion_window: None,
+        pacing: Pacing::On,
+        main_stream_priority: StreamPriority {
+            urgency: 1,
+            incremental: false,
+        },
+        rt_stream_priority: StreamPriority {
+            urgency: 0,
+            incremental: false,
+        },
+        background_stream_priority: StreamPriority {
+            urgency: 6,
+            incremental: true,
+        },
+        background_bandwidth_share: None,
+        rt_max_age: Some(Duration::from_millis(100)),
+        send_queue_low_water: TRANSFER_CHUNK_SIZE,
+        max_background_streams: MAX_BACKGROUND_STREAMS,
+        udp_batching: true,
+        max_datagram_size: Some(MAX_DATAGRAM_SIZE),
+        admission: AdmissionLimits {
+            max_connections: Some(MAX_CONNECTIONS),
+            max_handshakes: Some(32),
+            max_new_connections_per_ip: Some(4),
+            max_recv_bytes_per_sec: Some(MAX_RECV_BYTES_PER_SEC),
+            max_recv_packets_per_sec: Some(MAX_RECV_BYTES_PER_SEC / 1200),
+        },
+    };
+
+    // Fall back to an ephemeral self-signed certificate when no certificate files exist
+    let identity = if std::path::Path::new(CERT_PATH).exists()
+        && std::path::Path::new(PKEY_PATH).exists()
+    {
+        Identity::PemFiles {
+            cert_path: CERT_PATH.into(),
+            pkey_path: PKEY_PATH.into(),
+        }
+    } else {
+        match tls::generate_self_signed(SERVER_NAME) {
+            Ok(generated_identity) => {
+                let _ = terminal_channels.debug_send.push(
+                    "No certificate files found, using a self-signed certificate!\n".to_string(),
+                );
+                generated_identity
+            }
+            Err(err) => {
+                let error_print = format!("Server Certificate Creation Error: {:?}\n", err);
+                let _ = terminal_channels.debug_send.push(error_print);
+                return;
+            }
+        }
     };
 
+    let fingerprint_opt = identity.get_fingerprint();
+
     let mut server_endpoint =
-        match Endpoint::new_server(!use_ipv4, port, ALPN_NAME, CERT_PATH, PKEY_PATH, config) {
+        match Endpoint::new_server_with_identity(&bind_addrs, ALPN_NAME, identity, config) {
             Ok(endpoint) => endpoint,
             Err(err) => {
                 let _ = terminal_channels
@@ -1401,8 +1739,21 @@ pub(crate) fn server_thread(
             }
         };
 
+    // Lets the terminal thread wake up the event loop right when it sends a command
+    // The first wake handles any commands that were sent before the waker was
+    let waker = server_endpoint.get_waker();
+    waker.wake();
+    let _ = terminal_channels.waker_send.push(waker);
+
     let mut server_state = ServerState::new(server_name, terminal_channels);
     server_state.send_debug_text("Starting Server Network!\n");
+    if let Some(fingerprint) = fingerprint_opt {
+        let fingerprint_text = format!(
+            "Certificate SHA-256 Fingerprint: {}\n",
+            tls::fingerprint_to_hex(&fingerprint)
+        );
+        server_state.send_debug_text(&fingerprint_text);
+    }
 
     let mut rtc_handler = EndpointHandler::new(&mut server_endpoint, &mut server_state);
     match rtc_handler.run_event_loop(std::time::Duration::from_millis(5)) {
@@ -1418,9 +1769,29 @@ pub(crate) fn server_thread(
     server_state.send_debug_text("Server Network Thread Exiting\n");
 }
 
+// Picks how the client verifies the server certificate based on the program arguments
+#[cfg(feature = "client")]
+pub(crate) fn create_server_verification(
+    pin_opt: Option<String>,
+    tofu: bool,
+) -> Option<ServerVerification> {
+    if let Some(pin) = pin_opt {
+        let fingerprint = tls::fingerprint_from_hex(&pin)?;
+        Some(ServerVerification::Fingerprints(vec![fingerprint]))
+    } else if tofu {
+        Some(ServerVerification::KnownHosts(KNOWN_HOSTS_PATH.into()))
+    } else if std::path::Path::new(CERT_PATH).exists() {
+        Some(ServerVerification::CaFile(CERT_PATH.into()))
+    } else {
+        Some(ServerVerification::SystemRoots)
+    }
+}
+
 #[cfg(feature = "client")]
 pub(crate) fn client_thread(
     server_address: SocketAddr,
+    server_name: String,
+    server_verification: ServerVerification,
     user_name: String,
     mut terminal_channels: NetworkTerminalThreadChannels,
     audio_channels: NetworkAudioThreadChannels,
@@ -1436,26 +1807,68 @@ pub(crate) fn client_thread(
         rt_recv_first_bytes: protocol::MESSAGE_HEADER_SIZE,
         initial_background_recv_size: BUFFER_SIZE_PER_CONNECTION,
         background_recv_first_bytes: protocol::MESSAGE_HEADER_SIZE,
+        rt_datagram_queue_len: 0,
+        qlog_dir: None,
+        keylog_path: None,
+        client_identity: None,
+        client_verification: None,
+        stateless_retry: false,
+        early_data: true,
+        session_cache_path: Some(SESSION_CACHE_PATH.into()),
+        congestion_control: CongestionControl::Cubic,
+        initial_congestion_window: None,
+        pacing: Pacing::On,
+        main_stream_priority: StreamPriority {
+            urgency: 1,
+            incremental: false,
+        },
+        rt_stream_priority: StreamPriority {
+            urgency: 0,
+            incremental: false,
+        },
+        background_stream_priority: StreamPriority {
+            urgency: 6,
+            incremental: true,
+        },
+        background_bandwidth_share: None,
+        rt_max_age: Some(Duration::from_millis(100)),
+        send_queue_low_water: TRANSFER_CHUNK_SIZE,
+        max_background_streams: MAX_BACKGROUND_STREAMS,
+        udp_batching: true,
+        max_datagram_size: Some(MAX_DATAGRAM_SIZE),
+        admission: AdmissionLimits::default(),
     };
-    let mut client_endpoint = match Endpoint::new_client_with_first_connection(
+    let mut client_endpoint = match Endpoint::new_client_with_verification(
         server_address.is_ipv6(),
         ALPN_NAME,
-        CERT_PATH,
-        server_address,
-        SERVER_NAME,
+        server_verification,
         config,
     ) {
         Ok(endpoint) => endpoint,
         Err(err) => {
-            let _ = terminal_channels
-                .debug_send
-                .push("Client Endpoint Creation Error!\n".to_string());
-            // Can add more detailed print here later
+            let error_print = format!("Client Endpoint Creation Error: {:?}\n", err);
+            let _ = terminal_channels.debug_send.push(error_print);
             return;
         }
     };
 
-    let mut client_handler = ClientHandler::new(user_name, terminal_channels, audio_channels);
+    // Lets the terminal thread wake up the event loop right when it sends a command
+    // The first wake handles any commands that were sent before the waker was
+    let waker = client_endpoint.get_waker();
+    waker.wake();
+    let _ = terminal_channels.waker_send.push(waker);
+
+    let mut client_handler =
+        ClientHandler::new(user_name, server_name, terminal_channels, audio_channels);
+
+    if client_handler
+        .connect(&mut client_endpoint, server_address)
+        .is_err()
+    {
+        client_handler.send_debug_text("Client Connection Creation Error!\n");
+        return;
+    }
+
     client_handler.send_debug_text("Starting Client Network!\n");
 
     loop {
@@ -1463,7 +1876,7 @@ pub(crate) fn client_thread(
         match endpoint_handler.run_event_loop(std::time::Duration::from_millis(5)) {
             Ok(true) => {
                 loop {
-                    std::thread::sleep(std::time::Duration::from_millis(50));
+                    client_endpoint.sleep_till_woken(std::time::Duration::from_millis(500));
                     if client_handler.handle_limited_commands(&mut client_endpoint) {
                         break;
                     }